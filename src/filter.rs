@@ -0,0 +1,677 @@
+//! Filters choosing which files are sorted, as in "only JPEG images bigger than
+//! 10 KB, but not the ones in thumbnails directories". A [`Filter`] can be
+//! written in the json config, or on the command line as an expression:
+//!
+//! ```text
+//! ext:jpg,jpeg and size:10KB.. and not glob:**/thumbnails/**
+//! ```
+//!
+//! The terms of an expression are:
+//!
+//! <ul>
+//! <li><code>ext:jpg,png</code>: the file's extension is one of these, ignoring case; <code>ext:</code> matches files without one</li>
+//! <li><code>glob:PATTERN</code>: the file's path inside SOURCE matches the glob, as in <code>**/thumbnails/**</code>; see <code>glob_match_path</code></li>
+//! <li><code>regex:REGEX</code>: the file's name matches the regular expression; see the <code>regex</code> crate for the syntax</li>
+//! <li><code>size:MIN..MAX</code>: the file's size is in this range, either end of which can be left out, as in <code>size:10KB..</code></li>
+//! <li><code>newer:AGE</code> and <code>older:AGE</code>: the file's date is at most, or at least, this old, as in <code>newer:30d</code></li>
+//! <li><code>since:DATE</code> and <code>until:DATE</code>: the file's date is on or after, or before, this date, as in <code>since:2021-02-03</code></li>
+//! </ul>
+//!
+//! The file's date is the one it is sorted by, and files without one never
+//! match the date terms. Terms are combined with `and`, `or`, `not` and
+//! parentheses; `and` binds tighter than `or`, and terms next to each other
+//! are joined with `and`. Values with spaces in them can be quoted, as in
+//! `size:"10 KB.."`.
+
+use chrono::{DateTime, Duration, Local};
+use crate::structs::{glob_match_path, parse_age, parse_date, parse_size, File};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[cfg(test)]
+/// Tests for the filters. Each test is named after the function it tests,
+/// prefixed with test.
+mod tests {
+
+    use chrono::{Duration, Local, TimeZone};
+    use std::path::Path;
+    use crate::structs::File;
+    use super::{Filter, Limits, PathPattern};
+
+    #[test]
+    /// Test [`Filter::parse`]
+    fn test_parse() {
+        let thumbnails = Filter::Not(Box::new(Filter::Glob(String::from("**/thumbnails/**"))));
+        assert_eq!(
+            Filter::parse("ext:jpg,.PNG and size:10KB.. and not glob:**/thumbnails/**"),
+            Ok(Filter::And(vec![
+                Filter::Extension(vec![String::from("jpg"), String::from("png")]),
+                Filter::Size { min: Some(String::from("10KB")), max: None },
+                thumbnails.clone(),
+            ]))
+        );
+        assert_eq!(
+            Filter::parse(r#"(regex:^(IMG|DSC)_ or newer:30d) size:"..1 MB""#),
+            Ok(Filter::And(vec![
+                Filter::Or(vec![Filter::Regex(String::from("^(IMG|DSC)_")), Filter::NewerThan(String::from("30d"))]),
+                Filter::Size { min: None, max: Some(String::from("1 MB")) },
+            ]))
+        );
+        assert_eq!(
+            Filter::parse("ext: or since:2021-02-03 until:2022-01-01"),
+            Ok(Filter::Or(vec![
+                Filter::Extension(vec![String::new()]),
+                Filter::And(vec![Filter::Since(String::from("2021-02-03")), Filter::Until(String::from("2022-01-01"))]),
+            ]))
+        );
+        assert!(Filter::parse("ext:jpg and").is_err());
+        assert!(Filter::parse("(ext:jpg").is_err());
+        assert!(Filter::parse("ext:jpg)").is_err());
+        assert!(Filter::parse("jpg").is_err());
+        assert!(Filter::parse("color:red").is_err());
+        assert!(Filter::parse("size:10KB").is_err());
+        assert!(Filter::parse("size:big..").is_err());
+        assert!(Filter::parse("older:30").is_err());
+        assert!(Filter::parse("since:yesterday").is_err());
+        assert!(Filter::parse("regex:(IMG").is_err());
+        assert!(Filter::parse("glob:\"My Photos").is_err());
+        assert!(Filter::parse("").is_err());
+    }
+
+    #[test]
+    /// Test [`Filter::to_string`]
+    fn test_to_string() {
+        for expression in [
+            "ext:jpg,png and size:10KB.. and not glob:**/thumbnails/**",
+            "(regex:^(IMG|DSC)_ or newer:30d) and size:\"..1 MB\"",
+            "not (ext: or older:1y)",
+        ].iter() {
+            assert_eq!(Filter::parse(expression).unwrap().to_string(), *expression);
+        }
+    }
+
+    #[test]
+    /// Test [`CompiledFilter::matches`]
+    fn test_matches() {
+        let matches = |expression: &str, path: &str| {
+            let path = File::new(path);
+            let relative = path.pathbuf.strip_prefix("testing").unwrap_or(&path.pathbuf).to_path_buf();
+            Filter::parse(expression).unwrap().compile().unwrap().matches(&path, &relative, &mut || Some(Local.ymd(2021, 2, 3).and_hms(14, 22, 33)))
+        };
+
+        // Files without an extension only match when asked for
+        assert!(matches("ext:jpg,png", "testing/test.jpg"));
+        assert!(!matches("ext:jpg,png", "testing/test"));
+        assert!(matches("not ext:txt", "testing/test"));
+        assert!(matches("ext:", "testing/files/test"));
+        assert!(!matches("ext:", "testing/test.txt"));
+
+        assert!(matches("glob:files/*", "testing/files/test"));
+        assert!(!matches("glob:files/*", "testing/test"));
+        assert!(matches("regex:(?i)^TEST\\.(jpg|png)$", "testing/test.png"));
+        assert!(matches("size:..1KB", "testing/test.jpg"));
+        assert!(!matches("size:1..", "testing/test.jpg"));
+        assert!(!matches("size:..1KB", "testing/nothing.jpg"));
+        assert!(matches("since:2021-02-03 until:2021-02-04", "testing/test"));
+        assert!(!matches("until:2021-02-03", "testing/test"));
+        assert!(matches("older:1d", "testing/test"));
+        assert!(!matches("newer:1d", "testing/test"));
+        assert!(matches("ext:jpg or (ext:txt and not glob:files/**)", "testing/test.txt"));
+
+        // Undated files never match the date terms
+        let undated = |filter: Filter| filter.compile().unwrap().matches(&File::new("testing/test"), Path::new("test"), &mut || None);
+        assert!(!undated(Filter::OlderThan(String::from("1d"))));
+        assert!(undated(Filter::Not(Box::new(Filter::OlderThan(String::from("1d"))))));
+
+        // The date is only looked up when it is needed
+        let mut lookups = 0;
+        let mut date = || {
+            lookups += 1;
+            Some(Local::now() - Duration::days(2))
+        };
+        assert!(!Filter::parse("ext:jpg and newer:1d").unwrap().compile().unwrap().matches(&File::new("testing/test.txt"), Path::new("test.txt"), &mut date));
+        assert_eq!(lookups, 0);
+    }
+
+    #[test]
+    /// Test [`CompiledFilter::reason`]
+    fn test_reason() {
+        let reason = |expression: &str, path: &str| {
+            let path = File::new(path);
+            Filter::parse(expression).unwrap().compile().unwrap().reason(&path, &path.pathbuf, &mut || Some(Local.ymd(2021, 2, 3).and_hms(14, 22, 33)))
+        };
+
+        assert_eq!(reason("ext:jpg", "testing/test.jpg"), None);
+        assert_eq!(reason("ext:png,jpg", "testing/test.txt"), Some(String::from("doesn't match ext:png,jpg")));
+        assert_eq!(reason("size:1..", "testing/test.txt"), Some(String::from("smaller than 1")));
+        assert_eq!(reason("size:..1KB", "testing/nothing.txt"), Some(String::from("no size")));
+        assert_eq!(reason("newer:1w", "testing/test.txt"), Some(String::from("older than 1w")));
+        assert_eq!(reason("since:2022-01-01", "testing/test.txt"), Some(String::from("before 2022-01-01")));
+        assert_eq!(reason("until:2021-01-01", "testing/test.txt"), Some(String::from("not before 2021-01-01")));
+        assert_eq!(reason("ext:txt size:1..", "testing/test.txt"), Some(String::from("smaller than 1")));
+        assert_eq!(reason("ext:jpg or newer:1d", "testing/test.txt"), Some(String::from("doesn't match ext:jpg and older than 1d")));
+        assert_eq!(reason("not ext:txt", "testing/test.txt"), Some(String::from("matches ext:txt")));
+        assert_eq!(
+            Filter::NewerThan(String::from("1d")).compile().unwrap().reason(&File::new("testing/test"), Path::new("test"), &mut || None),
+            Some(String::from("no date"))
+        );
+    }
+
+    #[test]
+    /// Test [`Limits::filters`]
+    fn test_limits() {
+        let limits = Limits {
+            max_size: Some(String::from("1 MB")),
+            older_than: Some(String::from("1w")),
+            until: Some(String::from("2021-02-03")),
+            ..Limits::default()
+        };
+        assert_eq!(limits.filters(), vec![
+            Filter::Size { min: None, max: Some(String::from("1 MB")) },
+            Filter::OlderThan(String::from("1w")),
+            Filter::Until(String::from("2021-02-03")),
+        ]);
+        assert!(Limits::default().filters().is_empty());
+    }
+
+    #[test]
+    /// Test the [`PathPattern`] enum
+    fn test_pathpattern() {
+        let matches = |pattern: &str, path: &str| PathPattern::parse(pattern).unwrap().matches(Path::new(path));
+
+        assert!(matches("node_modules", "web/node_modules"));
+        assert!(matches("glob:node_modules", "node_modules"));
+        assert!(matches("**/.thumbnails", "photos/2021/.thumbnails"));
+        assert!(!matches("photos/*", "photos/2021/a.jpg"));
+        assert!(matches("regex:(^|/)@eaDir$", "photos/@eaDir"));
+        assert!(!matches("regex:(^|/)@eaDir$", "photos/@eaDir.jpg"));
+        assert!(matches("regex:^photos/\\d{4}$", "photos/2021"));
+        assert!(PathPattern::parse("regex:(").is_err());
+        assert!(PathPattern::parse("").is_err());
+    }
+
+    #[test]
+    /// Test [`Filter::from_types`]
+    fn test_from_types() {
+        assert_eq!(Filter::from_types("jpg-png"), Some(Filter::Extension(vec![String::from("jpg"), String::from("png")])));
+        assert_eq!(Filter::from_types("jpg-"), Some(Filter::Extension(vec![String::from("jpg")])));
+        assert_eq!(Filter::from_types(""), None);
+    }
+
+    #[test]
+    /// Test [`Filter::validate`], and the json form of filters
+    fn test_validate() {
+        let filter: Filter = serde_json::from_str(r#"{"and": [
+            {"extension": ["jpg"]},
+            {"not": {"glob": "**/thumbnails/**"}},
+            {"size": {"min": "10 KB"}},
+            {"or": [{"newer_than": "30d"}, {"regex": "^IMG_"}]}
+        ]}"#).expect("Failed to parse filter.");
+        assert!(filter.validate().is_ok());
+        assert_eq!(filter, Filter::parse("ext:jpg not glob:**/thumbnails/** size:\"10 KB..\" (newer:30d or regex:^IMG_)").unwrap());
+        assert!(Filter::Size { min: Some(String::from("big")), max: None }.validate().is_err());
+        assert!(Filter::Not(Box::new(Filter::Since(String::from("2021")))).validate().is_err());
+        assert!(Filter::Or(vec![Filter::Regex(String::from("a)"))]).validate().is_err());
+    }
+}
+
+/// A condition on the files to sort. See the module documentation for what
+/// each of them does. In the json config, a filter is an object with the name
+/// of the filter as its only key, as in `{"size": {"min": "10 KB"}}` or
+/// `{"not": {"glob": "**/thumbnails/**"}}`.
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    Extension(Vec<String>),
+    Glob(String),
+    Regex(String),
+    Size { min: Option<String>, max: Option<String> },
+    NewerThan(String),
+    OlderThan(String),
+    Since(String),
+    Until(String),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+impl Filter {
+
+    /// Return the [`Filter`] represented by the expression `expression`, or a
+    /// [`String`] describing why it isn't valid. See the module documentation
+    /// for the syntax.
+    pub fn parse(expression: &str) -> Result<Filter, String> {
+        let mut parser = ExpressionParser { tokens: tokenize(expression)?, position: 0 };
+        let filter = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            Some(Token::Close) => Err(String::from("unopened \")\"")),
+            Some(Token::Word(word)) => Err(format!("unexpected \"{}\"", word)),
+            Some(Token::Open) | None => filter.validate().map(|_| filter),
+        }
+    }
+
+    /// Return an [`Filter::Extension`] filter for the extensions in `types`,
+    /// separated by `-`, as in `"jpg-png"`, or [`None`] if there aren't any.
+    pub fn from_types(types: &str) -> Option<Filter> {
+        let extensions: Vec<String> = types.split('-')
+            .filter(|extension| !extension.is_empty())
+            .map(String::from)
+            .collect();
+        match extensions.is_empty() {
+            true => None,
+            false => Some(Filter::Extension(extensions)),
+        }
+    }
+
+    /// Return a [`String`] describing what is wrong with the filter, if
+    /// anything: sizes, ages, dates and regular expressions that aren't valid.
+    pub fn validate(&self) -> Result<(), String> {
+        self.compile().map(|_| ())
+    }
+
+    /// Return the [`CompiledFilter`] matching the files this filter describes,
+    /// with its regular expressions compiled and its sizes, ages and dates
+    /// parsed, or a [`String`] describing why it isn't valid.
+    pub fn compile(&self) -> Result<CompiledFilter, String> {
+        let size = |size: &Option<String>| match size.as_deref() {
+            Some(size) => match parse_size(size) {
+                Some(bytes) => Ok(Some((bytes, String::from(size)))),
+                None => Err(format!("invalid size \"{}\"", size)),
+            },
+            None => Ok(None),
+        };
+        let age = |age: &str| parse_age(age).ok_or_else(|| format!("invalid age \"{}\"", age));
+        let date = |date: &str| parse_date(date).ok_or_else(|| format!("invalid date \"{}\"", date));
+        let condition = match self {
+            Filter::Extension(extensions) => Condition::Extension(extensions.iter()
+                .map(|extension| extension.trim_start_matches('.').to_lowercase())
+                .collect()),
+            Filter::Glob(pattern) => Condition::Glob(pattern.clone()),
+            Filter::Regex(pattern) => match Regex::new(pattern) {
+                Ok(regex) => Condition::Regex(regex),
+                Err(reason) => return Err(format!("invalid regex \"{}\": {}", pattern, reason)),
+            },
+            Filter::Size { min, max } => Condition::Size { min: size(min)?, max: size(max)? },
+            Filter::NewerThan(limit) => Condition::NewerThan(age(limit)?, limit.clone()),
+            Filter::OlderThan(limit) => Condition::OlderThan(age(limit)?, limit.clone()),
+            Filter::Since(limit) => Condition::Since(date(limit)?, limit.clone()),
+            Filter::Until(limit) => Condition::Until(date(limit)?, limit.clone()),
+            Filter::And(filters) => Condition::And(filters.iter().map(Filter::compile).collect::<Result<_, _>>()?),
+            Filter::Or(filters) => Condition::Or(filters.iter().map(Filter::compile).collect::<Result<_, _>>()?),
+            Filter::Not(filter) => Condition::Not(Box::new(filter.compile()?)),
+        };
+        Ok(CompiledFilter { expression: self.to_string(), condition })
+    }
+
+    /// Return the filter as an expression, as given to [`Filter::parse`].
+    pub fn to_string(&self) -> String {
+        let term = |name: &str, value: &str| format!("{}:{}", name, quote(value));
+        let nested = |filter: &Filter, parenthesize: bool| match parenthesize {
+            true => format!("({})", filter.to_string()),
+            false => filter.to_string(),
+        };
+        match self {
+            Filter::Extension(extensions) => term("ext", &extensions.join(",")),
+            Filter::Glob(pattern) => term("glob", pattern),
+            Filter::Regex(pattern) => term("regex", pattern),
+            Filter::Size { min, max } => term("size", &format!("{}..{}", min.as_deref().unwrap_or(""), max.as_deref().unwrap_or(""))),
+            Filter::NewerThan(age) => term("newer", age),
+            Filter::OlderThan(age) => term("older", age),
+            Filter::Since(date) => term("since", date),
+            Filter::Until(date) => term("until", date),
+            Filter::And(filters) => filters.iter()
+                .map(|filter| nested(filter, matches!(filter, Filter::Or(_))))
+                .collect::<Vec<String>>()
+                .join(" and "),
+            Filter::Or(filters) => filters.iter()
+                .map(|filter| nested(filter, false))
+                .collect::<Vec<String>>()
+                .join(" or "),
+            Filter::Not(filter) => format!("not {}", nested(filter, matches!(**filter, Filter::And(_) | Filter::Or(_)))),
+        }
+    }
+}
+
+/// A [`Filter`] ready to match files, as returned by [`Filter::compile`], so
+/// that its regular expressions, sizes, ages and dates are only parsed once
+/// for all the files.
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct CompiledFilter {
+    expression: String,
+    condition: Condition,
+}
+impl CompiledFilter {
+
+    /// Return [`true`] if the file `path` matches the filter. `relative` is
+    /// its path inside the source, for globs, and `date` returns the date it
+    /// is sorted by, for the date filters; it is only called when one of them
+    /// is reached.
+    pub fn matches(&self, path: &File, relative: &Path, date: &mut dyn FnMut() -> Option<DateTime<Local>>) -> bool {
+        self.reason(path, relative, date).is_none()
+    }
+
+    /// Return why the file `path` doesn't match the filter, as in `"smaller
+    /// than 10 KB"`, or [`None`] if it does. The arguments are as in
+    /// [`CompiledFilter::matches`].
+    pub fn reason(&self, path: &File, relative: &Path, date: &mut dyn FnMut() -> Option<DateTime<Local>>) -> Option<String> {
+        let unmatched = || Some(format!("doesn't match {}", self.expression));
+        match &self.condition {
+            Condition::Extension(extensions) => {
+                let extension = path.extension().to_lowercase();
+                match extensions.contains(&extension) {
+                    true => None,
+                    false => unmatched(),
+                }
+            },
+            Condition::Glob(pattern) => match glob_match_path(pattern, relative) {
+                true => None,
+                false => unmatched(),
+            },
+            Condition::Regex(regex) => match regex.is_match(&path.file_name()) {
+                true => None,
+                false => unmatched(),
+            },
+            Condition::Size { min, max } => {
+                let size = match path.pathbuf.metadata() {
+                    Ok(metadata) => metadata.len(),
+                    Err(_) => return Some(String::from("no size")),
+                };
+                match (min, max) {
+                    (Some((min_size, min)), _) if size < *min_size => Some(format!("smaller than {}", min)),
+                    (_, Some((max_size, max))) if size > *max_size => Some(format!("bigger than {}", max)),
+                    _ => None,
+                }
+            },
+            Condition::NewerThan(limit_age, limit) | Condition::OlderThan(limit_age, limit) => {
+                let age = match date() {
+                    Some(date) => Local::now().signed_duration_since(date),
+                    None => return Some(String::from("no date")),
+                };
+                match &self.condition {
+                    Condition::NewerThan(..) if age > *limit_age => Some(format!("older than {}", limit)),
+                    Condition::OlderThan(..) if age < *limit_age => Some(format!("newer than {}", limit)),
+                    _ => None,
+                }
+            },
+            Condition::Since(limit_date, limit) | Condition::Until(limit_date, limit) => {
+                let date = match date() {
+                    Some(date) => date,
+                    None => return Some(String::from("no date")),
+                };
+                match &self.condition {
+                    Condition::Since(..) if date < *limit_date => Some(format!("before {}", limit)),
+                    Condition::Until(..) if date >= *limit_date => Some(format!("not before {}", limit)),
+                    _ => None,
+                }
+            },
+            Condition::And(filters) => filters.iter().find_map(|filter| filter.reason(path, relative, date)),
+            Condition::Or(filters) => {
+                let mut reasons: Vec<String> = Vec::new();
+                for filter in filters.iter() {
+                    reasons.push(filter.reason(path, relative, date)?);
+                }
+                match reasons.is_empty() {
+                    true => unmatched(),
+                    false => Some(reasons.join(" and ")),
+                }
+            },
+            Condition::Not(filter) => match filter.reason(path, relative, date) {
+                Some(_) => None,
+                None => Some(format!("matches {}", filter.expression)),
+            },
+        }
+    }
+}
+
+/// What a [`CompiledFilter`] checks, as its [`Filter`] with the values parsed.
+/// The sizes, ages and dates keep the text they were given as, for the reasons
+/// files don't match.
+#[derive(Debug)]
+#[derive(Clone)]
+enum Condition {
+    Extension(Vec<String>),
+    Glob(String),
+    Regex(Regex),
+    Size { min: Option<(u64, String)>, max: Option<(u64, String)> },
+    NewerThan(Duration, String),
+    OlderThan(Duration, String),
+    Since(DateTime<Local>, String),
+    Until(DateTime<Local>, String),
+    And(Vec<CompiledFilter>),
+    Or(Vec<CompiledFilter>),
+    Not(Box<CompiledFilter>),
+}
+
+/// A pattern for paths inside SOURCE, as given to `--exclude-path`: a glob, as
+/// in `**/node_modules` or `.git`, or a regular expression after `regex:`, as
+/// in `regex:(^|/)@eaDir$`. Globs are matched as in [`glob_match_path`], so
+/// globs without a `/` match names at any depth, and regular expressions are
+/// matched against the whole path inside SOURCE, with `/` between its parts.
+#[derive(Debug)]
+#[derive(Clone)]
+pub enum PathPattern {
+    Glob(String),
+    Regex(Regex),
+}
+impl PathPattern {
+
+    /// Return the [`PathPattern`] represented by `pattern`, or a [`String`]
+    /// describing why it isn't valid. A `glob:` prefix is allowed, but not needed.
+    pub fn parse(pattern: &str) -> Result<PathPattern, String> {
+        match pattern.strip_prefix("regex:") {
+            Some(regex) => Regex::new(regex)
+                .map(PathPattern::Regex)
+                .map_err(|reason| format!("invalid regex \"{}\": {}", regex, reason)),
+            None if pattern.is_empty() || pattern == "glob:" => Err(String::from("empty pattern")),
+            None => Ok(PathPattern::Glob(String::from(pattern.strip_prefix("glob:").unwrap_or(pattern)))),
+        }
+    }
+
+    /// Return [`true`] if the path `relative`, inside SOURCE, matches the pattern.
+    pub fn matches(&self, relative: &Path) -> bool {
+        match self {
+            PathPattern::Glob(pattern) => glob_match_path(pattern, relative),
+            PathPattern::Regex(regex) => {
+                let parts: Vec<String> = relative.iter().map(|part| part.to_string_lossy().to_string()).collect();
+                regex.is_match(&parts.join("/"))
+            },
+        }
+    }
+}
+
+/// Limits on the size and date of the files to sort, as given by the
+/// `--min-size`, `--max-size`, `--newer-than`, `--older-than`, `--since` and
+/// `--until` options, and the keys of the same names in the json config. Each
+/// limit that is given becomes a [`Filter`].
+#[derive(Debug)]
+#[derive(Clone, Default, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub struct Limits {
+    pub min_size: Option<String>,
+    pub max_size: Option<String>,
+    pub newer_than: Option<String>,
+    pub older_than: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+}
+impl Limits {
+
+    /// Return the filters for the limits that are given.
+    pub fn filters(self) -> Vec<Filter> {
+        let mut filters: Vec<Filter> = Vec::new();
+        if self.min_size.is_some() || self.max_size.is_some() {
+            filters.push(Filter::Size { min: self.min_size, max: self.max_size });
+        }
+        filters.extend(self.newer_than.map(Filter::NewerThan));
+        filters.extend(self.older_than.map(Filter::OlderThan));
+        filters.extend(self.since.map(Filter::Since));
+        filters.extend(self.until.map(Filter::Until));
+        filters
+    }
+}
+
+/// Return `value` in quotes if it has to be quoted in an expression.
+fn quote(value: &str) -> String {
+    match value.contains(|c: char| c.is_whitespace()) {
+        true => format!("\"{}\"", value),
+        false => String::from(value),
+    }
+}
+
+/// A token of a filter expression: a parenthesis, or a word, which is a term
+/// or one of `and`, `or` and `not`.
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Word(String),
+}
+
+/// Return the tokens of the filter expression `expression`. A word runs up to
+/// whitespace, or a `)` that wasn't opened in the word, so that regular
+/// expressions can have groups in them; quoted parts of words can have
+/// anything in them but quotes.
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            '(' | ')' => {
+                chars.next();
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            },
+            c if c.is_whitespace() => {
+                chars.next();
+            },
+            _ => {
+                let mut word = String::new();
+                let (mut depth, mut quoted) = (0, false);
+                while let Some(&c) = chars.peek() {
+                    match c {
+                        '"' => quoted = !quoted,
+                        _ if quoted => word.push(c),
+                        c if c.is_whitespace() => break,
+                        ')' if depth == 0 => break,
+                        '(' | ')' => {
+                            depth = if c == '(' { depth + 1 } else { depth - 1 };
+                            word.push(c);
+                        },
+                        _ => word.push(c),
+                    }
+                    chars.next();
+                }
+                if quoted {
+                    return Err(format!("unclosed quote in \"{}\"", word));
+                }
+                tokens.push(Token::Word(word));
+            },
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads a [`Filter`] from the `tokens` of an expression, keeping track of the
+/// `position` of the next token to read.
+struct ExpressionParser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+impl ExpressionParser {
+
+    /// Return the next word, without reading it, if the next token is one.
+    fn peek_word(&self) -> Option<&str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Word(word)) => Some(word),
+            _ => None,
+        }
+    }
+
+    /// Read filters separated by `or`.
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut filters = vec![self.and()?];
+        while self.peek_word() == Some("or") {
+            self.position += 1;
+            filters.push(self.and()?);
+        }
+        Ok(match filters.len() {
+            1 => filters.remove(0),
+            _ => Filter::Or(filters),
+        })
+    }
+
+    /// Read filters separated by `and`, or by nothing.
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut filters = vec![self.not()?];
+        loop {
+            match self.tokens.get(self.position) {
+                Some(Token::Word(word)) if word == "and" => {
+                    self.position += 1;
+                    filters.push(self.not()?);
+                },
+                Some(Token::Word(word)) if word != "or" => filters.push(self.not()?),
+                Some(Token::Open) => filters.push(self.not()?),
+                _ => break,
+            }
+        }
+        Ok(match filters.len() {
+            1 => filters.remove(0),
+            _ => Filter::And(filters),
+        })
+    }
+
+    /// Read a term, a filter in parentheses, or either of them after `not`.
+    fn not(&mut self) -> Result<Filter, String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Word(word)) if word == "not" => Ok(Filter::Not(Box::new(self.not()?))),
+            Some(Token::Word(word)) if word == "and" || word == "or" => Err(format!("unexpected \"{}\"", word)),
+            Some(Token::Word(word)) => term(&word),
+            Some(Token::Open) => {
+                let filter = self.or()?;
+                match self.tokens.get(self.position) {
+                    Some(Token::Close) => {
+                        self.position += 1;
+                        Ok(filter)
+                    },
+                    _ => Err(String::from("unclosed \"(\"")),
+                }
+            },
+            Some(Token::Close) => Err(String::from("unexpected \")\"")),
+            None => Err(String::from("unexpected end")),
+        }
+    }
+}
+
+/// Return the filter for the term `word`, as in `ext:jpg,png`.
+fn term(word: &str) -> Result<Filter, String> {
+    let (name, value) = match word.split_once(':') {
+        Some(term) => term,
+        None => return Err(format!("\"{}\" isn't a term like ext:jpg", word)),
+    };
+    let value = String::from(value);
+    Ok(match name {
+        "ext" => Filter::Extension(value.split(',').map(|extension| extension.trim_start_matches('.').to_lowercase()).collect()),
+        "glob" => Filter::Glob(value),
+        "regex" => Filter::Regex(value),
+        "size" => match value.split_once("..") {
+            Some((min, max)) => {
+                let bound = |size: &str| match size.is_empty() {
+                    true => None,
+                    false => Some(String::from(size)),
+                };
+                Filter::Size { min: bound(min), max: bound(max) }
+            },
+            None => return Err(format!("size \"{}\" isn't a range like 10KB..1MB", value)),
+        },
+        "newer" => Filter::NewerThan(value),
+        "older" => Filter::OlderThan(value),
+        "since" => Filter::Since(value),
+        "until" => Filter::Until(value),
+        _ => return Err(format!("unknown filter \"{}\"", name)),
+    })
+}
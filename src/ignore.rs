@@ -0,0 +1,222 @@
+//! Ignore files, which leave paths out of sorts and extractions without
+//! editing the config. A [`IGNORE_FILE_NAME`] file can be put in any directory
+//! of SOURCE, and ignores the paths below that directory matching its
+//! patterns, one per line, with the same syntax as `.gitignore` files:
+//!
+//! <ul>
+//! <li>Blank lines, and lines starting with <code>#</code>, are left out</li>
+//! <li>A pattern starting with <code>!</code> brings back the paths it matches, if an earlier pattern ignored them</li>
+//! <li>A pattern ending with <code>/</code> only matches directories</li>
+//! <li>A pattern with a <code>/</code> at the start or in the middle matches paths from the ignore file's directory, as in <code>/build</code> or <code>docs/*.pdf</code>; other patterns match names at any depth, as in <code>*.tmp</code></li>
+//! <li><code>*</code>, <code>?</code> and <code>[...]</code> match within a name, as in <code>glob_match</code>, and <code>**</code> matches any number of directories, as in <code>photos/**/raw</code></li>
+//! </ul>
+//!
+//! Later patterns take precedence over earlier ones, and the ignore files of
+//! deeper directories over those of the directories they are in. Ignored
+//! directories aren't walked into, so their contents can't be brought back.
+//! `.gitignore` files can be honoured as well, with [`IGNORE_FILE_NAME`]
+//! taking precedence over them in the same directory.
+
+use crate::structs::{glob_match, glob_match_path};
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
+
+#[cfg(test)]
+mod tests {
+
+    use std::{env, fs, path::Path};
+    use super::{IgnoreFiles, IgnorePattern};
+
+    #[test]
+    /// Test [`IgnorePattern::parse`]
+    fn test_parse() {
+        assert_eq!(IgnorePattern::parse(""), None);
+        assert_eq!(IgnorePattern::parse("   "), None);
+        assert_eq!(IgnorePattern::parse("# comment"), None);
+        assert_eq!(IgnorePattern::parse("/"), None);
+        let pattern = IgnorePattern::parse("!/build/  ").unwrap();
+        assert_eq!((pattern.glob.as_str(), pattern.negated, pattern.dir_only, pattern.anchored), ("build", true, true, true));
+        let pattern = IgnorePattern::parse("\\#notes.txt").unwrap();
+        assert_eq!((pattern.glob.as_str(), pattern.negated, pattern.dir_only, pattern.anchored), ("#notes.txt", false, false, false));
+        let pattern = IgnorePattern::parse("docs/*.pdf").unwrap();
+        assert_eq!((pattern.glob.as_str(), pattern.anchored), ("docs/*.pdf", true));
+        assert_eq!(pattern.to_string(), "docs/*.pdf");
+    }
+
+    #[test]
+    /// Test [`IgnorePattern::matches`]
+    fn test_matches() {
+        let matches = |pattern: &str, path: &str, is_dir: bool| IgnorePattern::parse(pattern).unwrap().matches(Path::new(path), is_dir);
+
+        assert!(matches("*.tmp", "a/b/c.tmp", false));
+        assert!(matches("build/", "src/build", true));
+        assert!(!matches("build/", "src/build", false));
+        assert!(matches("/build", "build", true));
+        assert!(!matches("/build", "src/build", true));
+        assert!(matches("docs/*.pdf", "docs/a.pdf", false));
+        assert!(!matches("docs/*.pdf", "docs/old/a.pdf", false));
+        assert!(matches("photos/**/raw", "photos/2021/02/raw", true));
+        assert!(matches("**/cache", "a/cache", true));
+        assert!(matches("IMG_[0-9]*.jpg", "IMG_0001.jpg", false));
+    }
+
+    #[test]
+    /// Test the [`IgnoreFiles`] struct
+    fn test_ignorefiles() {
+        let dir = env::temp_dir().join("sortery_test_ignorefiles");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+        }
+        fs::create_dir_all(dir.join("photos/raw")).expect("Failed to create dir.");
+        fs::write(dir.join(".sorteryignore"), "*.tmp\nraw/\n").expect("Failed to write file.");
+        fs::write(dir.join("photos/.sorteryignore"), "!keep.tmp\n!raw/\n").expect("Failed to write file.");
+        fs::write(dir.join(".gitignore"), "*.log\n").expect("Failed to write file.");
+
+        let mut ignore_files = IgnoreFiles::new(&dir, false);
+        assert_eq!(ignore_files.reason(Path::new("a.tmp"), false), Some(String::from("matches *.tmp in .sorteryignore")));
+        assert_eq!(ignore_files.reason(Path::new("photos/b.tmp"), false), Some(String::from("matches *.tmp in .sorteryignore")));
+        assert_eq!(ignore_files.reason(Path::new("photos/keep.tmp"), false), None);
+        assert_eq!(ignore_files.reason(Path::new("raw"), true), Some(String::from("matches raw/ in .sorteryignore")));
+        assert_eq!(ignore_files.reason(Path::new("photos/raw"), true), None);
+        assert_eq!(ignore_files.reason(Path::new("a.log"), false), None);
+        assert_eq!(ignore_files.reason(Path::new("a.jpg"), false), None);
+
+        assert_eq!(ignore_files.file_names(), &[".sorteryignore"]);
+
+        let mut ignore_files = IgnoreFiles::new(&dir, true);
+        assert_eq!(ignore_files.file_names(), &[".gitignore", ".sorteryignore"]);
+        assert_eq!(ignore_files.reason(Path::new("photos/a.log"), false), Some(String::from("matches *.log in .gitignore")));
+
+        fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+    }
+}
+
+/// The name of the ignore files that can be put in any directory of SOURCE.
+pub const IGNORE_FILE_NAME: &str = ".sorteryignore";
+
+/// The name of git's ignore files, which are honoured if asked for.
+pub const GITIGNORE_FILE_NAME: &str = ".gitignore";
+
+/// A single pattern of an ignore file.
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+pub struct IgnorePattern {
+    pub glob: String,
+    pub negated: bool,
+    pub dir_only: bool,
+    pub anchored: bool,
+}
+impl IgnorePattern {
+
+    /// Return the pattern on `line` of an ignore file, or [`None`] if the line
+    /// is blank or a comment. A `\` at the start lets patterns start with `#` or `!`.
+    pub fn parse(line: &str) -> Option<IgnorePattern> {
+
+        // Trailing spaces are left out, unless they are escaped
+        let mut line = line.trim_end_matches(['\r', '\n']);
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() || line.starts_with('#') { return None }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line.strip_prefix('\\').filter(|line| line.starts_with(['#', '!'])).unwrap_or(line)),
+        };
+        let dir_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        let anchored = line.contains('/');
+        let glob = line.trim_start_matches('/').replace("\\ ", " ");
+        if glob.is_empty() { return None }
+        Some(IgnorePattern { glob, negated, dir_only, anchored })
+    }
+
+    /// Return [`true`] if the pattern matches the path `relative`, inside the
+    /// ignore file's directory. `is_dir` is whether the path is a directory.
+    pub fn matches(&self, relative: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir { return false }
+        let name = relative.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        match (self.anchored, self.glob.contains('/')) {
+            (false, _) => glob_match(&self.glob, &name),
+            (true, false) => relative.iter().count() == 1 && glob_match(&self.glob, &name),
+            (true, true) => glob_match_path(&self.glob, relative),
+        }
+    }
+
+    /// Return the pattern as it would be written in an ignore file.
+    pub fn to_string(&self) -> String {
+        format!(
+            "{}{}{}{}",
+            if self.negated { "!" } else { "" },
+            if self.anchored && !self.glob.contains('/') { "/" } else { "" },
+            self.glob,
+            if self.dir_only { "/" } else { "" }
+        )
+    }
+}
+
+/// The ignore files in the directory tree at `root`, which are read as the
+/// directories they are in are reached. If `gitignore` is [`true`], the
+/// `.gitignore` files are read along with the [`IGNORE_FILE_NAME`] files.
+pub struct IgnoreFiles {
+    root: PathBuf,
+    file_names: Vec<&'static str>,
+    patterns: HashMap<PathBuf, Vec<(PathBuf, IgnorePattern)>>,
+}
+impl IgnoreFiles {
+
+    /// Return the ignore files in the directory tree at `root`.
+    pub fn new(root: &Path, gitignore: bool) -> IgnoreFiles {
+        IgnoreFiles {
+            root: root.to_path_buf(),
+            file_names: match gitignore {
+                true => vec![GITIGNORE_FILE_NAME, IGNORE_FILE_NAME],
+                false => vec![IGNORE_FILE_NAME],
+            },
+            patterns: HashMap::new(),
+        }
+    }
+
+    /// Return the names of the ignore files that are read, which are left
+    /// where they are by sorts and extractions.
+    pub fn file_names(&self) -> &[&'static str] {
+        &self.file_names
+    }
+
+    /// Return the patterns of the ignore files in the directory `dir`, inside
+    /// the root, each with the path of its file, reading them if they haven't been yet.
+    fn patterns_in(&mut self, dir: &Path) -> &Vec<(PathBuf, IgnorePattern)> {
+        let (root, file_names) = (&self.root, &self.file_names);
+        self.patterns.entry(dir.to_path_buf()).or_insert_with(|| {
+            let mut patterns = Vec::new();
+            for file_name in file_names.iter() {
+                let file = dir.join(file_name);
+                if let Ok(contents) = fs::read_to_string(root.join(&file)) {
+                    patterns.extend(contents.lines().filter_map(IgnorePattern::parse).map(|pattern| (file.clone(), pattern)));
+                }
+            }
+            patterns
+        })
+    }
+
+    /// Return why the path `relative`, inside the root, is ignored, as in
+    /// `"matches *.tmp in photos/.sorteryignore"`, or [`None`] if it isn't.
+    /// `is_dir` is whether the path is a directory.
+    pub fn reason(&mut self, relative: &Path, is_dir: bool) -> Option<String> {
+        let mut dirs: Vec<PathBuf> = relative.ancestors().skip(1).map(Path::to_path_buf).collect();
+        dirs.reverse();
+
+        let mut reason = None;
+        for dir in dirs.iter() {
+            let inside = relative.strip_prefix(dir).unwrap_or(relative);
+            for (file, pattern) in self.patterns_in(dir).iter() {
+                if pattern.matches(inside, is_dir) {
+                    reason = match pattern.negated {
+                        true => None,
+                        false => Some(format!("matches {} in {}", pattern.to_string(), file.to_string_lossy())),
+                    };
+                }
+            }
+        }
+        reason
+    }
+}
@@ -0,0 +1,25 @@
+//! Sortery is a simple, fast command-line file sorter for Linux.
+//! You can find the GitHib repo at <https://github.com/SamMatzko/Sortery>, and the
+//! Sortery Wiki at <https://github.com/SamMatzko/Sortery/wiki>.
+//! 
+//! The command-line interface is a thin layer over this library. To sort from
+//! your own code, set up a [`Sorter`], [`Sorter::plan`] the sort, and execute
+//! the [`SortPlan`]; see [`Sorter`] for an example. [`tools`] has the other
+//! commands, like extracting and undoing. To follow a sort as it happens, pass
+//! a [`observer::SortObserver`] to [`Sorter::plan_with`] and [`SortPlan::execute_with`].
+//! To keep sorting the files that arrive in a directory, see [`watch::Watcher`].
+
+pub mod filter;
+pub mod ignore;
+pub mod messages;
+pub mod metadata;
+pub mod observer;
+pub mod perceptual;
+mod sorter;
+pub mod structs;
+pub mod template;
+pub mod tools;
+pub mod transfer;
+pub mod watch;
+
+pub use sorter::{PlannedMove, SortError, SortPlan, Sorter};
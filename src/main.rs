@@ -1,15 +1,21 @@
-//! Sortery is a simple, fast command-line file sorter for Linux.
-//! You can find the GitHib repo at <https://github.com/SamMatzko/Sortery>, and the
-//! Sortery Wiki at <https://github.com/SamMatzko/Sortery/wiki>.
+//! The command-line interface of Sortery, a simple, fast command-line file
+//! sorter for Linux. All the sorting is done by the sortery library.
 
-mod messages;
-mod structs;
-mod tools;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use sortery::{
+    filter::{Filter, Limits},
+    messages::error_messages,
+    structs::{ConfigData, ConflictPolicy, Depth, DuplicatePolicy, File, Operation, PerceptualHash, SimilarPolicy, Similarity, SortBy, SymlinkPolicy, JOURNAL_FILE_NAME},
+    observer::{JsonLinesObserver, LogObserver, SilentObserver, SortSummary},
+    tools,
+    watch::Watcher,
+    SortPlan,
+    Sorter,
+};
+use std::{fs, io, time::Duration};
 
-use clap::{App, Arg, SubCommand};
-use messages::error_messages;
-use std::fs;
-use structs::File;
+/// The help for the copy options, at the top level and of the sort subcommand.
+const COPY_HELP: &str = "Copy files instead of moving them, leaving SOURCE untouched.";
 
 /// Run the whole Sortery application, including the cli.
 fn main() {
@@ -26,6 +32,7 @@ Sortery Wiki at <https://github.com/SamMatzko/Sortery/wiki>.";
                         .version("1.4.2")
                         .author("Samuel Matzko")
                         .about(about)
+                        .setting(AppSettings::SubcommandsNegateReqs)
                         .arg(Arg::with_name("SOURCE")
                             .help("The source directory.")
                             .required(true)
@@ -47,32 +54,83 @@ Sortery Wiki at <https://github.com/SamMatzko/Sortery/wiki>.";
                             .short("d")
                             .long("dry-run")
                             .help("Show the intended sort, without actually sorting."))
+                        .arg(Arg::with_name("copy")
+                            .long("copy")
+                            .help(COPY_HELP))
+                        .arg(Arg::with_name("json")
+                            .long("json")
+                            .help("Print the plan, progress and results as json lines for other programs, instead of the \
+progress bar. With --dry-run, only the plan is printed."))
+                        .arg(Arg::with_name("verbose")
+                            .short("v")
+                            .long("verbose")
+                            .help("With --dry-run, also show the paths left out by .sorteryignore files, and the patterns \
+that ignore them."))
+                        .arg(Arg::with_name("force")
+                            .long("force")
+                            .help("Sort even if SOURCE is inside TARGET, which would sort the files already in TARGET \
+again. A TARGET inside SOURCE is always left out of the sort."))
+                        .arg(Arg::with_name("min-depth")
+                            .long("min-depth")
+                            .takes_value(true)
+                            .help("Leave the paths shallower than this depth in SOURCE where they are; the paths \
+directly in SOURCE are at depth 1. When extracting, the directories above this depth are left out of TARGET."))
+                        .arg(Arg::with_name("max-depth")
+                            .long("max-depth")
+                            .takes_value(true)
+                            .help("Don't look deeper than this depth in SOURCE; 1 only sorts the files directly in it. \
+When extracting, the entries at this depth are moved as a whole. Defaults to the min depth when extracting."))
+                        .arg(Arg::with_name("gitignore")
+                            .long("gitignore")
+                            .help("Honour .gitignore files as well as .sorteryignore files, which leave the paths \
+matching their patterns in SOURCE where they are."))
                         .subcommand(SubCommand::with_name("sort")
                             .about("Advanced sorting options.")
-                            .arg(Arg::with_name("preserve-name")
-                                .short("p")
-                                .long("preserve-name")
-                                .help("Preserve the original file name when renaming."))
-                            .arg(Arg::with_name("date-format")
-                                .long("date-format")
-                                .takes_value(true)
-                                .default_value("%Y-%m-%d %Hh%Mm%Ss")
-                                .help("The date format for renaming files."))
-                            .arg(Arg::with_name("date-type")
-                                .long("date-type")
+                            .args(&sort_args())
+                            .arg(Arg::with_name("refresh")
+                                .long("refresh")
+                                .requires("link")
+                                .help("Remove the links made into TARGET by earlier runs before linking, so that \
+the view matches SOURCE again.")))
+                        .subcommand(SubCommand::with_name("watch")
+                            .about("Keep watching SOURCE, and sort the files that arrive in it into TARGET as they do, \
+with the same options as sort, until stopped.")
+                            .arg(Arg::with_name("SOURCE")
+                                .help("The directory to watch.")
+                                .required(true)
+                                .index(1))
+                            .arg(Arg::with_name("TARGET")
+                                .help("The target directory.")
+                                .required(true)
+                                .index(2))
+                            .arg(Arg::with_name("debounce")
+                                .long("debounce")
                                 .takes_value(true)
-                                .default_value("c")
-                                .help("Set which date to sort by."))
-                            .arg(Arg::with_name("exclude-type")
-                                .short("e")
-                                .long("exclude-type")
+                                .default_value("2")
+                                .help("How many seconds nothing has to happen to a file for before it is sorted, so that \
+files still being written are left alone until they are done."))
+                            .arg(Arg::with_name("sort-existing")
+                                .long("sort-existing")
+                                .help("Sort the files already in SOURCE too, instead of only the ones that arrive."))
+                            .args(&sort_args()))
+                        .subcommand(SubCommand::with_name("undo")
+                            .about("Move the files of a previous run back to where they came from.")
+                            .arg(Arg::with_name("TARGET")
+                                .help("The target directory of the run to undo.")
+                                .required(true)
+                                .index(1))
+                            .arg(Arg::with_name("run-id")
+                                .long("run-id")
                                 .takes_value(true)
-                                .help("File extension(s) to exclude when sorting."))
-                            .arg(Arg::with_name("only-type")
-                                .short("o")
-                                .long("only-type")
-                                .takes_value(true)
-                                .help("File extension(s) to exclusively sort.")))
+                                .help("The id of the run to undo. Defaults to the most recent run."))
+                            .arg(Arg::with_name("force")
+                                .short("f")
+                                .long("force")
+                                .help("Undo files even if they were modified since they were sorted."))
+                            .arg(Arg::with_name("dry-run")
+                                .short("d")
+                                .long("dry-run")
+                                .help("Show the intended undo, without actually moving anything.")))
                         .get_matches();
     /*
     Run everything according to the command-line arguments
@@ -81,68 +139,475 @@ Sortery Wiki at <https://github.com/SamMatzko/Sortery/wiki>.";
     // The variable telling whether we need to exit because of an error
     let mut exit_for_error = false;
 
-    // The source and target directories
-    let source = File::new(matches.value_of("SOURCE").unwrap());
-    let target = File::new(matches.value_of("TARGET").unwrap());
+    // Undo a previous run; this is the only command that doesn't take a source
+    if let Some(sub_matches) = matches.subcommand_matches("undo") {
+        let target = File::new(sub_matches.value_of("TARGET").unwrap());
+        if !target.exists() {
+            println!("{}", error_messages::PathDoesNotExistError { path: &target }.to_string());
+            return;
+        }
+        tools::undo(
+            &target,
+            sub_matches.value_of("run-id"),
+            sub_matches.is_present("force"),
+            sub_matches.is_present("dry-run")
+        );
+        return;
+    }
+
+    // The errors are printed as json lines too when asked for, and without
+    // colors then
+    let json_lines = matches.is_present("json");
+    if json_lines {
+        colored::control::set_override(false);
+    }
+
+    // The source and target directories are required by all the other commands
+    let paths = matches.subcommand_matches("watch").unwrap_or(&matches);
+    let (source, target) = match (paths.value_of("SOURCE"), paths.value_of("TARGET")) {
+        (Some(source), Some(target)) => (File::new(source), File::new(target)),
+        _ => {
+            print_error(&error_messages::MissingArgumentsError.to_string(), json_lines);
+            return;
+        }
+    };
 
     // Check the existence of source and target direcotories, and raise errors
     // if they don't exist
     if !source.exists() {
-        println!("{}", error_messages::PathDoesNotExistError { path: &source }.to_string());
+        print_error(&error_messages::PathDoesNotExistError { path: &source }.to_string(), json_lines);
         exit_for_error = true;
     }
     if !target.exists() {
-        println!("{}", error_messages::PathDoesNotExistError { path: &target }.to_string());
+        print_error(&error_messages::PathDoesNotExistError { path: &target }.to_string(), json_lines);
         exit_for_error = true;
     }
 
     // Exit if there were any errors
     if exit_for_error { return; }
 
-    // If a json config file was given, sort according to it
+    let dry_run = matches.is_present("dry-run");
+    let verbose = matches.is_present("verbose");
+    let gitignore = matches.is_present("gitignore");
+    let mut depths: Vec<Option<usize>> = Vec::new();
+    for name in ["min-depth", "max-depth"].iter() {
+        match matches.value_of(name).map(|depth| depth.parse::<usize>().map_err(|_| depth)).transpose() {
+            Ok(depth) => depths.push(depth),
+            Err(depth) => {
+                print_error(&error_messages::InvalidDepthError { reason: &format!("\"{}\" isn't a number", depth) }.to_string(), json_lines);
+                return;
+            }
+        }
+    }
+    let depth = Depth { min_depth: depths[0], max_depth: depths[1] };
+
+    // Watch SOURCE, sorting the files that arrive in it as they settle, with
+    // the json config if one was given
+    if let Some(sub_matches) = matches.subcommand_matches("watch") {
+        let debounce = sub_matches.value_of("debounce").unwrap_or("2");
+        let debounce = match debounce.parse::<f64>().ok().and_then(|seconds| Duration::try_from_secs_f64(seconds).ok()) {
+            Some(debounce) => debounce,
+            None => {
+                print_error(&error_messages::InvalidDebounceError { debounce }.to_string(), json_lines);
+                return;
+            }
+        };
+        let sorter = match matches.is_present("config-file") {
+            true => config_sorter(source, target, &matches, depth),
+            false => match options_sorter(source, &target, &matches, sub_matches, depth) {
+                Some(sorter) => sorter,
+                None => return,
+            },
+        };
+        run_watcher(sorter, debounce, sub_matches.is_present("sort-existing"), dry_run, json_lines, verbose);
+        return;
+    }
+
+    // If a json config file was given, sort according to it, with the
+    // operation given on the command line taking precedence
     if matches.is_present("config-file") {
-        tools::sort::sort_from_json(
-            fs::read_to_string(matches.value_of("config-file").unwrap())
-                .expect("Failed to read config file."),
-            source,
-            target,
-            matches.is_present("dry-run")
-        );
+        run_sorter(config_sorter(source, target, &matches, depth), dry_run, json_lines, verbose);
         return;
     }
     
     // Run the commands
     if matches.is_present("extract") {
-        tools::extract(&source, &target, matches.is_present("dry-run"));
+        let operation = match matches.is_present("copy") {
+            true => Operation::Copy,
+            false => Operation::Move,
+        };
+        if let Err(reason) = depth.validate() {
+            print_error(&error_messages::InvalidDepthError { reason: &reason }.to_string(), json_lines);
+            return;
+        }
+        let plan = match json_lines {
+            true => tools::extract(&source, &target, operation, gitignore, depth, &mut JsonLinesObserver::new(io::stdout())),
+            false => tools::extract(&source, &target, operation, gitignore, depth, &mut SilentObserver),
+        };
+        run_plan(plan, dry_run, json_lines, verbose);
     } else if matches.is_present("sort") {
 
         // The sub-command matches
         let sub_matches = matches.subcommand_matches("sort").unwrap();
 
-        // Variables configured by the command-line options and used when
-        // running the sort tool.
-        let date_format = sub_matches.value_of("date-format").unwrap_or("%Y-%m-%d %Hh%Mm%Ss");
-        let date_type = sub_matches.value_of("date-type").unwrap_or("c");
-        let preserve_name: bool = sub_matches.is_present("preserve-name");
-        let exclude_type = (
-            sub_matches.value_of("exclude-type").unwrap_or(""),
-            sub_matches.is_present("exclude-type")
-        );
-        let only_type = (
-            sub_matches.value_of("only-type").unwrap_or(""),
-            sub_matches.is_present("only-type")
-        );
+        // The sorter, configured by the command-line options
+        let sorter = match options_sorter(source, &target, &matches, sub_matches, depth) {
+            Some(sorter) => sorter,
+            None => return,
+        };
+
+        // Clear out the old view first, if we're refreshing it
+        if sub_matches.is_present("refresh") && sorter.validate().is_empty() {
+            tools::refresh_view(&target, dry_run, json_lines);
+        }
 
         // Run the sort tool, or dry run if commanded
-        tools::sort::sort(
-            &source,
-            &target,
-            &date_format,
-            &date_type,
-            &preserve_name,
-            exclude_type,
-            only_type,
-            matches.is_present("dry-run")
-        );
+        run_sorter(sorter, dry_run, json_lines, verbose);
+    }
+}
+
+/// Return the [`Sorter`] sorting `source` into `target` with the json config
+/// file given on the command line, with the top-level options in `matches`
+/// taking precedence over it.
+fn config_sorter(source: File, target: File, matches: &ArgMatches, depth: Depth) -> Sorter {
+    let json = fs::read_to_string(matches.value_of("config-file").unwrap())
+        .expect("Failed to read config file.");
+    let mut config = ConfigData::from_json(&json);
+    config.depth = Depth {
+        min_depth: depth.min_depth.or(config.depth.min_depth),
+        max_depth: depth.max_depth.or(config.depth.max_depth),
+    };
+    let mut sorter = Sorter::from_config(source, target, config);
+    if matches.is_present("copy") {
+        sorter = sorter.operation(Operation::Copy);
+    }
+    if matches.is_present("gitignore") {
+        sorter = sorter.gitignore(true);
+    }
+    if matches.is_present("force") {
+        sorter = sorter.force(true);
+    }
+    sorter
+}
+
+/// Return the [`Sorter`] sorting `source` into `target` with the options of
+/// the sort or watch subcommand in `sub_matches`, and the top-level ones in
+/// `matches`. If an option isn't valid, print what is wrong with it and return
+/// [`None`].
+fn options_sorter(source: File, target: &File, matches: &ArgMatches, sub_matches: &ArgMatches, depth: Depth) -> Option<Sorter> {
+    let distance = sub_matches.value_of("similar-distance").unwrap_or("5");
+    let similarity = Similarity {
+        action: SimilarPolicy::from_name(sub_matches.value_of("similar").unwrap_or("off")).unwrap_or_default(),
+        hash: PerceptualHash::from_name(sub_matches.value_of("similar-hash").unwrap_or("dhash")).unwrap_or_default(),
+        distance: match distance.parse::<u32>() {
+            Ok(distance) => distance,
+            Err(_) => {
+                print_error(&error_messages::InvalidDistanceError { distance }.to_string(), matches.is_present("json"));
+                return None;
+            }
+        },
+    };
+    let operation = match sub_matches.value_of("link") {
+        Some(link) => Operation::from_link_name(link, sub_matches.is_present("relative")).unwrap_or_default(),
+        None if matches.is_present("copy") || sub_matches.is_present("copy") => Operation::Copy,
+        None => Operation::Move,
+    };
+    let mut sorter = Sorter::new(source, target.copy())
+        .date_format(sub_matches.value_of("date-format").unwrap_or("%Y-%m-%d %Hh%Mm%Ss"))
+        .dir_format(sub_matches.value_of("dir-format").unwrap_or(tools::sort::DEFAULT_DIR_FORMAT))
+        .date_type(sub_matches.value_of("date-type").unwrap_or("c"))
+        .name_fallback(sub_matches.value_of("name-fallback").unwrap_or("m"))
+        .preserve_name(sub_matches.is_present("preserve-name"))
+        .gitignore(matches.is_present("gitignore"))
+        .force(matches.is_present("force"))
+        .depth(depth)
+        .name_template(sub_matches.value_of("name-template").unwrap_or(""))
+        .exclude_type(sub_matches.value_of("exclude-type").unwrap_or(""))
+        .only_type(sub_matches.value_of("only-type").unwrap_or(""))
+        .on_conflict(ConflictPolicy::from_name(sub_matches.value_of("on-conflict").unwrap_or("rename")).unwrap_or_default())
+        .sort_by(SortBy::from_name(sub_matches.value_of("by").unwrap_or("date")).unwrap_or_default())
+        .duplicates(DuplicatePolicy::from_name(sub_matches.value_of("duplicates").unwrap_or("keep")).unwrap_or_default())
+        .one_file_system(sub_matches.is_present("one-file-system"))
+        .follow_symlinks(sub_matches.is_present("follow-symlinks"))
+        .symlinks(SymlinkPolicy::from_name(sub_matches.value_of("symlinks").unwrap_or("move")).unwrap_or_default())
+        .similarity(similarity)
+        .operation(operation)
+        .limits(Limits {
+            min_size: sub_matches.value_of("min-size").map(String::from),
+            max_size: sub_matches.value_of("max-size").map(String::from),
+            newer_than: sub_matches.value_of("newer-than").map(String::from),
+            older_than: sub_matches.value_of("older-than").map(String::from),
+            since: sub_matches.value_of("since").map(String::from),
+            until: sub_matches.value_of("until").map(String::from),
+        });
+    for expression in sub_matches.values_of("filter").into_iter().flatten() {
+        match Filter::parse(expression) {
+            Ok(filter) => sorter = sorter.filter(filter),
+            Err(reason) => {
+                print_error(&error_messages::InvalidFilterError { filter: expression, reason: &reason }.to_string(), matches.is_present("json"));
+                return None;
+            }
+        }
+    }
+
+    for pattern in sub_matches.values_of("exclude-path").into_iter().flatten() {
+        sorter = sorter.exclude_path(pattern);
     }
-}
\ No newline at end of file
+    Some(sorter)
+}
+
+/// Plan the sort of `sorter`, and run the plan with [`run_plan`]. If the
+/// options of `sorter` aren't valid, print what is wrong with them instead.
+fn run_sorter(sorter: Sorter, dry_run: bool, json: bool, verbose: bool) {
+    let plan = match json {
+        true => sorter.plan_with(&mut JsonLinesObserver::new(io::stdout())),
+        false => sorter.plan(),
+    };
+    match plan {
+        Ok(plan) => {
+            run_plan(plan, dry_run, json, verbose);
+        },
+        Err(errors) => {
+            for error in errors.iter() {
+                print_error(&error.to_string(), json);
+            }
+        },
+    }
+}
+
+/// Print `plan` if `dry_run` is [`true`], or execute it otherwise, showing the
+/// progress bar. If `json` is [`true`], the progress is printed as json lines
+/// instead, and the plan isn't printed again, since it already was as it was made.
+/// If `verbose` is [`true`], the printed plan includes the ignored paths.
+/// Return [`false`] if the sort had to stop; see [`check_executed`].
+fn run_plan(plan: SortPlan, dry_run: bool, json: bool, verbose: bool) -> bool {
+    let result = match (dry_run, json) {
+        (true, true) => return true,
+        (true, false) => {
+            match verbose {
+                true => plan.print_verbose(),
+                false => plan.print(),
+            }
+            return true;
+        },
+        (false, true) => plan.execute_with(&mut JsonLinesObserver::new(io::stdout())),
+        (false, false) => plan.execute().inspect_err(|_| println!()),
+    };
+    check_executed(&plan, result, json)
+}
+
+/// Return [`true`] if `plan` was executed, as told by `result`, or print why
+/// the sort had to stop, as a json line if `json` is [`true`], and return [`false`].
+fn check_executed(plan: &SortPlan, result: io::Result<SortSummary>, json: bool) -> bool {
+    match result {
+        Ok(_) => true,
+        Err(error) => {
+            let journal = File::from_pathbuf(&plan.target).join_string(&String::from(JOURNAL_FILE_NAME));
+            print_error(&error_messages::JournalFailedError { path: &journal, error: &error.to_string() }.to_string(), json);
+            false
+        },
+    }
+}
+
+/// Print the error `message`, or write it as a json line with the `failed`
+/// event if `json` is [`true`]; see [`JsonLinesObserver`].
+fn print_error(message: &str, json: bool) {
+    match json {
+        true => JsonLinesObserver::new(io::stdout()).on_failed(message),
+        false => println!("{}", message),
+    }
+}
+
+/// Watch the source of `sorter`, and sort the files that arrive in it once
+/// nothing has happened to them for `debounce`, as well as the files already
+/// in it if `sort_existing` is [`true`], logging what is done with each,
+/// until the source or the target goes away. If `dry_run` is [`true`],
+/// the sorts are printed as with [`run_plan`] instead, and if `json` is
+/// [`true`], everything is printed as json lines.
+fn run_watcher(sorter: Sorter, debounce: Duration, sort_existing: bool, dry_run: bool, json: bool, verbose: bool) {
+    let mut watcher = match Watcher::new(sorter, debounce, sort_existing) {
+        Ok(watcher) => watcher,
+        Err(error) => {
+            print_error(&error.to_string(), json);
+            return;
+        }
+    };
+    loop {
+        let plan = match json {
+            true => watcher.poll(&mut JsonLinesObserver::new(io::stdout())),
+            false => watcher.poll(&mut SilentObserver),
+        };
+        match plan {
+            Ok(Some(plan)) if dry_run || json => {
+                if !run_plan(plan, dry_run, json, verbose) { return }
+            },
+            Ok(Some(plan)) => {
+                let result = plan.execute_with(&mut LogObserver::new(io::stdout(), plan.operation));
+                if !check_executed(&plan, result, json) { return }
+            },
+            Ok(None) => {},
+            Err(error) => {
+                print_error(&error.to_string(), json);
+                return;
+            }
+        }
+    }
+}
+
+/// Return the options of the sort subcommand that the watch subcommand takes
+/// as well, which set up the [`Sorter`]; see [`options_sorter`].
+fn sort_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("copy")
+            .long("copy")
+            .conflicts_with("link")
+            .help(COPY_HELP),
+        Arg::with_name("link")
+            .long("link")
+            .takes_value(true)
+            .possible_values(&Operation::LINK_NAMES)
+            .help("Link files into TARGET instead of moving them, to build a view of SOURCE."),
+        Arg::with_name("relative")
+            .long("relative")
+            .requires("link")
+            .help("Make symbolic links relative to where they are, rather than absolute."),
+        Arg::with_name("preserve-name")
+            .short("p")
+            .long("preserve-name")
+            .help("Preserve the original file name when renaming."),
+        Arg::with_name("name-template")
+            .short("n")
+            .long("name-template")
+            .takes_value(true)
+            .help("A template for the new file names, as in {date:%Y%m%d}_{stem}_{counter:04}.{ext|lower}. \
+Overrides --date-format and --preserve-name."),
+        Arg::with_name("date-format")
+            .long("date-format")
+            .takes_value(true)
+            .default_value("%Y-%m-%d %Hh%Mm%Ss")
+            .help("The date format for renaming files."),
+        Arg::with_name("dir-format")
+            .long("dir-format")
+            .takes_value(true)
+            .default_value("%Y/%m/")
+            .help("The date format of the directories to sort files into. Also takes \
+{quarter}, {half} and {week}, the ISO week, which goes with the ISO year %G, as in %G/W{week}; use \"\" to sort \
+all files directly into TARGET."),
+        Arg::with_name("date-type")
+            .long("date-type")
+            .takes_value(true)
+            .default_value("c")
+            .help("Set which date to sort by: c (created), a (accessed), m (modified), exif, video, or filename, \
+or a comma-separated chain of them to try in turn, as in exif,filename,m."),
+        Arg::with_name("name-fallback")
+            .long("name-fallback")
+            .takes_value(true)
+            .default_value("m")
+            .help("The date type to use for files with no date in their name, with --date-type filename."),
+        Arg::with_name("exclude-type")
+            .short("e")
+            .long("exclude-type")
+            .takes_value(true)
+            .help("File extension(s) to exclude when sorting."),
+        Arg::with_name("only-type")
+            .short("o")
+            .long("only-type")
+            .takes_value(true)
+            .help("File extension(s) to exclusively sort."),
+        Arg::with_name("filter")
+            .short("f")
+            .long("filter")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Only sort files matching a filter expression, as in \
+\"ext:jpg,png and size:10KB.. and not glob:**/thumbnails/**\". The terms are ext:, glob:, regex:, size:MIN..MAX, \
+newer:AGE, older:AGE, since:DATE and until:DATE, combined with and, or, not and parentheses. Can be given \
+more than once; files have to match all the filters."),
+        Arg::with_name("exclude-path")
+            .short("x")
+            .long("exclude-path")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Leave paths inside SOURCE matching a glob, as in **/node_modules or .git, \
+or a regex after regex:, out of the sort. Excluded directories aren't walked into at all. Can be given more than once."),
+        Arg::with_name("min-size")
+            .long("min-size")
+            .takes_value(true)
+            .help("Only sort files of at least this size, as in 10KB or 1.5MiB."),
+        Arg::with_name("max-size")
+            .long("max-size")
+            .takes_value(true)
+            .help("Only sort files of at most this size, as in 10KB or 1.5MiB."),
+        Arg::with_name("newer-than")
+            .long("newer-than")
+            .takes_value(true)
+            .help("Only sort files at most this old by the date they are sorted by, as in 30d. \
+The units are s, min, h, d, w, mo and y."),
+        Arg::with_name("older-than")
+            .long("older-than")
+            .takes_value(true)
+            .help("Only sort files at least this old by the date they are sorted by, as in 1w."),
+        Arg::with_name("since")
+            .long("since")
+            .takes_value(true)
+            .help("Only sort files dated on or after this date, as in 2021-02-03 or \"2021-02-03 14:22\"."),
+        Arg::with_name("until")
+            .long("until")
+            .takes_value(true)
+            .help("Only sort files dated before this date, as in 2021-02-03 or \"2021-02-03 14:22\"."),
+        Arg::with_name("on-conflict")
+            .long("on-conflict")
+            .takes_value(true)
+            .possible_values(&ConflictPolicy::NAMES)
+            .default_value("rename")
+            .help("What to do when a file already exists in TARGET."),
+        Arg::with_name("duplicates")
+            .long("duplicates")
+            .takes_value(true)
+            .possible_values(&DuplicatePolicy::NAMES)
+            .default_value("keep")
+            .help("What to do with files identical to one already in TARGET or sorted before them. \
+move puts them in TARGET/duplicates, delete puts them in TARGET/.sortery_trash so that the run can still be undone, \
+and hardlink sorts them as links to the original."),
+        Arg::with_name("one-file-system")
+            .long("one-file-system")
+            .help("Don't look into directories on other filesystems than SOURCE, like mounted network shares."),
+        Arg::with_name("follow-symlinks")
+            .long("follow-symlinks")
+            .help("Look into the directories that symlinks in SOURCE point to."),
+        Arg::with_name("symlinks")
+            .long("symlinks")
+            .takes_value(true)
+            .possible_values(&SymlinkPolicy::NAMES)
+            .default_value("move")
+            .help("What to do with symlinks to files. resolve sorts a copy of the file each symlink points to \
+in its place. Named pipes, sockets and devices are always left where they are."),
+        Arg::with_name("similar")
+            .long("similar")
+            .takes_value(true)
+            .possible_values(&SimilarPolicy::NAMES)
+            .default_value("off")
+            .help("Look for JPEG and PNG images that look like a higher-resolution image being sorted, \
+as when resized or re-encoded. report notes them, and quarantine puts them in TARGET/similar."),
+        Arg::with_name("similar-hash")
+            .long("similar-hash")
+            .takes_value(true)
+            .possible_values(&PerceptualHash::NAMES)
+            .default_value("dhash")
+            .help("The perceptual hash used to compare images with --similar."),
+        Arg::with_name("similar-distance")
+            .long("similar-distance")
+            .takes_value(true)
+            .default_value("5")
+            .help("How many bits out of 64 the hashes of two images can differ by for them to count \
+as similar with --similar."),
+        Arg::with_name("by")
+            .long("by")
+            .takes_value(true)
+            .possible_values(&SortBy::NAMES)
+            .default_value("date")
+            .help("Sort files by date, or into category directories (Images, Videos, Documents, etc.)."),
+    ]
+}
@@ -1,26 +1,97 @@
 //! The module containing all the commonly-used command-line messages.
 
 use colored::Colorize;
-use crate::structs::File;
+use crate::structs::{File, Operation, SortAction};
 use std::{io, io::Write};
 
-/// The highlighted message for dry-run output.
+/// The highlighted message for dry-run output. `action` is what is going to be
+/// done with `from_file`; anything other than [`SortAction::Move`] is noted
+/// after the message, along with `date_source`, the date source used for
+/// `from_file`, and `rule`, the name of the routing rule it matched, if there are any.
+/// `operation` is whether the file is going to be moved or copied,
+/// `duplicate_of` is the file that `from_file` is a duplicate of, if it is one,
+/// `similar_to` is the higher-resolution image that `from_file` looks like,
+/// if there is one, `skip_reason` is why the filters or an ignore file left
+/// it out, if they did, and `resolved` is the file that `from_file` links to,
+/// if a copy of it is going to be sorted in place of the link.
 pub struct DryRunMessage {
     pub from_file: File,
     pub to_file: File,
+    pub action: SortAction,
+    pub date_source: Option<String>,
+    pub rule: Option<String>,
+    pub operation: Operation,
+    pub duplicate_of: Option<File>,
+    pub similar_to: Option<File>,
+    pub skip_reason: Option<String>,
+    pub resolved: Option<File>,
 }
 impl DryRunMessage {
 
     /// Return the highlighted message as a [`String`] that can be printed to the terminal
     pub fn to_string(&self) -> String {
+        let mut notes: Vec<String> = Vec::new();
+        if let Some(rule) = &self.rule {
+            notes.push(format!("rule: {}", rule));
+        }
+        if let Some(date_source) = &self.date_source {
+            notes.push(format!("date: {}", date_source));
+        }
+        match (&self.duplicate_of, &self.skip_reason) {
+            (Some(original), _) => notes.push(format!("{} {}", self.action.description(), original.to_string())),
+            (None, Some(reason)) => notes.push(format!("{}: {}", self.action.description(), reason)),
+            (None, None) if self.action != SortAction::Move => notes.push(String::from(self.action.description())),
+            (None, None) => {},
+        }
+        if let Some(image) = &self.similar_to {
+            notes.push(format!("similar to {}", image.to_string()));
+        }
+        if let Some(resolved) = &self.resolved {
+            notes.push(format!("copy of {}", resolved.to_string()));
+        }
+        let note = match notes.is_empty() {
+            true => String::new(),
+            false => format!(" ({})", notes.join("; ")).yellow().to_string(),
+        };
+        if matches!(self.action, SortAction::SkipFiltered | SortAction::SkipIgnored | SortAction::SkipSpecial) {
+            return format!("Skipping {}{}.", self.from_file.to_string().green(), note);
+        }
         String::from(format!(
-            "Sorting {} to {}.",
+            "{} {} to {}{}.",
+            match self.operation {
+                Operation::Move => "Sorting",
+                Operation::Copy => "Copying",
+                _ => "Linking",
+            },
             format!("{}", self.from_file.to_string()).green(),
-            format!("{}", self.to_file.to_string()).red()
+            format!("{}", self.to_file.to_string()).red(),
+            note
         ))
     }
 }
 
+/// The highlighted message for the dry-run output of removing a copy of or
+/// link to `original`, made by `operation`, which leaves `original` where it is.
+pub struct RemoveMessage {
+    pub file: File,
+    pub original: File,
+    pub operation: Operation,
+}
+impl RemoveMessage {
+
+    /// Return the highlighted message as a [`String`] that can be printed to the terminal
+    pub fn to_string(&self) -> String {
+        format!(
+            "Removing {}{}.",
+            self.file.to_string().red(),
+            format!(" ({} {})", match self.operation {
+                Operation::Copy => "copy of",
+                _ => "link to",
+            }, self.original.to_string()).yellow()
+        )
+    }
+}
+
 /// The command-line progress bar used when sorting.
 /// 
 /// <ul>
@@ -37,29 +108,45 @@ impl DryRunMessage {
 /// `total` is a [`usize`] representing the total value of the progress bar. Bar
 /// pogress and percent completed are calculated using `total`. For example, if
 /// you are going to sort 20 files, you would pass 20 to `total`, and the progress
-/// bar would know to show `50%` when `set_progress(10)` is called.
+/// bar would know to show `50%` when `set_progress(10, ...)` is called.
+/// </li>
+/// <li>
+/// 
+/// `total_bytes` is a [`u64`] of the total size of the items, shown next to the
+/// number of items. If it isn't 0, the bar and percent follow the bytes done
+/// instead, since a single big file can take longer than many small ones.
+/// </li>
+/// </ul>
 pub struct ProgressBar {
     pub completed_message: String,
     pub message: String,
     pub total: usize,
+    pub total_bytes: u64,
 }
 impl ProgressBar {
 
-    /// Print the updated progress bar, with `completed` number of items completed
-    /// out of the total. Automatically calculates percent and bar size.
-    pub fn set_progress(&self, completed: usize) {
+    /// Print the updated progress bar, with `completed` number of items and
+    /// `bytes` bytes completed out of the totals. Automatically calculates
+    /// percent and bar size.
+    pub fn set_progress(&self, completed: usize, bytes: u64) {
 
-        let progress_done: usize = ((20f32/self.total as f32)*completed as f32) as usize;
-        let progress_todo: usize = 20-progress_done as usize;
+        let fraction = match self.total_bytes {
+            0 => completed as f32 / self.total as f32,
+            total_bytes => bytes as f32 / total_bytes as f32,
+        };
+        let progress_done: usize = ((20f32*fraction) as usize).min(20);
+        let progress_todo: usize = 20-progress_done;
 
         print!(
-            " {0} |{1}{2}| {3}% {4}/{5}{6}\r",
+            " {0} |{1}{2}| {3}% {4}/{5} ({6}/{7}){8}\r",
             self.message,
             "⌷".repeat(progress_done),
             "-".repeat(progress_todo),
-            ((100f32/self.total as f32)*completed as f32) as usize,
+            (100f32*fraction) as usize,
             completed,
             self.total,
+            format_bytes(bytes),
+            format_bytes(self.total_bytes),
             " ".repeat(15)
         );
         io::stdout().flush().expect("Failed to flush stdout.");
@@ -68,15 +155,31 @@ impl ProgressBar {
     /// Print the full progress bar, along with `completed_message`.
     pub fn complete(&self) {
         println!(
-            "{0} |{1}| 100% {2}/{2}{3}",
+            "{0} |{1}| 100% {2}/{2} ({3}){4}",
             self.completed_message,
             "⌷".repeat(20),
             self.total,
+            format_bytes(self.total_bytes),
             " ".repeat(15)
         )
     }
 }
 
+/// Return `bytes` as a human-readable size, as in `"12.3 MB"`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
+
 /// Error message structs for common errors.
 pub mod error_messages {
     
@@ -101,6 +204,229 @@ pub mod error_messages {
         }
     }
 
+    /// When [`File`] `source` is inside [`File`] `target`, or is `target`, so
+    /// that sorting it would sort the files already in `target` again.
+    pub struct SourceInTargetError <'a> {
+        pub source: &'a File,
+        pub target: &'a File,
+    }
+    impl <'a> SourceInTargetError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} SOURCE \"{}\" is inside TARGET \"{}\", so the files already sorted would be sorted again. \
+Use --force to sort anyway.",
+                format!("Error:").red(),
+                self.source.to_string(),
+                self.target.to_string()
+            );
+        }
+    }
+
+    /// When a date or directory format isn't a valid strftime format. `format`
+    /// is the invalid format.
+    pub struct InvalidFormatError <'a> {
+        pub format: &'a str,
+    }
+    impl <'a> InvalidFormatError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} invalid format \"{}\". Try sortery sort --help for more info.",
+                format!("Error:").red(),
+                self.format
+            );
+        }
+    }
+
+    /// When a file name template isn't valid. `template` is the template, and
+    /// `reason` tells what is wrong with it.
+    pub struct InvalidTemplateError <'a> {
+        pub template: &'a str,
+        pub reason: &'a str,
+    }
+    impl <'a> InvalidTemplateError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} invalid name template \"{}\": {}. Try sortery sort --help for more info.",
+                format!("Error:").red(),
+                self.template,
+                self.reason
+            );
+        }
+    }
+
+    /// When a date type chain contains a date source that doesn't exist.
+    /// `date_type` is the whole chain.
+    pub struct InvalidDateTypeError <'a> {
+        pub date_type: &'a str,
+    }
+    impl <'a> InvalidDateTypeError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} invalid date type \"{}\". Try sortery sort --help for more info.",
+                format!("Error:").red(),
+                self.date_type
+            );
+        }
+    }
+
+    /// When the distance for similar images isn't a number of bits from 0 to
+    /// 64. `distance` is the invalid distance.
+    pub struct InvalidDistanceError <'a> {
+        pub distance: &'a str,
+    }
+    impl <'a> InvalidDistanceError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} invalid similarity distance \"{}\"; it must be a number from 0 to 64. Try sortery sort --help for more info.",
+                format!("Error:").red(),
+                self.distance
+            );
+        }
+    }
+
+    /// When the time for files to settle before they are sorted, when
+    /// watching, isn't a number of seconds. `debounce` is the invalid time.
+    pub struct InvalidDebounceError <'a> {
+        pub debounce: &'a str,
+    }
+    impl <'a> InvalidDebounceError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} invalid debounce \"{}\"; it must be a number of seconds. Try sortery watch --help for more info.",
+                format!("Error:").red(),
+                self.debounce
+            );
+        }
+    }
+
+    /// When a file filter isn't valid. `filter` is the filter, and `reason`
+    /// tells what is wrong with it.
+    pub struct InvalidFilterError <'a> {
+        pub filter: &'a str,
+        pub reason: &'a str,
+    }
+    impl <'a> InvalidFilterError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} invalid filter \"{}\": {}. Try sortery sort --help for more info.",
+                format!("Error:").red(),
+                self.filter,
+                self.reason
+            );
+        }
+    }
+
+    /// When a pattern of paths to exclude isn't valid. `pattern` is the
+    /// pattern, and `reason` tells what is wrong with it.
+    pub struct InvalidExcludePathError <'a> {
+        pub pattern: &'a str,
+        pub reason: &'a str,
+    }
+    impl <'a> InvalidExcludePathError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} invalid path to exclude \"{}\": {}. Try sortery sort --help for more info.",
+                format!("Error:").red(),
+                self.pattern,
+                self.reason
+            );
+        }
+    }
+
+    /// When the depths to look into SOURCE at aren't valid. `reason` tells
+    /// what is wrong with them.
+    pub struct InvalidDepthError <'a> {
+        pub reason: &'a str,
+    }
+    impl <'a> InvalidDepthError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} invalid depth: {}. Try sortery --help for more info.",
+                format!("Error:").red(),
+                self.reason
+            );
+        }
+    }
+
+    /// When a routing rule in the json config isn't valid. `rule` is the name
+    /// of the rule, and `reason` describes what is wrong with it.
+    pub struct InvalidRuleError <'a> {
+        pub rule: &'a str,
+        pub reason: &'a str,
+    }
+    impl <'a> InvalidRuleError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} invalid rule {}: {}.",
+                format!("Error:").red(),
+                self.rule,
+                self.reason
+            );
+        }
+    }
+
+    /// When the categories in the json config aren't valid. `reason` tells
+    /// what is wrong with them.
+    pub struct InvalidCategoriesError <'a> {
+        pub reason: &'a str,
+    }
+    impl <'a> InvalidCategoriesError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} invalid categories: {}.",
+                format!("Error:").red(),
+                self.reason
+            );
+        }
+    }
+
+    /// When the SOURCE and/or TARGET arguments are missing.
+    pub struct MissingArgumentsError;
+    impl MissingArgumentsError {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} SOURCE and TARGET are required. Try sortery --help for more info.",
+                format!("Error:").red()
+            );
+        }
+    }
+
     /// When an attemtped file rename fails. [`File`] `source` is the old file path,
     /// and [`File`] `target` is the path that `source` should have been renamed to.
     pub struct PathMoveFailedError <'a> {
@@ -120,4 +446,119 @@ pub mod error_messages {
                 )
         }
     }
-}
\ No newline at end of file
+
+    /// When the link [`File`] `path` can't be removed because of `error`.
+    pub struct PathRemoveFailedError <'a> {
+        pub path: &'a File,
+        pub error: &'a str,
+    }
+    impl <'a> PathRemoveFailedError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} failed to remove {}: {}.",
+                format!("Error:").red(),
+                self.path.to_string(),
+                self.error
+            );
+        }
+    }
+
+    /// When the undo journal [`File`] `path` can't be written because of
+    /// `error`, which stops the sort.
+    pub struct JournalFailedError <'a> {
+        pub path: &'a File,
+        pub error: &'a str,
+    }
+    impl <'a> JournalFailedError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} failed to write the undo journal {}: {}; stopping.",
+                format!("Error:").red(),
+                self.path.to_string(),
+                self.error
+            );
+        }
+    }
+
+    /// When the undo journal [`File`] `path` can't be read because of `error`.
+    pub struct JournalReadFailedError <'a> {
+        pub path: &'a File,
+        pub error: &'a str,
+    }
+    impl <'a> JournalReadFailedError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} failed to read the undo journal {}: {}.",
+                format!("Error:").red(),
+                self.path.to_string(),
+                self.error
+            );
+        }
+    }
+
+    /// When a path that is about to be moved back by `sortery undo` has been
+    /// changed since it was sorted. `path` is the modified [`File`].
+    pub struct PathModifiedError <'a> {
+        pub path: &'a File,
+    }
+    impl <'a> PathModifiedError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} {} was modified since it was sorted; skipping. Use --force to undo it anyway.",
+                format!("Error:").red(),
+                self.path.to_string()
+            );
+        }
+    }
+
+    /// When a path can't be moved because [`File`] `target` is already taken.
+    /// `source` is the path that should have been moved there.
+    pub struct PathExistsError <'a> {
+        pub source: &'a File,
+        pub target: &'a File,
+    }
+    impl <'a> PathExistsError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} can't move {} to {}: path already exists; skipping.",
+                format!("Error:").red(),
+                self.source.to_string(),
+                self.target.to_string()
+            );
+        }
+    }
+
+    /// When [`File`] `path` can't be watched for new files because of `error`.
+    pub struct WatchFailedError <'a> {
+        pub path: &'a File,
+        pub error: &'a str,
+    }
+    impl <'a> WatchFailedError <'a> {
+
+        /// Return the full, colorized error message as a string.
+        pub fn to_string(&self) -> String {
+
+            return format!(
+                "{} failed to watch {} for new files: {}.",
+                format!("Error:").red(),
+                self.path.to_string(),
+                self.error
+            );
+        }
+    }
+}
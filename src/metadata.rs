@@ -0,0 +1,737 @@
+//! Readers for the dates stored inside files and in their names, as opposed to
+//! the dates kept by the filesystem.
+
+/// Reading capture dates and camera models from the EXIF metadata of JPEG and
+/// TIFF (including DNG and most other TIFF-based raw formats) images.
+pub mod exif {
+
+    use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone};
+    use crate::structs::File;
+    use std::{fs, io::{Read, Seek, SeekFrom}};
+
+    #[cfg(test)]
+    mod tests {
+        /// Tests for exif. Each test function is named after the function in
+        /// exif it tests, with the test_ prefix.
+
+        use std::io::Cursor;
+        use super::*;
+
+        /// Return a little-endian TIFF block with an EXIF sub-IFD containing
+        /// `tags`, each a tuple of (tag, ASCII value).
+        fn tiff_block(tags: &[(u16, &str)]) -> Vec<u8> {
+
+            // The header, and IFD0 with only the EXIF IFD pointer
+            let mut data: Vec<u8> = vec![b'I', b'I', 42, 0, 8, 0, 0, 0];
+            data.extend_from_slice(&1u16.to_le_bytes());
+            data.extend_from_slice(&TAG_EXIF_IFD.to_le_bytes());
+            data.extend_from_slice(&4u16.to_le_bytes());
+            data.extend_from_slice(&1u32.to_le_bytes());
+            data.extend_from_slice(&26u32.to_le_bytes());
+            data.extend_from_slice(&0u32.to_le_bytes());
+
+            // The EXIF IFD, with the values stored after it
+            let mut values_offset = 26 + 2 + 12 * tags.len() as u32 + 4;
+            let mut values: Vec<u8> = Vec::new();
+            data.extend_from_slice(&(tags.len() as u16).to_le_bytes());
+            for (tag, value) in tags {
+                let mut value = value.as_bytes().to_vec();
+                value.push(0);
+                data.extend_from_slice(&tag.to_le_bytes());
+                data.extend_from_slice(&2u16.to_le_bytes());
+                data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                data.extend_from_slice(&values_offset.to_le_bytes());
+                values_offset += value.len() as u32;
+                values.extend(value);
+            }
+            data.extend_from_slice(&0u32.to_le_bytes());
+            data.extend(values);
+            data
+        }
+
+        /// Return a JPEG containing `tiff` in an APP1 segment, after an APP0 segment.
+        fn jpeg(tiff: &[u8]) -> Vec<u8> {
+            let mut data: Vec<u8> = vec![0xFF, 0xD8, 0xFF, 0xE0, 0, 4, 0, 0, 0xFF, 0xE1];
+            data.extend_from_slice(&(tiff.len() as u16 + 8).to_be_bytes());
+            data.extend_from_slice(b"Exif\0\0");
+            data.extend_from_slice(tiff);
+            data.extend_from_slice(&[0xFF, 0xDA, 0, 2]);
+            data
+        }
+
+        #[test]
+        fn test_read_datetime() {
+
+            // DateTimeOriginal is preferred over CreateDate, in the local time zone
+            let tiff = tiff_block(&[(TAG_CREATE_DATE, "2020:01:01 00:00:00"), (TAG_DATE_TIME_ORIGINAL, "2021:02:03 14:22:33")]);
+            let expected = Local.from_local_datetime(
+                &NaiveDateTime::parse_from_str("2021:02:03 14:22:33", EXIF_DATE_FORMAT).unwrap()
+            ).unwrap();
+            assert_eq!(read_datetime(&mut Cursor::new(jpeg(&tiff))), Some(expected));
+            assert_eq!(read_datetime(&mut Cursor::new(tiff)), Some(expected));
+
+            // OffsetTimeOriginal is honoured
+            let tiff = tiff_block(&[(TAG_DATE_TIME_ORIGINAL, "2021:02:03 14:22:33"), (TAG_OFFSET_TIME_ORIGINAL, "+02:00")]);
+            let expected = FixedOffset::east(2 * 3600).ymd(2021, 2, 3).and_hms(14, 22, 33);
+            assert_eq!(read_datetime(&mut Cursor::new(jpeg(&tiff))), Some(expected.with_timezone(&Local)));
+
+            // CreateDate is used when there is no DateTimeOriginal
+            let tiff = tiff_block(&[(TAG_CREATE_DATE, "2020:01:01 00:00:00")]);
+            assert!(read_datetime(&mut Cursor::new(tiff)).is_some());
+
+            // Missing, blank, and non-EXIF data
+            assert_eq!(read_datetime(&mut Cursor::new(tiff_block(&[]))), None);
+            assert_eq!(read_datetime(&mut Cursor::new(tiff_block(&[(TAG_DATE_TIME_ORIGINAL, "    :  :     :  :  ")]))), None);
+            assert_eq!(read_datetime(&mut Cursor::new(b"not an image".to_vec())), None);
+        }
+
+        #[test]
+        fn test_read_model() {
+
+            // A big-endian TIFF block with only the model in IFD0
+            let mut data: Vec<u8> = vec![b'M', b'M', 0, 42, 0, 0, 0, 8, 0, 1];
+            data.extend_from_slice(&TAG_MODEL.to_be_bytes());
+            data.extend_from_slice(&2u16.to_be_bytes());
+            data.extend_from_slice(&10u32.to_be_bytes());
+            data.extend_from_slice(&26u32.to_be_bytes());
+            data.extend_from_slice(&0u32.to_be_bytes());
+            data.extend_from_slice(b"Pixel 5  \0");
+            assert_eq!(read_model(&mut Cursor::new(data)), Some(String::from("Pixel 5")));
+            assert_eq!(read_model(&mut Cursor::new(tiff_block(&[]))), None);
+        }
+
+        #[test]
+        fn test_parse_offset() {
+            assert_eq!(parse_offset("+02:00"), Some(FixedOffset::east(7200)));
+            assert_eq!(parse_offset("-05:30"), Some(FixedOffset::west(19800)));
+            assert_eq!(parse_offset("Z"), None);
+            assert_eq!(parse_offset("+é:00"), None);
+            assert_eq!(parse_offset("+0é00"), None);
+            assert_eq!(parse_offset("+-1:00"), None);
+            assert_eq!(parse_offset("+99:00"), None);
+        }
+    }
+
+    /// The format of EXIF date strings.
+    const EXIF_DATE_FORMAT: &str = "%Y:%m:%d %H:%M:%S";
+
+    /// The tag of the camera model, in IFD0.
+    const TAG_MODEL: u16 = 0x0110;
+
+    /// The tag of the pointer to the EXIF sub-IFD.
+    const TAG_EXIF_IFD: u16 = 0x8769;
+
+    /// The tag of the date and time the image was captured.
+    const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+    /// The tag of the date and time the image was digitized, also known as CreateDate.
+    const TAG_CREATE_DATE: u16 = 0x9004;
+
+    /// The tag of the UTC offset of [`TAG_DATE_TIME_ORIGINAL`].
+    const TAG_OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+
+    /// The tag of the UTC offset of [`TAG_CREATE_DATE`].
+    const TAG_OFFSET_TIME_DIGITIZED: u16 = 0x9012;
+
+    /// An IFD entry, as a tuple of (tag, type, count, offset of the value field).
+    type IfdEntry = (u16, u16, u32, u64);
+
+    /// A TIFF block inside `reader`, starting at `base`. All offsets in the
+    /// block are relative to `base`.
+    struct Tiff<'a, R: Read + Seek> {
+        reader: &'a mut R,
+        base: u64,
+        little_endian: bool,
+    }
+    impl <'a, R: Read + Seek> Tiff<'a, R> {
+
+        /// Return the TIFF block starting at `base`, or [`None`] if there is no
+        /// valid TIFF header there.
+        fn new(reader: &'a mut R, base: u64) -> Option<Tiff<'a, R>> {
+            let mut header = [0u8; 4];
+            reader.seek(SeekFrom::Start(base)).ok()?;
+            reader.read_exact(&mut header).ok()?;
+            let little_endian = match header {
+                [b'I', b'I', 42, 0] => true,
+                [b'M', b'M', 0, 42] => false,
+                _ => return None,
+            };
+            Some(Tiff { reader, base, little_endian })
+        }
+
+        /// Read `buffer.len()` bytes at `offset`.
+        fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Option<()> {
+            self.reader.seek(SeekFrom::Start(self.base + offset)).ok()?;
+            self.reader.read_exact(buffer).ok()
+        }
+
+        /// Read a [`u16`] at `offset`.
+        fn read_u16(&mut self, offset: u64) -> Option<u16> {
+            let mut buffer = [0u8; 2];
+            self.read_at(offset, &mut buffer)?;
+            Some(if self.little_endian { u16::from_le_bytes(buffer) } else { u16::from_be_bytes(buffer) })
+        }
+
+        /// Read a [`u32`] at `offset`.
+        fn read_u32(&mut self, offset: u64) -> Option<u32> {
+            let mut buffer = [0u8; 4];
+            self.read_at(offset, &mut buffer)?;
+            Some(if self.little_endian { u32::from_le_bytes(buffer) } else { u32::from_be_bytes(buffer) })
+        }
+
+        /// Return the entries of the IFD at `offset`.
+        fn read_ifd(&mut self, offset: u64) -> Option<Vec<IfdEntry>> {
+            let count = self.read_u16(offset)?;
+            let mut entries = Vec::new();
+            for i in 0..count as u64 {
+                let entry = offset + 2 + i * 12;
+                entries.push((
+                    self.read_u16(entry)?,
+                    self.read_u16(entry + 2)?,
+                    self.read_u32(entry + 4)?,
+                    entry + 8,
+                ));
+            }
+            Some(entries)
+        }
+
+        /// Return the ASCII value of an IFD entry, without the trailing NUL.
+        fn read_ascii(&mut self, count: u32, value_field: u64) -> Option<String> {
+
+            // Values longer than four bytes are stored elsewhere
+            let offset = if count > 4 { self.read_u32(value_field)? as u64 } else { value_field };
+            let mut buffer = vec![0u8; count.min(64) as usize];
+            self.read_at(offset, &mut buffer)?;
+            let end = buffer.iter().position(|byte| *byte == 0).unwrap_or(buffer.len());
+            String::from_utf8(buffer[..end].to_vec()).ok()
+        }
+
+        /// Return the ASCII value of `tag` in the IFD at `offset`, if there is one.
+        fn find_ascii(&mut self, entries: &[IfdEntry], tag: u16) -> Option<String> {
+            let (_, kind, count, value_field) = *entries.iter().find(|entry| entry.0 == tag)?;
+            if kind != 2 { return None }
+            self.read_ascii(count, value_field)
+        }
+    }
+
+    /// Return the UTC offset represented by an EXIF offset string, as in `"+02:00"`.
+    fn parse_offset(offset: &str) -> Option<FixedOffset> {
+        let offset = offset.trim().as_bytes();
+        if offset.len() != 6 || offset[3] != b':' { return None }
+        if !offset[1..3].iter().chain(&offset[4..6]).all(u8::is_ascii_digit) { return None }
+        let digits = |i: usize| (offset[i] - b'0') as i32 * 10 + (offset[i + 1] - b'0') as i32;
+        let secs = digits(1) * 3600 + digits(4) * 60;
+        match offset[0] {
+            b'+' => FixedOffset::east_opt(secs),
+            b'-' => FixedOffset::west_opt(secs),
+            _ => None,
+        }
+    }
+
+    /// Return the date from the EXIF date string `date`, in the time zone given
+    /// by the EXIF offset string `offset`, or the local time zone if there is
+    /// no offset.
+    fn parse_datetime(date: &str, offset: Option<String>) -> Option<DateTime<Local>> {
+        let naive = NaiveDateTime::parse_from_str(date.trim(), EXIF_DATE_FORMAT).ok()?;
+        match offset.as_deref().and_then(parse_offset) {
+            Some(offset) => Some(offset.from_local_datetime(&naive).single()?.with_timezone(&Local)),
+            None => Local.from_local_datetime(&naive).earliest(),
+        }
+    }
+
+    /// Return the offset of the TIFF block in the EXIF APP1 segment of the
+    /// JPEG in `reader`, or [`None`] if there is no EXIF segment.
+    fn find_jpeg_tiff<R: Read + Seek>(reader: &mut R) -> Option<u64> {
+        let mut offset: u64 = 2;
+        loop {
+
+            // Each segment starts with a marker and its length
+            let mut marker = [0u8; 4];
+            reader.seek(SeekFrom::Start(offset)).ok()?;
+            reader.read_exact(&mut marker).ok()?;
+            if marker[0] != 0xFF { return None }
+            let length = u16::from_be_bytes([marker[2], marker[3]]) as u64;
+
+            // The metadata all comes before the start of the image data
+            if marker[1] == 0xDA || marker[1] == 0xD9 { return None }
+            if marker[1] == 0xE1 {
+                let mut header = [0u8; 6];
+                reader.read_exact(&mut header).ok()?;
+                if &header == b"Exif\0\0" {
+                    return Some(offset + 10);
+                }
+            }
+            offset += 2 + length;
+        }
+    }
+
+    /// Return the TIFF block of the JPEG or TIFF image in `reader`, and the
+    /// entries of its IFD0.
+    fn read_ifd0<R: Read + Seek>(reader: &mut R) -> Option<(Tiff<'_, R>, Vec<IfdEntry>)> {
+        let mut magic = [0u8; 2];
+        reader.seek(SeekFrom::Start(0)).ok()?;
+        reader.read_exact(&mut magic).ok()?;
+        let base = if magic == [0xFF, 0xD8] { find_jpeg_tiff(reader)? } else { 0 };
+        let mut tiff = Tiff::new(reader, base)?;
+        let ifd0_offset = tiff.read_u32(4)? as u64;
+        let ifd0 = tiff.read_ifd(ifd0_offset)?;
+        Some((tiff, ifd0))
+    }
+
+    /// Return the capture date of the JPEG or TIFF image in `reader`. This is
+    /// DateTimeOriginal, or CreateDate if there is no DateTimeOriginal, in the
+    /// time zone given by their offset tag if there is one.
+    fn read_datetime<R: Read + Seek>(reader: &mut R) -> Option<DateTime<Local>> {
+
+        // Find the EXIF sub-IFD through IFD0
+        let (mut tiff, ifd0) = read_ifd0(reader)?;
+        let (_, _, _, pointer) = *ifd0.iter().find(|entry| entry.0 == TAG_EXIF_IFD)?;
+        let exif_offset = tiff.read_u32(pointer)? as u64;
+        let exif = tiff.read_ifd(exif_offset)?;
+
+        // Read the dates, in order of preference
+        for (date_tag, offset_tag) in [
+            (TAG_DATE_TIME_ORIGINAL, TAG_OFFSET_TIME_ORIGINAL),
+            (TAG_CREATE_DATE, TAG_OFFSET_TIME_DIGITIZED)].iter() {
+            if let Some(date) = tiff.find_ascii(&exif, *date_tag) {
+                let offset = tiff.find_ascii(&exif, *offset_tag);
+                if let Some(datetime) = parse_datetime(&date, offset) {
+                    return Some(datetime);
+                }
+            }
+        }
+        None
+    }
+
+    /// Return the camera model of the JPEG or TIFF image in `reader`, without
+    /// surrounding whitespace.
+    fn read_model<R: Read + Seek>(reader: &mut R) -> Option<String> {
+        let (mut tiff, ifd0) = read_ifd0(reader)?;
+        let model = tiff.find_ascii(&ifd0, TAG_MODEL)?;
+        let model = model.trim();
+        if model.is_empty() { None } else { Some(String::from(model)) }
+    }
+
+    /// Return the capture date of `path` from its EXIF metadata, or [`None`]
+    /// if `path` isn't a JPEG or TIFF image, or has no capture date.
+    pub fn get_datetime(path: &File) -> Option<DateTime<Local>> {
+        let mut file = fs::File::open(&path.pathbuf).ok()?;
+        read_datetime(&mut file)
+    }
+
+    /// Return the model of the camera that took `path` from its EXIF metadata,
+    /// or [`None`] if `path` isn't a JPEG or TIFF image, or has no camera model.
+    pub fn get_camera_model(path: &File) -> Option<String> {
+        let mut file = fs::File::open(&path.pathbuf).ok()?;
+        read_model(&mut file)
+    }
+}
+
+/// Reading recording dates from MP4, MOV and other QuickTime (ISO base media)
+/// containers.
+pub mod quicktime {
+
+    use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+    use crate::structs::File;
+    use std::{convert::{TryFrom, TryInto}, fs, io::{Read, Seek, SeekFrom}};
+
+    #[cfg(test)]
+    mod tests {
+        /// Tests for quicktime. Each test function is named after the function
+        /// in quicktime it tests, with the test_ prefix.
+
+        use std::io::Cursor;
+        use super::*;
+
+        /// Return a box of type `kind` containing `content`.
+        fn make_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+            let mut data = (content.len() as u32 + 8).to_be_bytes().to_vec();
+            data.extend_from_slice(kind);
+            data.extend_from_slice(content);
+            data
+        }
+
+        /// Return a version 0 `mvhd` box with `creation_time`.
+        fn mvhd(creation_time: u32) -> Vec<u8> {
+            let mut content = vec![0u8; 4];
+            content.extend_from_slice(&creation_time.to_be_bytes());
+            content.extend_from_slice(&[0u8; 92]);
+            make_box(b"mvhd", &content)
+        }
+
+        /// Return a QuickTime `meta` box with a single string `key` set to `value`.
+        fn meta(key: &str, value: &str) -> Vec<u8> {
+            let mut keys = vec![0u8; 4];
+            keys.extend_from_slice(&1u32.to_be_bytes());
+            keys.extend(make_box(b"mdta", key.as_bytes()));
+            let mut data = 1u32.to_be_bytes().to_vec();
+            data.extend_from_slice(&[0u8; 4]);
+            data.extend_from_slice(value.as_bytes());
+            let item = make_box(&1u32.to_be_bytes(), &make_box(b"data", &data));
+            let mut content = make_box(b"hdlr", &[0u8; 24]);
+            content.extend(make_box(b"keys", &keys));
+            content.extend(make_box(b"ilst", &item));
+            make_box(b"meta", &content)
+        }
+
+        /// Return a file with an `ftyp` box and a `moov` box containing `boxes`.
+        fn movie(boxes: &[Vec<u8>]) -> Vec<u8> {
+            let mut data = make_box(b"ftyp", b"qt  \0\0\0\0");
+            data.extend(make_box(b"moov", &boxes.concat()));
+            data
+        }
+
+        #[test]
+        fn test_read_datetime() {
+
+            // The movie header creation time, in seconds since 1904
+            let expected = Utc.ymd(2021, 2, 3).and_hms(14, 22, 33).with_timezone(&Local);
+            let secs = (expected.timestamp() + MAC_EPOCH_OFFSET) as u32;
+            assert_eq!(read_datetime(&mut Cursor::new(movie(&[mvhd(secs)]))), Some(expected));
+            assert_eq!(read_datetime(&mut Cursor::new(movie(&[mvhd(0)]))), None);
+
+            // The Apple creation date key is preferred
+            let apple = meta(APPLE_CREATION_DATE_KEY, "2021-02-03T15:22:33+0100");
+            assert_eq!(read_datetime(&mut Cursor::new(movie(&[mvhd(1), apple]))), Some(expected));
+
+            // The user data date is used when there is no Apple creation date
+            let mut day = 22u16.to_be_bytes().to_vec();
+            day.extend_from_slice(&[0u8; 2]);
+            day.extend_from_slice(b"2021-02-03T14:22:33Z");
+            let udta = make_box(b"udta", &make_box(b"\xA9day", &day));
+            assert_eq!(read_datetime(&mut Cursor::new(movie(&[mvhd(1), udta]))), Some(expected));
+
+            // Files that aren't movies
+            assert_eq!(read_datetime(&mut Cursor::new(b"not a movie".to_vec())), None);
+
+            // Truncated and crafted movies have no date, rather than crashing
+            assert_eq!(read_datetime(&mut Cursor::new(movie(&[make_box(b"mvhd", &[0u8; 6])]))), None);
+            let mut huge = vec![1u8, 0, 0, 0];
+            huge.extend_from_slice(&u64::MAX.to_be_bytes());
+            assert_eq!(read_datetime(&mut Cursor::new(movie(&[make_box(b"mvhd", &huge)]))), None);
+            huge[4..12].copy_from_slice(&(i64::MAX as u64).to_be_bytes());
+            assert_eq!(read_datetime(&mut Cursor::new(movie(&[make_box(b"mvhd", &huge)]))), None);
+            let mut large = movie(&[]);
+            large.extend_from_slice(&1u32.to_be_bytes());
+            large.extend_from_slice(b"moov");
+            large.extend_from_slice(&u64::MAX.to_be_bytes());
+            assert_eq!(read_datetime(&mut Cursor::new(large)), None);
+        }
+
+        #[test]
+        fn test_parse_date() {
+            let expected = Utc.ymd(2021, 2, 3).and_hms(14, 22, 33).with_timezone(&Local);
+            assert_eq!(parse_date("2021-02-03T15:22:33+0100"), Some(expected));
+            assert_eq!(parse_date("2021-02-03T15:22:33+01:00"), Some(expected));
+            assert_eq!(parse_date("2021-02-03T14:22:33Z"), Some(expected));
+            assert!(parse_date("2021-02-03").is_some());
+            assert_eq!(parse_date("yesterday"), None);
+        }
+    }
+
+    /// The number of seconds between 1904-01-01 (the QuickTime epoch) and the
+    /// UNIX epoch.
+    const MAC_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+    /// The metadata key Apple devices store the recording date under.
+    const APPLE_CREATION_DATE_KEY: &str = "com.apple.quicktime.creationdate";
+
+    /// A box in the file, with its type and the offsets of the start of its
+    /// content and its end.
+    struct MediaBox {
+        kind: [u8; 4],
+        start: u64,
+        end: u64,
+    }
+
+    /// Return the boxes between `start` and `end` in `reader`.
+    fn read_boxes<R: Read + Seek>(reader: &mut R, start: u64, end: u64) -> Vec<MediaBox> {
+        let mut boxes = Vec::new();
+        let mut offset = start;
+        while offset + 8 <= end {
+
+            // The box header: its size and type, and possibly a 64-bit size
+            let mut header = [0u8; 8];
+            if reader.seek(SeekFrom::Start(offset)).is_err() || reader.read_exact(&mut header).is_err() {
+                break;
+            }
+            let mut kind = [0u8; 4];
+            kind.copy_from_slice(&header[4..]);
+            let (size, header_size) = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+                0 => (end - offset, 8),
+                1 => {
+                    let mut large_size = [0u8; 8];
+                    if reader.read_exact(&mut large_size).is_err() { break }
+                    (u64::from_be_bytes(large_size), 16)
+                },
+                size => (size as u64, 8),
+            };
+            if size < header_size || offset.checked_add(size).is_none_or(|box_end| box_end > end) { break }
+
+            boxes.push(MediaBox { kind, start: offset + header_size, end: offset + size });
+            offset += size;
+        }
+        boxes
+    }
+
+    /// Return the contents of `media_box`, up to `limit` bytes.
+    fn read_content<R: Read + Seek>(reader: &mut R, media_box: &MediaBox, limit: u64) -> Option<Vec<u8>> {
+        let mut content = vec![0u8; (media_box.end - media_box.start).min(limit) as usize];
+        reader.seek(SeekFrom::Start(media_box.start)).ok()?;
+        reader.read_exact(&mut content).ok()?;
+        Some(content)
+    }
+
+    /// Return the date represented by `date`, an ISO 8601 date as stored in
+    /// QuickTime metadata, as in `"2021-02-03T14:22:33+0100"`.
+    fn parse_date(date: &str) -> Option<DateTime<Local>> {
+        let date = date.trim().trim_end_matches('\0');
+        for format in ["%Y-%m-%dT%H:%M:%S%z", "%Y-%m-%dT%H:%M:%S%.f%z"].iter() {
+            if let Ok(datetime) = DateTime::parse_from_str(date, format) {
+                return Some(datetime.with_timezone(&Local));
+            }
+        }
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(date) {
+            return Some(datetime.with_timezone(&Local));
+        }
+        let day = NaiveDate::parse_from_str(date.get(..10)?, "%Y-%m-%d").ok()?;
+        Local.from_local_datetime(&day.and_hms(0, 0, 0)).earliest()
+    }
+
+    /// Return the value of `key` in the QuickTime `meta` box `meta`, if it is a
+    /// UTF-8 string.
+    fn read_meta_string<R: Read + Seek>(reader: &mut R, meta: &MediaBox, key: &str) -> Option<String> {
+
+        // ISO meta boxes have a version and flags before their children;
+        // QuickTime ones don't
+        let mut peek = [0u8; 4];
+        reader.seek(SeekFrom::Start(meta.start + 4)).ok()?;
+        reader.read_exact(&mut peek).ok()?;
+        let start = if &peek == b"hdlr" { meta.start } else { meta.start + 4 };
+        let children = read_boxes(reader, start, meta.end);
+
+        // Find the index of the key; the keys are numbered from 1
+        let keys = children.iter().find(|child| &child.kind == b"keys")?;
+        let index = read_boxes(reader, keys.start + 8, keys.end)
+            .iter()
+            .position(|entry| read_content(reader, entry, 256).as_deref() == Some(key.as_bytes()))? as u32 + 1;
+
+        // Find the item with that index, and its value
+        let ilst = children.iter().find(|child| &child.kind == b"ilst")?;
+        let item = read_boxes(reader, ilst.start, ilst.end)
+            .into_iter()
+            .find(|item| item.kind == index.to_be_bytes())?;
+        let data = read_boxes(reader, item.start, item.end)
+            .into_iter()
+            .find(|data| &data.kind == b"data")?;
+        let content = read_content(reader, &data, 264)?;
+        if content.len() < 8 || content[..4] != 1u32.to_be_bytes() { return None }
+        String::from_utf8(content[8..].to_vec()).ok()
+    }
+
+    /// Return the recording date of the movie in `reader`. In order of preference,
+    /// this is the Apple creation date key, the `©day` user data, or the
+    /// creation time of the movie header.
+    fn read_datetime<R: Read + Seek>(reader: &mut R) -> Option<DateTime<Local>> {
+        let length = reader.seek(SeekFrom::End(0)).ok()?;
+        let moov = read_boxes(reader, 0, length).into_iter().find(|media_box| &media_box.kind == b"moov")?;
+        let children = read_boxes(reader, moov.start, moov.end);
+
+        // The metadata, which may be in the movie box or its user data
+        let mut metas: Vec<&MediaBox> = children.iter().filter(|child| &child.kind == b"meta").collect();
+        let udta = children.iter().find(|child| &child.kind == b"udta");
+        let udta_children = match udta {
+            Some(udta) => read_boxes(reader, udta.start, udta.end),
+            None => Vec::new(),
+        };
+        metas.extend(udta_children.iter().filter(|child| &child.kind == b"meta"));
+
+        // The Apple creation date
+        for meta in metas {
+            if let Some(datetime) = read_meta_string(reader, meta, APPLE_CREATION_DATE_KEY).as_deref().and_then(parse_date) {
+                return Some(datetime);
+            }
+        }
+
+        // The user data date, stored after its length and language
+        if let Some(day) = udta_children.iter().find(|child| &child.kind == b"\xA9day") {
+            let content = read_content(reader, day, 68)?;
+            if content.len() > 4 {
+                if let Some(datetime) = std::str::from_utf8(&content[4..]).ok().and_then(parse_date) {
+                    return Some(datetime);
+                }
+            }
+        }
+
+        // The movie header creation time, in seconds since 1904 in UTC
+        let mvhd = children.iter().find(|child| &child.kind == b"mvhd")?;
+        let content = read_content(reader, mvhd, 12)?;
+        let secs = match (content.first()?, content.get(4..8), content.get(4..12)) {
+            (0, Some(secs), _) => u32::from_be_bytes(secs.try_into().ok()?) as i64,
+            (1, _, Some(secs)) => i64::try_from(u64::from_be_bytes(secs.try_into().ok()?)).ok()?,
+            _ => return None,
+        };
+        if secs == 0 { return None }
+        Some(Utc.timestamp_opt(secs.checked_sub(MAC_EPOCH_OFFSET)?, 0).single()?.with_timezone(&Local))
+    }
+
+    /// Return the recording date of `path` from its QuickTime metadata, or
+    /// [`None`] if `path` isn't an MP4 or QuickTime movie, or has no recording date.
+    pub fn get_datetime(path: &File) -> Option<DateTime<Local>> {
+        let mut file = fs::File::open(&path.pathbuf).ok()?;
+        read_datetime(&mut file)
+    }
+}
+
+/// Reading dates embedded in file names, as in `IMG_20210203_142233.jpg`.
+pub mod filename {
+
+    use chrono::{format::{Fixed, Item, Numeric, StrftimeItems}, DateTime, Datelike, Local, NaiveDate, NaiveDateTime, TimeZone};
+    use crate::structs::File;
+
+    #[cfg(test)]
+    mod tests {
+        /// Tests for filename. Each test function is named after the function
+        /// in filename it tests, with the test_ prefix.
+
+        use super::*;
+
+        /// Return the local date and time for the given values.
+        fn local(y: i32, m: u32, d: u32, h: u32, min: u32, s: u32) -> Option<DateTime<Local>> {
+            Some(Local.ymd(y, m, d).and_hms(h, min, s))
+        }
+
+        #[test]
+        fn test_parse_name() {
+            let no_patterns: Vec<String> = Vec::new();
+            assert_eq!(parse_name("IMG_20210203_142233", &no_patterns), local(2021, 2, 3, 14, 22, 33));
+            assert_eq!(parse_name("PXL_20210203_142233123", &no_patterns), local(2021, 2, 3, 14, 22, 33));
+            assert_eq!(parse_name("Screenshot 2021-02-03 at 14.22.33", &no_patterns), local(2021, 2, 3, 14, 22, 33));
+            assert_eq!(parse_name("VID-20210203-WA0001", &no_patterns), local(2021, 2, 3, 0, 0, 0));
+            assert_eq!(parse_name("notes 2021-02-03", &no_patterns), local(2021, 2, 3, 0, 0, 0));
+            assert_eq!(parse_name("holiday", &no_patterns), None);
+            assert_eq!(parse_name("track 12345678", &no_patterns), None);
+
+            // User patterns are tried first
+            let patterns = vec![String::from("%d.%m.%Y")];
+            assert_eq!(parse_name("scan 03.02.2021", &no_patterns), None);
+            assert_eq!(parse_name("scan 03.02.2021", &patterns), local(2021, 2, 3, 0, 0, 0));
+            assert_eq!(parse_name("scan 3.2.2021", &patterns), local(2021, 2, 3, 0, 0, 0));
+        }
+
+        #[test]
+        fn test_match_lengths() {
+            assert_eq!(match_lengths("%Y-%m-%d"), Some((8, 10)));
+            assert_eq!(match_lengths("%Y%m%d_%H%M%S"), Some((10, 15)));
+            assert_eq!(match_lengths("%d %B %Y"), Some((8, 17)));
+            assert_eq!(match_lengths("%Y-%m-%d at %H.%M.%S"), Some((15, 22)));
+            assert_eq!(match_lengths("%Y-%m-%d %Q"), None);
+        }
+    }
+
+    /// The built-in date patterns, in the order they are tried. Patterns with
+    /// times come before the date-only ones, so that the time isn't lost.
+    pub const BUILTIN_PATTERNS: [&str; 14] = [
+        "%Y%m%d_%H%M%S",
+        "%Y%m%d-%H%M%S",
+        "%Y-%m-%d at %H.%M.%S",
+        "%Y-%m-%d %H.%M.%S",
+        "%Y-%m-%d %H-%M-%S",
+        "%Y-%m-%d_%H-%M-%S",
+        "%Y-%m-%d-%H-%M-%S",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d_%H%M%S",
+        "%Y%m%d%H%M%S",
+        "%Y-%m-%d",
+        "%Y_%m_%d",
+        "%Y%m%d",
+    ];
+
+    /// Return the class of `c` used to find where the parts of a name start:
+    /// digits, letters, and everything else.
+    fn char_class(c: char) -> u8 {
+        if c.is_ascii_digit() { 0 } else if c.is_alphabetic() { 1 } else { 2 }
+    }
+
+    /// Return the shortest and longest text in bytes that the chrono-style
+    /// `pattern` can match, or [`None`] if it isn't a valid pattern. Years
+    /// have 4 digits, as the others are ignored anyway.
+    fn match_lengths(pattern: &str) -> Option<(usize, usize)> {
+        let mut lengths = (0, 0);
+        for item in StrftimeItems::new(pattern) {
+            let (min, max) = match item {
+                Item::Literal(text) => (text.len(), text.len()),
+                Item::Space(text) => (0, text.len()),
+                Item::Numeric(Numeric::Year, _) | Item::Numeric(Numeric::IsoYear, _) => (4, 4),
+                Item::Numeric(Numeric::Ordinal, _) => (1, 3),
+                Item::Numeric(Numeric::Nanosecond, _) => (1, 9),
+                Item::Numeric(Numeric::Timestamp, _) => (1, 20),
+                Item::Numeric(_, _) => (1, 2),
+                Item::Fixed(Fixed::ShortMonthName) | Item::Fixed(Fixed::ShortWeekdayName) => (3, 3),
+                Item::Fixed(Fixed::LongMonthName) | Item::Fixed(Fixed::LongWeekdayName) => (3, 9),
+                Item::Fixed(Fixed::LowerAmPm) | Item::Fixed(Fixed::UpperAmPm) => (2, 2),
+                Item::Error => return None,
+                _ => (0, 64),
+            };
+            lengths = (lengths.0 + min, lengths.1 + max);
+        }
+        Some(lengths)
+    }
+
+    /// Return the date in `text` according to the chrono-style `pattern`, if
+    /// all of `text` matches it. Dates outside of 1900-2100 are ignored, as they
+    /// are almost certainly other numbers.
+    fn parse_exact(text: &str, pattern: &str) -> Option<NaiveDateTime> {
+        let datetime = match NaiveDateTime::parse_from_str(text, pattern) {
+            Ok(datetime) => datetime,
+            Err(_) => NaiveDate::parse_from_str(text, pattern).ok()?.and_hms(0, 0, 0),
+        };
+        if datetime.year() < 1900 || datetime.year() > 2100 { return None }
+        Some(datetime)
+    }
+
+    /// Return the first date found in the file stem `stem`, trying each of
+    /// `patterns` and then each of the [`BUILTIN_PATTERNS`] in order.
+    ///
+    /// A pattern can match any part of the stem that starts where a run of
+    /// digits, letters or other characters apart from spaces starts, and is
+    /// as long as the pattern can match, as told by `match_lengths`; the
+    /// longest match is used.
+    pub fn parse_name(stem: &str, patterns: &[String]) -> Option<DateTime<Local>> {
+
+        // The byte offsets where matches can start and end
+        let chars: Vec<(usize, char)> = stem.char_indices().collect();
+        let starts: Vec<usize> = chars.iter()
+            .enumerate()
+            .filter(|(i, (_, c))| !c.is_whitespace() && (*i == 0 || char_class(chars[i - 1].1) != char_class(*c)))
+            .map(|(_, (offset, _))| *offset)
+            .collect();
+        let mut ends: Vec<usize> = chars.iter().skip(1).map(|(offset, _)| *offset).collect();
+        ends.push(stem.len());
+        ends.reverse();
+
+        let builtin = BUILTIN_PATTERNS.iter().copied();
+        for pattern in patterns.iter().map(|pattern| pattern.as_str()).chain(builtin) {
+            let (min, max) = match match_lengths(pattern) {
+                Some(lengths) => lengths,
+                None => continue,
+            };
+            for start in starts.iter() {
+                for end in ends.iter().filter(|end| *end > start && (min..=max).contains(&(*end - *start))) {
+                    if let Some(datetime) = parse_exact(&stem[*start..*end], pattern) {
+                        return Local.from_local_datetime(&datetime).earliest();
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Return the date in the name of `path`, trying each of the chrono-style
+    /// `patterns` and then the [`BUILTIN_PATTERNS`], or [`None`] if there is
+    /// no date in the name.
+    pub fn get_datetime(path: &File, patterns: &[String]) -> Option<DateTime<Local>> {
+        parse_name(&path.file_stem(), patterns)
+    }
+}
@@ -0,0 +1,462 @@
+//! Observers of the sort and extract engines, which are told about everything
+//! the engines do, so that it can be shown on the terminal, in a GUI, or to
+//! another program.
+
+use crate::{
+    messages::{error_messages, ProgressBar},
+    sorter::{PlannedMove, SortPlan},
+    structs::{File, JournalEntry, Operation, SortAction, SortBy},
+};
+use chrono::Local;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{io, io::Write, path::{Path, PathBuf}};
+
+#[cfg(test)]
+mod tests {
+    /// Tests for the observers. Each test is named after the struct it tests,
+    /// prefixed with test.
+
+    use super::*;
+
+    /// A writer whose reader has gone away.
+    struct BrokenPipe;
+    impl Write for BrokenPipe {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "gone"))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "gone"))
+        }
+    }
+
+    #[test]
+    /// Test the [`JsonLinesObserver`] struct
+    fn test_jsonlinesobserver() {
+        let planned = PlannedMove {
+            from: PathBuf::from("source/a.jpg"),
+            to: PathBuf::from("target/2021/a.jpg"),
+            action: SortAction::Move,
+            date_source: Some(String::from("exif")),
+            rule: None,
+            duplicate_of: None,
+            similar_to: None,
+            skip_reason: None,
+            resolved: None,
+        };
+        let progress = Progress { completed: 1, total: 2, bytes: 10, total_bytes: 20 };
+        let mut observer = JsonLinesObserver::new(Vec::new());
+        observer.on_scan_progress(2);
+        observer.on_planned(std::slice::from_ref(&planned));
+        observer.on_moved(&planned, &progress);
+        observer.on_error(&planned, &io::Error::new(io::ErrorKind::NotFound, "gone"));
+        observer.on_complete(&SortSummary {
+            target: PathBuf::from("target"),
+            operation: Operation::Move,
+            sort_by: Some(SortBy::Date),
+            sorted: 1,
+            skipped: 0,
+            taken: 0,
+            duplicates: 0,
+            filtered: 0,
+            ignored: 0,
+            special: 0,
+            failed: 1,
+        });
+
+        let output = String::from_utf8(observer.writer).expect("Failed to read output.");
+        let events: Vec<Value> = output.lines().map(|line| serde_json::from_str(line).expect("Failed to parse line.")).collect();
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[0], json!({"event": "scan", "scanned": 2}));
+        assert_eq!(events[1]["event"], "planned");
+        assert_eq!(events[1]["to"], "target/2021/a.jpg");
+        assert_eq!(events[1]["date_source"], "exif");
+        assert_eq!(events[2]["event"], "moved");
+        assert_eq!(events[2]["action"], "move");
+        assert_eq!(events[2]["progress"], json!({"completed": 1, "total": 2, "bytes": 10, "total_bytes": 20}));
+        assert_eq!(events[3]["event"], "error");
+        assert_eq!(events[3]["error"], "gone");
+        assert_eq!(events[4]["event"], "complete");
+        assert_eq!(events[4]["sort_by"], "date");
+        assert_eq!(events[4]["failed"], 1);
+
+        // Events outside the engines, and events that can't be written
+        let mut observer = JsonLinesObserver::new(Vec::new());
+        observer.on_refreshed(Path::new("target"), 2);
+        observer.on_failed("stopped");
+        let output = String::from_utf8(observer.writer).expect("Failed to read output.");
+        let events: Vec<Value> = output.lines().map(|line| serde_json::from_str(line).expect("Failed to parse line.")).collect();
+        assert_eq!(events, vec![
+            json!({"event": "refreshed", "target": "target", "removed": 2}),
+            json!({"event": "failed", "error": "stopped"}),
+        ]);
+        JsonLinesObserver::new(BrokenPipe).on_failed("stopped");
+    }
+
+    #[test]
+    /// Test the [`LogObserver`] struct
+    fn test_logobserver() {
+        let mut planned = PlannedMove {
+            from: PathBuf::from("source/a.jpg"),
+            to: PathBuf::from("target/2021/a.jpg"),
+            action: SortAction::Rename,
+            date_source: None,
+            rule: None,
+            duplicate_of: None,
+            similar_to: None,
+            skip_reason: None,
+            resolved: None,
+        };
+        let progress = Progress { completed: 1, total: 1, bytes: 10, total_bytes: 10 };
+        let mut observer = LogObserver::new(Vec::new(), Operation::Copy);
+        observer.on_moved(&planned, &progress);
+        planned.action = SortAction::SkipFiltered;
+        planned.skip_reason = Some(String::from("doesn't match ext:png"));
+        observer.on_skipped(&planned, &progress);
+        observer.on_error(&planned, &io::Error::new(io::ErrorKind::NotFound, "gone"));
+
+        let output = String::from_utf8(observer.writer).expect("Failed to read output.");
+        let lines: Vec<&str> = output.lines().map(|line| &line[line.find(']').expect("No timestamp.") + 2..]).collect();
+        assert_eq!(lines, vec![
+            "Copied source/a.jpg to target/2021/a.jpg (renamed, target exists).",
+            "Skipped source/a.jpg (filtered out: doesn't match ext:png).",
+            "Failed to copy source/a.jpg to target/2021/a.jpg: gone.",
+        ]);
+        LogObserver::new(BrokenPipe, Operation::Move).on_moved(&planned, &progress);
+    }
+}
+
+/// How far along the engine is: `completed` out of `total` items are done,
+/// and `bytes` out of `total_bytes` bytes have been moved.
+#[derive(Debug)]
+#[derive(Clone, Copy, PartialEq)]
+#[derive(Serialize)]
+pub struct Progress {
+    pub completed: usize,
+    pub total: usize,
+    pub bytes: u64,
+    pub total_bytes: u64,
+}
+
+/// What the engine did, once it is done: `sorted` items were moved, copied or
+/// linked into `target` as told by `operation`, and `skipped` were left where
+/// they were, of which `taken` because their new path was taken, `filtered`
+/// because they didn't match the filters,
+/// `ignored` because an ignore file ignores them, and `special` because they
+/// are special files or symlinks to leave alone.
+/// `duplicates` of the items were duplicates, and `failed` items couldn't be
+/// moved. `sort_by` is what the items were sorted by, or
+/// [`None`] for extractions.
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+#[derive(Serialize)]
+pub struct SortSummary {
+    pub target: PathBuf,
+    pub operation: Operation,
+    pub sort_by: Option<SortBy>,
+    pub sorted: usize,
+    pub skipped: usize,
+    pub taken: usize,
+    pub duplicates: usize,
+    pub filtered: usize,
+    pub ignored: usize,
+    pub special: usize,
+    pub failed: usize,
+}
+
+/// Something that is told about everything the sort and extract engines do.
+/// Every method does nothing by default, so implementations only need the
+/// ones they care about.
+pub trait SortObserver {
+
+    /// Called while the source is being scanned, with the number of items
+    /// found so far.
+    fn on_scan_progress(&mut self, _scanned: usize) {}
+
+    /// Called once the sort or extraction is planned, with what is going to
+    /// happen to each item, before anything is moved.
+    fn on_planned(&mut self, _moves: &[PlannedMove]) {}
+
+    /// Called while an item is being moved, as its bytes are moved.
+    fn on_progress(&mut self, _progress: &Progress) {}
+
+    /// Called when `planned` has been moved, copied or linked to its new path.
+    fn on_moved(&mut self, _planned: &PlannedMove, _progress: &Progress) {}
+
+    /// Called when `planned` has been left where it was, as told by its action.
+    fn on_skipped(&mut self, _planned: &PlannedMove, _progress: &Progress) {}
+
+    /// Called when `planned` couldn't be moved because of `error`. The engine
+    /// goes on with the next item.
+    fn on_error(&mut self, _planned: &PlannedMove, _error: &io::Error) {}
+
+    /// Called once the engine is done.
+    fn on_complete(&mut self, _summary: &SortSummary) {}
+}
+
+/// A [`SortObserver`] that ignores everything.
+pub struct SilentObserver;
+impl SortObserver for SilentObserver {}
+
+/// A [`SortObserver`] for people at the terminal: it shows the [`ProgressBar`],
+/// prints errors as they happen, and what was done at the end.
+pub struct TerminalObserver {
+    progress_bar: ProgressBar,
+    similar: Vec<String>,
+}
+impl TerminalObserver {
+
+    /// Return a [`TerminalObserver`] for executing `plan`, with the messages
+    /// of the progress bar fitting its operation, and whether it is a sort
+    /// or an extraction.
+    pub fn for_plan(plan: &SortPlan) -> TerminalObserver {
+        TerminalObserver {
+            progress_bar: ProgressBar {
+                completed_message: String::from(match plan.sort_by {
+                    Some(_) => "Done.",
+                    None => "Completed.",
+                }),
+                message: String::from(match (plan.operation, plan.sort_by) {
+                    (Operation::Move, Some(_)) => "Sorting...",
+                    (Operation::Move, None) => "Extracting...",
+                    (Operation::Copy, _) => "Copying...",
+                    _ => "Linking...",
+                }),
+                total: plan.moves.len(),
+                total_bytes: 0,
+            },
+            similar: Vec::new(),
+        }
+    }
+
+    /// Remember that `planned` looks like another image, to tell at the end.
+    fn note_similar(&mut self, planned: &PlannedMove) {
+        if let Some(image) = &planned.similar_to {
+            self.similar.push(format!("{} looks like {}.", planned.to.display(), image.display()));
+        }
+    }
+}
+impl SortObserver for TerminalObserver {
+
+    fn on_progress(&mut self, progress: &Progress) {
+        self.progress_bar.total_bytes = progress.total_bytes;
+        self.progress_bar.set_progress(progress.completed, progress.bytes);
+    }
+
+    fn on_moved(&mut self, planned: &PlannedMove, progress: &Progress) {
+        self.note_similar(planned);
+        self.on_progress(progress);
+    }
+
+    fn on_skipped(&mut self, planned: &PlannedMove, progress: &Progress) {
+        self.note_similar(planned);
+        self.on_progress(progress);
+    }
+
+    fn on_error(&mut self, planned: &PlannedMove, error: &io::Error) {
+        println!(
+            "\n{} ({})",
+            error_messages::PathMoveFailedError {
+                source: &File::from_pathbuf(&planned.from),
+                target: &File::from_pathbuf(&planned.to),
+            }.to_string(),
+            error
+        );
+    }
+
+    fn on_complete(&mut self, summary: &SortSummary) {
+        self.progress_bar.complete();
+        let verb = match (summary.operation, summary.sort_by) {
+            (Operation::Move, Some(_)) => "sorted",
+            (Operation::Move, None) => "moved",
+            (Operation::Copy, _) => "copied",
+            _ => "linked",
+        };
+        match summary.sort_by {
+            Some(sort_by) => println!("Sucessfully {} {} items by {} into {}.", verb, summary.sorted, match sort_by {
+                SortBy::Date => "date",
+                SortBy::Category => "category",
+            }, summary.target.display()),
+            None => println!("Successfully {} {} items to {}.", verb, summary.sorted, summary.target.display()),
+        }
+        if summary.taken > 0 {
+            println!("Skipped {} items whose new path was already taken.", summary.taken);
+        }
+        if summary.duplicates > 0 {
+            println!("Found {} duplicates of files already in {} or sorted.", summary.duplicates, summary.target.display());
+        }
+        if summary.filtered > 0 {
+            println!("Left out {} items that didn't match the filters.", summary.filtered);
+        }
+        if summary.ignored > 0 {
+            println!("Left out {} paths ignored by ignore files.", summary.ignored);
+        }
+        if summary.special > 0 {
+            println!("Left out {} special files and symlinks.", summary.special);
+        }
+        if summary.failed > 0 {
+            println!("Failed to move {} items.", summary.failed);
+        }
+        for line in self.similar.iter() {
+            println!("{}", line);
+        }
+    }
+}
+
+/// A [`SortObserver`] for other programs: it writes every event to `writer` as
+/// a line of json, an object with the name of the event in `"event"`, as in
+/// `{"event": "moved", "from": "a.jpg", "to": "2021/a.jpg", ...}`. The events are
+/// `scan`, with the number of items `scanned`; `planned` for each item, with
+/// the fields of its [`PlannedMove`]; `progress`, with the fields of
+/// [`Progress`]; `moved` and `skipped`, with the fields of the [`PlannedMove`]
+/// and the `progress`; `error`, with the [`PlannedMove`] and the `error`
+/// message; and `complete`, with the fields of the [`SortSummary`]. Outside
+/// the engines, there are also `unlink` and `unlinked` for each link that
+/// refreshing a view is going to remove or removed, with the fields of its
+/// [`JournalEntry`]; `refreshed`, with the `target` and the number of links
+/// `removed`; and `failed`, with the `error` message, for anything that
+/// stops sortery before or while it sorts.
+///
+/// Events that can't be written, as when the program reading them has gone
+/// away, are dropped without stopping the engine.
+pub struct JsonLinesObserver<W: Write> {
+    pub writer: W,
+}
+impl<W: Write> JsonLinesObserver<W> {
+
+    /// Return a [`JsonLinesObserver`] writing to `writer`.
+    pub fn new(writer: W) -> JsonLinesObserver<W> {
+        JsonLinesObserver { writer }
+    }
+
+    /// Write the event called `event`, with the fields of `fields`, which
+    /// should serialize to an object, and then `extra`, as a line.
+    fn write<T: Serialize>(&mut self, event: &str, fields: &T, extra: Value) {
+        let mut line = json!({ "event": event });
+        for fields in [serde_json::to_value(fields).expect("Failed to serialize event."), extra].iter() {
+            if let (Some(line), Some(fields)) = (line.as_object_mut(), fields.as_object()) {
+                line.extend(fields.clone());
+            }
+        }
+        let _ = writeln!(self.writer, "{}", line).and_then(|_| self.writer.flush());
+    }
+
+    /// Tell about the link of `entry` that refreshing a view is going to
+    /// remove if `dry_run` is [`true`], or just removed otherwise.
+    pub fn on_unlinked(&mut self, entry: &JournalEntry, dry_run: bool) {
+        let event = match dry_run {
+            true => "unlink",
+            false => "unlinked",
+        };
+        self.write(event, entry, Value::Null);
+    }
+
+    /// Tell that `removed` links were removed from the view in `target`.
+    pub fn on_refreshed(&mut self, target: &Path, removed: usize) {
+        self.write("refreshed", &json!({ "target": target, "removed": removed }), Value::Null);
+    }
+
+    /// Tell that sortery had to stop because of `error`.
+    pub fn on_failed(&mut self, error: &str) {
+        self.write("failed", &json!({ "error": error }), Value::Null);
+    }
+}
+impl<W: Write> SortObserver for JsonLinesObserver<W> {
+
+    fn on_scan_progress(&mut self, scanned: usize) {
+        self.write("scan", &json!({ "scanned": scanned }), Value::Null);
+    }
+
+    fn on_planned(&mut self, moves: &[PlannedMove]) {
+        for planned in moves.iter() {
+            self.write("planned", planned, Value::Null);
+        }
+    }
+
+    fn on_progress(&mut self, progress: &Progress) {
+        self.write("progress", progress, Value::Null);
+    }
+
+    fn on_moved(&mut self, planned: &PlannedMove, progress: &Progress) {
+        self.write("moved", planned, json!({ "progress": progress }));
+    }
+
+    fn on_skipped(&mut self, planned: &PlannedMove, progress: &Progress) {
+        self.write("skipped", planned, json!({ "progress": progress }));
+    }
+
+    fn on_error(&mut self, planned: &PlannedMove, error: &io::Error) {
+        self.write("error", planned, json!({ "error": error.to_string() }));
+    }
+
+    fn on_complete(&mut self, summary: &SortSummary) {
+        self.write("complete", summary, Value::Null);
+    }
+}
+
+/// A [`SortObserver`] for long-running sorts, like watching a directory: it
+/// writes a timestamped line to `writer` for each item that is moved, left
+/// where it is, or fails to move, as in
+/// `[2021-02-03 14:22:00] Moved a.jpg to 2021/02/a.jpg.` Lines that can't be
+/// written are dropped.
+pub struct LogObserver<W: Write> {
+    pub writer: W,
+    operation: Operation,
+}
+impl<W: Write> LogObserver<W> {
+
+    /// Return a [`LogObserver`] writing to `writer`, for a sort done with `operation`.
+    pub fn new(writer: W, operation: Operation) -> LogObserver<W> {
+        LogObserver { writer, operation }
+    }
+
+    /// Write `message` as a line, after the current time.
+    fn write(&mut self, message: &str) {
+        let _ = writeln!(self.writer, "[{}] {}", Local::now().format("%Y-%m-%d %H:%M:%S"), message).and_then(|_| self.writer.flush());
+    }
+
+    /// Return what is noted about `planned`, as in ` (renamed, target exists)`,
+    /// or an empty string if there is nothing to note.
+    fn note(planned: &PlannedMove) -> String {
+        let note = match (&planned.duplicate_of, &planned.skip_reason) {
+            (Some(original), _) => format!("{} {}", planned.action.description(), original.display()),
+            (None, Some(reason)) => format!("{}: {}", planned.action.description(), reason),
+            (None, None) => String::from(planned.action.description()),
+        };
+        match note.is_empty() {
+            true => note,
+            false => format!(" ({})", note),
+        }
+    }
+}
+impl<W: Write> SortObserver for LogObserver<W> {
+
+    fn on_moved(&mut self, planned: &PlannedMove, _progress: &Progress) {
+        let verb = match self.operation {
+            Operation::Move => "Moved",
+            Operation::Copy => "Copied",
+            _ => "Linked",
+        };
+        let message = format!("{} {} to {}{}.", verb, planned.from.display(), planned.to.display(), LogObserver::<W>::note(planned));
+        self.write(&message);
+    }
+
+    fn on_skipped(&mut self, planned: &PlannedMove, _progress: &Progress) {
+        let verb = match planned.action {
+            SortAction::SkipDuplicate => "Kept",
+            _ => "Skipped",
+        };
+        let message = format!("{} {}{}.", verb, planned.from.display(), LogObserver::<W>::note(planned));
+        self.write(&message);
+    }
+
+    fn on_error(&mut self, planned: &PlannedMove, error: &io::Error) {
+        let verb = match self.operation {
+            Operation::Move => "move",
+            Operation::Copy => "copy",
+            _ => "link",
+        };
+        let message = format!("Failed to {} {} to {}: {}.", verb, planned.from.display(), planned.to.display(), error);
+        self.write(&message);
+    }
+}
@@ -0,0 +1,243 @@
+//! Perceptual hashes of images, used to find near-duplicates: copies of the
+//! same picture that were resized or re-encoded, so their contents differ.
+//! JPEG and PNG images are supported.
+
+use crate::structs::{File, PerceptualHash};
+use std::{fs, io::BufReader};
+
+#[cfg(test)]
+mod tests {
+    /// Tests for perceptual. Each test function is named after the function in
+    /// perceptual it tests, with the test_ prefix.
+
+    use std::env;
+    use super::*;
+
+    /// Return a grayscale gradient image `width` by `height`, brightening to
+    /// the right and the bottom, with a dark square in its top-right quarter.
+    fn gradient(width: usize, height: usize) -> Vec<u8> {
+        let mut pixels = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let value = match x >= width / 2 && y < height / 2 {
+                    true => 10,
+                    false => (x * 100 / width + y * 100 / height) as u8,
+                };
+                pixels.push(value);
+            }
+        }
+        pixels
+    }
+
+    /// Write `pixels`, an RGB image `width` by `height`, to the PNG file `path`.
+    fn write_png(path: &File, width: u32, height: u32, pixels: &[u8]) {
+        let file = fs::File::create(&path.pathbuf).expect("Failed to create file.");
+        let mut encoder = png::Encoder::new(file, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().expect("Failed to write header.");
+        writer.write_image_data(pixels).expect("Failed to write image.");
+    }
+
+    #[test]
+    /// Test the [`resize`] function
+    fn test_resize() {
+        let pixels = [0, 0, 100, 100, 0, 0, 100, 100, 50, 50, 50, 50, 50, 50, 50, 50];
+        assert_eq!(resize(&pixels, 4, 4, 2, 2), vec![0.0, 100.0, 50.0, 50.0]);
+        assert_eq!(resize(&[10, 20], 2, 1, 4, 2), vec![10.0, 10.0, 20.0, 20.0, 10.0, 10.0, 20.0, 20.0]);
+    }
+
+    #[test]
+    /// Test the [`average_hash`] and [`difference_hash`] functions
+    fn test_hashes() {
+        let small = gradient(64, 48);
+        let large = gradient(640, 480);
+        for kind in [PerceptualHash::AHash, PerceptualHash::DHash].iter() {
+            let hash = |pixels: &[u8], width, height| match kind {
+                PerceptualHash::AHash => average_hash(pixels, width, height),
+                PerceptualHash::DHash => difference_hash(pixels, width, height),
+            };
+            assert!(distance(hash(&small, 64, 48), hash(&large, 640, 480)) <= 2);
+            let rotated: Vec<u8> = small.iter().rev().copied().collect();
+            assert!(distance(hash(&small, 64, 48), hash(&rotated, 64, 48)) > 16);
+        }
+
+        // The gradient brightens to the right, except into and across the
+        // dark square in the top half
+        let hash = difference_hash(&large, 640, 480);
+        assert_eq!(hash >> 32, 0b11100000_11100000_11100000_11100000);
+        assert_eq!(hash & 0xFFFF_FFFF, 0xFFFF_FFFF);
+    }
+
+    #[test]
+    /// Test the [`distance`] function
+    fn test_distance() {
+        assert_eq!(distance(0, 0), 0);
+        assert_eq!(distance(0b1011, 0b0001), 2);
+        assert_eq!(distance(0, u64::MAX), 64);
+    }
+
+    #[test]
+    /// Test the [`fingerprint`] function
+    fn test_fingerprint() {
+        let dir = env::temp_dir().join("sortery_test_fingerprint");
+        fs::create_dir_all(&dir).expect("Failed to create dir.");
+        let small = File::from_pathbuf(&dir.join("small.PNG"));
+        let large = File::from_pathbuf(&dir.join("large.png"));
+        let rgb = |pixels: Vec<u8>| -> Vec<u8> { pixels.iter().flat_map(|value| vec![*value; 3]).collect() };
+        write_png(&small, 64, 48, &rgb(gradient(64, 48)));
+        write_png(&large, 320, 240, &rgb(gradient(320, 240)));
+        let text = File::from_pathbuf(&dir.join("text.png"));
+        fs::write(&text.pathbuf, "Not an image").expect("Failed to write file.");
+
+        let small_print = fingerprint(&small, PerceptualHash::DHash).expect("Failed to get fingerprint.");
+        let large_print = fingerprint(&large, PerceptualHash::DHash).expect("Failed to get fingerprint.");
+        assert_eq!(small_print.pixels, 64 * 48);
+        assert_eq!(large_print.pixels, 320 * 240);
+        assert!(distance(small_print.hash, large_print.hash) <= 2);
+        assert!(fingerprint(&text, PerceptualHash::DHash).is_none());
+        assert!(fingerprint(&File::new("Cargo.toml"), PerceptualHash::DHash).is_none());
+
+        fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+    }
+}
+
+/// The size jpeg images are scaled down to while they are decoded, which is
+/// much faster than decoding them at full size. They don't need more detail
+/// than this to be hashed.
+const JPEG_SCALE: u16 = 64;
+
+/// The perceptual hash of an image, along with its resolution.
+#[derive(Debug)]
+#[derive(Clone, Copy, PartialEq)]
+pub struct Fingerprint {
+    pub hash: u64,
+    pub pixels: u64,
+}
+
+/// Return the [`Fingerprint`] of the image `path` using the hash `kind`, or
+/// [`None`] if it isn't a JPEG or PNG image, or can't be decoded.
+pub fn fingerprint(path: &File, kind: PerceptualHash) -> Option<Fingerprint> {
+    let extension = path.extension().to_lowercase();
+    let (pixels, width, height, resolution) = match extension.as_str() {
+        "jpg" | "jpeg" => decode_jpeg(path)?,
+        "png" => decode_png(path)?,
+        _ => return None,
+    };
+    if width == 0 || height == 0 { return None }
+    Some(Fingerprint {
+        hash: match kind {
+            PerceptualHash::AHash => average_hash(&pixels, width, height),
+            PerceptualHash::DHash => difference_hash(&pixels, width, height),
+        },
+        pixels: resolution,
+    })
+}
+
+/// Return the number of bits that differ between the hashes `hash` and `other`.
+pub fn distance(hash: u64, other: u64) -> u32 {
+    (hash ^ other).count_ones()
+}
+
+/// Return the average hash of the grayscale image `pixels`, `width` by
+/// `height`: each bit is set if that part of an 8 by 8 thumbnail of the
+/// image is brighter than the thumbnail's average.
+fn average_hash(pixels: &[u8], width: usize, height: usize) -> u64 {
+    let thumbnail = resize(pixels, width, height, 8, 8);
+    let mean = thumbnail.iter().sum::<f32>() / thumbnail.len() as f32;
+    thumbnail.iter().fold(0, |hash, value| (hash << 1) | (*value > mean) as u64)
+}
+
+/// Return the difference hash of the grayscale image `pixels`, `width` by
+/// `height`: each bit is set if that part of a 9 by 8 thumbnail of the image
+/// is brighter than the part to the left of it.
+fn difference_hash(pixels: &[u8], width: usize, height: usize) -> u64 {
+    let thumbnail = resize(pixels, width, height, 9, 8);
+    let mut hash = 0;
+    for row in thumbnail.chunks(9) {
+        for pair in row.windows(2) {
+            hash = (hash << 1) | (pair[1] > pair[0]) as u64;
+        }
+    }
+    hash
+}
+
+/// Return the grayscale image `pixels`, `width` by `height`, resized to
+/// `new_width` by `new_height` by averaging the pixels that make up each new
+/// pixel. Images smaller than the new size are stretched.
+fn resize(pixels: &[u8], width: usize, height: usize, new_width: usize, new_height: usize) -> Vec<f32> {
+    let span = |i: usize, size: usize, new_size: usize| {
+        let start = i * size / new_size;
+        (start, ((i + 1) * size / new_size).max(start + 1))
+    };
+    let mut resized = Vec::with_capacity(new_width * new_height);
+    for y in 0..new_height {
+        let (top, bottom) = span(y, height, new_height);
+        for x in 0..new_width {
+            let (left, right) = span(x, width, new_width);
+            let mut sum = 0u32;
+            for row in top..bottom {
+                sum += pixels[row * width + left..row * width + right].iter().map(|value| *value as u32).sum::<u32>();
+            }
+            resized.push(sum as f32 / ((bottom - top) * (right - left)) as f32);
+        }
+    }
+    resized
+}
+
+/// Return the brightness of a pixel of color `red`, `green`, `blue`.
+fn luma(red: u8, green: u8, blue: u8) -> u8 {
+    ((red as u32 * 299 + green as u32 * 587 + blue as u32 * 114) / 1000) as u8
+}
+
+/// Decode the jpeg image `path`, scaled down to about [`JPEG_SCALE`]. Return its
+/// grayscale pixels, their width and height, and the number of pixels in the
+/// full-size image.
+fn decode_jpeg(path: &File) -> Option<(Vec<u8>, usize, usize, u64)> {
+    let file = fs::File::open(&path.pathbuf).ok()?;
+    let mut decoder = jpeg_decoder::Decoder::new(BufReader::new(file));
+    decoder.read_info().ok()?;
+    let info = decoder.info()?;
+    let resolution = info.width as u64 * info.height as u64;
+    let (width, height) = decoder.scale(JPEG_SCALE, JPEG_SCALE).ok()?;
+    let data = decoder.decode().ok()?;
+    let pixels: Vec<u8> = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => data,
+        jpeg_decoder::PixelFormat::L16 => data.chunks(2).map(|value| value[0]).collect(),
+        jpeg_decoder::PixelFormat::RGB24 => data.chunks(3).map(|rgb| luma(rgb[0], rgb[1], rgb[2])).collect(),
+        jpeg_decoder::PixelFormat::CMYK32 => data.chunks(4)
+            .map(|cmyk| {
+                let channel = |value: u8| ((255 - value as u32) * (255 - cmyk[3] as u32) / 255) as u8;
+                luma(channel(cmyk[0]), channel(cmyk[1]), channel(cmyk[2]))
+            })
+            .collect(),
+    };
+    match pixels.len() == width as usize * height as usize {
+        true => Some((pixels, width as usize, height as usize, resolution)),
+        false => None,
+    }
+}
+
+/// Decode the png image `path`. Return its grayscale pixels, their width and
+/// height, and the number of pixels.
+fn decode_png(path: &File) -> Option<(Vec<u8>, usize, usize, u64)> {
+    let file = fs::File::open(&path.pathbuf).ok()?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().ok()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).ok()?;
+    data.truncate(info.buffer_size());
+    let pixels: Vec<u8> = match info.color_type {
+        png::ColorType::Grayscale => data,
+        png::ColorType::GrayscaleAlpha => data.chunks(2).map(|value| value[0]).collect(),
+        png::ColorType::Rgb => data.chunks(3).map(|rgb| luma(rgb[0], rgb[1], rgb[2])).collect(),
+        png::ColorType::Rgba => data.chunks(4).map(|rgba| luma(rgba[0], rgba[1], rgba[2])).collect(),
+        png::ColorType::Indexed => return None,
+    };
+    let (width, height) = (info.width as usize, info.height as usize);
+    match pixels.len() == width * height {
+        true => Some((pixels, width, height, width as u64 * height as u64)),
+        false => None,
+    }
+}
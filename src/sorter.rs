@@ -0,0 +1,869 @@
+//! The public sorting API: a [`Sorter`] is configured with a builder, and
+//! plans a sort as a [`SortPlan`], which can be looked over, saved as json and
+//! then executed.
+
+use crate::{
+    filter::{Filter, Limits, PathPattern},
+    messages::{error_messages, DryRunMessage},
+    observer::{Progress, SilentObserver, SortObserver, SortSummary, TerminalObserver},
+    structs::{Categories, ConfigData, ConflictPolicy, Depth, DuplicatePolicy, File, Journal, Operation, Rule, Similarity, SortAction, SortBy, SymlinkPolicy},
+    template::Template,
+    tools::sort::{get_sorting_results, is_valid_date_type, is_valid_format, DEFAULT_DIR_FORMAT},
+    transfer,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::{HashMap, HashSet}, fs, io::{self, ErrorKind}, os::unix, path::{Path, PathBuf}};
+
+#[cfg(test)]
+mod tests {
+    /// Tests for the sorter. Each test is named after the struct it tests,
+    /// prefixed with test.
+
+    use crate::structs::{JOURNAL_FILE_NAME, TRASH_DIR_NAME};
+    use std::{env, fs};
+    use super::*;
+
+    #[test]
+    /// Test the [`Sorter`] struct
+    fn test_sorter() {
+        let sorter = Sorter::new(File::new("testing"), File::new("testing/files"))
+            .date_type("exif,m")
+            .dir_format("")
+            .only_type("jpg-png")
+            .exclude_type("")
+            .filter(Filter::Glob(String::from("files/**")))
+            .exclude_path("**/.git")
+            .on_conflict(ConflictPolicy::Skip)
+            .operation(Operation::Copy);
+        assert_eq!(sorter.date_type, "exif,m");
+        assert_eq!(sorter.date_format, "%Y-%m-%d %Hh%Mm%Ss");
+        assert_eq!(sorter.all_filters(), vec![
+            Filter::Extension(vec![String::from("jpg"), String::from("png")]),
+            Filter::Glob(String::from("files/**")),
+        ]);
+        assert_eq!(sorter.conflict_policy, ConflictPolicy::Skip);
+        assert!(sorter.validate().is_empty());
+
+        // Only sorting some types overrides leaving others out
+        let types = |sorter: Sorter| sorter.all_filters();
+        let sorter = || Sorter::new(File::new("testing"), File::new("testing/files"));
+        assert_eq!(types(sorter().only_type("jpg").exclude_type("png")), vec![Filter::from_types("jpg").unwrap()]);
+        assert_eq!(types(sorter().exclude_type("png").only_type("jpg")), vec![Filter::from_types("jpg").unwrap()]);
+        assert_eq!(types(sorter().exclude_type("png").only_type("")), vec![Filter::Not(Box::new(Filter::from_types("png").unwrap()))]);
+
+        // Every invalid option is reported
+        let errors = Sorter::new(File::new("testing/nothing"), File::new("testing"))
+            .date_format("%Y-%Q")
+            .date_type("exif,q")
+            .name_template("{date")
+            .filter(Filter::Not(Box::new(Filter::NewerThan(String::from("soon")))))
+            .exclude_path("regex:[")
+            .depth(Depth { min_depth: Some(2), max_depth: Some(1) })
+            .similarity(Similarity { distance: 65, ..Similarity::default() })
+            .categories(vec![("Books", "epub"), ("Ebooks", "epub")].into_iter().map(|(name, extension)| (String::from(name), vec![String::from(extension)])).collect())
+            .validate();
+        assert_eq!(errors.len(), 9);
+        assert_eq!(errors[0], SortError::PathDoesNotExist(File::new("testing/nothing")));
+        assert_eq!(errors[1], SortError::InvalidFormat(String::from("%Y-%Q")));
+        assert_eq!(errors[2], SortError::InvalidDateType(String::from("exif,q")));
+        assert!(matches!(errors[3], SortError::InvalidTemplate { .. }));
+        assert_eq!(errors[4], SortError::InvalidFilter {
+            filter: String::from("not newer:soon"),
+            reason: String::from("invalid age \"soon\""),
+        });
+        assert!(matches!(errors[5], SortError::InvalidExcludePath { .. }));
+        assert_eq!(errors[6], SortError::InvalidDepth(String::from("the min depth 2 is more than the max depth 1")));
+        assert_eq!(errors[7], SortError::InvalidDistance(65));
+        assert_eq!(errors[8], SortError::InvalidCategories(String::from("\"epub\" is in both \"Books\" and \"Ebooks\"")));
+        assert!(Sorter::new(File::new("testing/nothing"), File::new("testing")).plan().is_err());
+
+        // Sorting the source into a directory it is in has to be forced
+        let errors = Sorter::new(File::new("testing/files"), File::new("testing/../testing")).validate();
+        assert_eq!(errors, vec![SortError::SourceInTarget { source: File::new("testing/files"), target: File::new("testing/../testing") }]);
+        assert_eq!(Sorter::new(File::new("testing"), File::new("testing")).validate().len(), 1);
+        assert!(Sorter::new(File::new("testing/files"), File::new("testing")).force(true).validate().is_empty());
+        assert!(Sorter::new(File::new("testing"), File::new("testing/files")).validate().is_empty());
+    }
+
+    #[test]
+    /// Test the [`SortPlan`] struct
+    fn test_sortplan() {
+        let dir = env::temp_dir().join("sortery_test_sortplan");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+        }
+        fs::create_dir_all(dir.join("source/files")).expect("Failed to create dir.");
+        fs::create_dir_all(dir.join("target")).expect("Failed to create dir.");
+        fs::write(dir.join("source/a.txt"), "a").expect("Failed to write file.");
+        fs::write(dir.join("source/files/b.txt"), "b").expect("Failed to write file.");
+        fs::write(dir.join("source/files/c.txt"), "a").expect("Failed to write file.");
+        let (source, target) = (File::from_pathbuf(&dir.join("source")), File::from_pathbuf(&dir.join("target")));
+
+        let plan = Sorter::new(source.copy(), target.copy())
+            .date_type("m")
+            .dir_format("")
+            .preserve_name(true)
+            .duplicates(DuplicatePolicy::Skip)
+            .plan()
+            .expect("Failed to plan sort.");
+        assert_eq!(plan.moves.len(), 3);
+        assert_eq!(plan.moves.iter().filter(|planned| planned.action == SortAction::Move).count(), 2);
+        let duplicate = plan.moves.iter().find(|planned| planned.action == SortAction::SkipDuplicate).expect("Failed to find duplicate.");
+        assert_eq!(duplicate.duplicate_of.as_ref().map(|path| path.parent().unwrap().to_path_buf()), Some(target.to_path_buf()));
+        assert_eq!(duplicate.date_source, Some(String::from("m")));
+
+        // The plan can be saved and read back, and executed
+        let read_plan = SortPlan::from_json(&plan.to_json().expect("Failed to write plan.")).expect("Failed to read plan.");
+        assert_eq!(read_plan, plan);
+        read_plan.execute_with(&mut SilentObserver).expect("Failed to sort.");
+        for planned in plan.moves.iter() {
+            assert!(planned.to.exists());
+            assert_eq!(planned.from.exists(), planned.action == SortAction::SkipDuplicate);
+        }
+        assert_eq!(Journal::read(&target).expect("Failed to read journal.").len(), 2);
+
+        // Deleted duplicates are trashed, so that undoing the run brings them back
+        fs::write(dir.join("source/d.txt"), "a").expect("Failed to write file.");
+        let plan = Sorter::new(source.copy(), target.copy())
+            .duplicates(DuplicatePolicy::Delete)
+            .plan()
+            .expect("Failed to plan sort.");
+        let trashed = plan.moves.iter().find(|planned| planned.action == SortAction::TrashDuplicate).expect("Failed to find duplicate.");
+        assert_eq!(trashed.to, target.to_path_buf().join(TRASH_DIR_NAME).join("d.txt"));
+        plan.execute_with(&mut SilentObserver).expect("Failed to sort.");
+        assert!(trashed.to.exists());
+        crate::tools::undo(&target, None, false, false);
+        assert!(dir.join("source/d.txt").exists());
+
+        // Nothing is trashed if the source is left untouched
+        let plan = Sorter::new(source.copy(), target.copy())
+            .duplicates(DuplicatePolicy::Delete)
+            .operation(Operation::Copy)
+            .plan()
+            .expect("Failed to plan sort.");
+        assert!(plan.moves.iter().any(|planned| planned.action == SortAction::SkipDuplicate));
+
+        // A file that can't be recorded in the journal is put back, and the
+        // sort stops there
+        fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+        fs::create_dir_all(dir.join("source")).expect("Failed to create dir.");
+        fs::create_dir_all(dir.join("target").join(JOURNAL_FILE_NAME)).expect("Failed to create dir.");
+        fs::write(dir.join("source/a.txt"), "a").expect("Failed to write file.");
+        fs::write(dir.join("source/b.txt"), "b").expect("Failed to write file.");
+        for operation in [Operation::Move, Operation::Copy].iter() {
+            let plan = Sorter::new(source.copy(), target.copy())
+                .dir_format("")
+                .preserve_name(true)
+                .operation(*operation)
+                .plan()
+                .expect("Failed to plan sort.");
+            assert!(plan.execute_with(&mut SilentObserver).is_err());
+            assert!(dir.join("source/a.txt").exists() && dir.join("source/b.txt").exists());
+            assert!(!dir.join("target/a.txt").exists() && !dir.join("target/b.txt").exists());
+        }
+
+        fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+    }
+}
+
+/// Sorts the files in a source directory into a target directory. Create one
+/// with [`Sorter::new`] or [`Sorter::from_config`], set its options with the
+/// builder methods, and then [`Sorter::plan`] the sort:
+///
+/// ```no_run
+/// use sortery::{filter::Filter, structs::File, Sorter};
+///
+/// let plan = Sorter::new(File::new("Downloads"), File::new("Pictures"))
+///     .date_type("exif,m")
+///     .filter(Filter::parse("ext:jpg,png and not glob:**/thumbnails/**").expect("Invalid filter"))
+///     .plan()
+///     .expect("Invalid options");
+/// println!("{}", plan.to_json().expect("Failed to serialize plan"));
+/// plan.execute().expect("Failed to write the undo journal");
+/// ```
+#[derive(Debug)]
+pub struct Sorter {
+    pub(crate) source: File,
+    pub(crate) target: File,
+    pub(crate) date_format: String,
+    pub(crate) dir_format: String,
+    pub(crate) date_type: String,
+    pub(crate) name_patterns: Vec<String>,
+    pub(crate) name_fallback: String,
+    pub(crate) preserve_name: bool,
+    pub(crate) name_template: String,
+    pub(crate) only_type: Option<Filter>,
+    pub(crate) exclude_type: Option<Filter>,
+    pub(crate) filters: Vec<Filter>,
+    pub(crate) exclude_paths: Vec<String>,
+    pub(crate) gitignore: bool,
+    pub(crate) depth: Depth,
+    pub(crate) one_file_system: bool,
+    pub(crate) follow_symlinks: bool,
+    pub(crate) symlinks: SymlinkPolicy,
+    pub(crate) force: bool,
+    pub(crate) paths: Option<HashSet<PathBuf>>,
+    pub(crate) conflict_policy: ConflictPolicy,
+    pub(crate) sort_by: SortBy,
+    pub(crate) categories: HashMap<String, Vec<String>>,
+    pub(crate) rules: Vec<Rule>,
+    pub(crate) duplicate_policy: DuplicatePolicy,
+    pub(crate) similarity: Similarity,
+    pub(crate) operation: Operation,
+}
+impl Sorter {
+
+    /// Return a [`Sorter`] sorting the files in `source`, and all its
+    /// subdirectories, into `target`, with the default options: moving them
+    /// into year and month directories by their creation date.
+    pub fn new(source: File, target: File) -> Sorter {
+        Sorter {
+            source,
+            target,
+            date_format: String::from("%Y-%m-%d %Hh%Mm%Ss"),
+            dir_format: String::from(DEFAULT_DIR_FORMAT),
+            date_type: String::from("c"),
+            name_patterns: Vec::new(),
+            name_fallback: String::from("m"),
+            preserve_name: false,
+            name_template: String::new(),
+            only_type: None,
+            exclude_type: None,
+            filters: Vec::new(),
+            exclude_paths: Vec::new(),
+            gitignore: false,
+            depth: Depth::default(),
+            one_file_system: false,
+            follow_symlinks: false,
+            symlinks: SymlinkPolicy::default(),
+            force: false,
+            paths: None,
+            conflict_policy: ConflictPolicy::default(),
+            sort_by: SortBy::default(),
+            categories: HashMap::new(),
+            rules: Vec::new(),
+            duplicate_policy: DuplicatePolicy::default(),
+            similarity: Similarity::default(),
+            operation: Operation::default(),
+        }
+    }
+
+    /// Return a [`Sorter`] sorting `source` into `target` with the options in
+    /// `config`. See [`ConfigData`] for more information on json configuration.
+    pub fn from_config(source: File, target: File, config: ConfigData) -> Sorter {
+        let sorter = Sorter::new(source, target)
+            .date_format(&config.date_format)
+            .dir_format(&config.dir_format)
+            .date_type(&config.date_type)
+            .name_patterns(config.name_patterns)
+            .name_fallback(&config.name_fallback)
+            .preserve_name(config.preserve_name)
+            .name_template(&config.name_template)
+            .exclude_type(&config.exclude_type.join("-"))
+            .only_type(&config.only_type.join("-"))
+            .on_conflict(config.on_conflict)
+            .sort_by(config.sort_by)
+            .categories(config.categories)
+            .rules(config.rules)
+            .duplicates(config.duplicates)
+            .similarity(config.similar)
+            .operation(config.operation)
+            .limits(config.limits)
+            .gitignore(config.gitignore)
+            .depth(config.depth)
+            .one_file_system(config.one_file_system)
+            .follow_symlinks(config.follow_symlinks)
+            .symlinks(config.symlinks)
+            .force(config.force);
+        let sorter = config.exclude_paths.iter().fold(sorter, |sorter, pattern| sorter.exclude_path(pattern));
+        match config.filter {
+            Some(filter) => sorter.filter(filter),
+            None => sorter,
+        }
+    }
+
+    /// Set the date *format* to rename the files with. It shares the formatting
+    /// rules with the [`chrono::format::strftime`] module.
+    pub fn date_format(mut self, date_format: &str) -> Sorter {
+        self.date_format = String::from(date_format);
+        self
+    }
+
+    /// Set the format of the directories to sort the files into, like
+    /// `"%Y/%m/"`. It shares the formatting rules with the date format, with
+    /// the extra tokens `{quarter}`, `{half}` and `{week}`, the ISO week, which
+    /// goes with the ISO year `%G`, as in `"%G/W{week}"`. An empty format
+    /// sorts all the files directly into the target. It can't leave the
+    /// target, so it can't be an absolute path or have `..` in it.
+    pub fn dir_format(mut self, dir_format: &str) -> Sorter {
+        self.dir_format = String::from(dir_format);
+        self
+    }
+
+    /// Set the date to sort the files by; one of `"c"` (created), `"a"`
+    /// (accessed), `"m"` modified, `"exif"` (captured, for photos), or `"video"`
+    /// (recorded, for MP4 and QuickTime movies), or `"filename"` (the date in
+    /// the file name, as in `IMG_20210203_142233.jpg`). Note that creation time
+    /// is not available on all filesystems. It can also be a comma-separated
+    /// chain of these, as in `"exif,filename,c,m"`, where each is tried in turn;
+    /// files that none of them have a date for are moved into
+    /// [`crate::tools::sort::UNDATED_DIR_NAME`].
+    pub fn date_type(mut self, date_type: &str) -> Sorter {
+        self.date_type = String::from(date_type);
+        self
+    }
+
+    /// Set the chrono-style patterns, like `"%d.%m.%Y"`, to try before the
+    /// built-in ones when the date type is `"filename"`.
+    pub fn name_patterns(mut self, name_patterns: Vec<String>) -> Sorter {
+        self.name_patterns = name_patterns;
+        self
+    }
+
+    /// Set the date type to use for files with no date in their name when the
+    /// date type is just `"filename"`.
+    pub fn name_fallback(mut self, name_fallback: &str) -> Sorter {
+        self.name_fallback = String::from(name_fallback);
+        self
+    }
+
+    /// If `preserve_name` is [`true`], add the original file name after the
+    /// date, separated by a space. For example, `test.txt` would be renamed to
+    /// `2021-04-21 06h34m02s test.txt`.
+    pub fn preserve_name(mut self, preserve_name: bool) -> Sorter {
+        self.preserve_name = preserve_name;
+        self
+    }
+
+    /// Set a template for the new file names, as in
+    /// `"{date:%Y%m%d}_{stem}_{counter:04}.{ext|lower}"`. See [`crate::template`]
+    /// for the syntax. If it is empty, files are named after the date format
+    /// and [`Sorter::preserve_name`] instead.
+    pub fn name_template(mut self, name_template: &str) -> Sorter {
+        self.name_template = String::from(name_template);
+        self
+    }
+
+    /// Leave the files with the extensions in `exclude_type`, separated by
+    /// `-`, as in `"jpg-png"`, out of the sort. This is a [`Filter::Not`] a
+    /// [`Filter::Extension`]; see [`Sorter::filter`]. It is overridden by
+    /// [`Sorter::only_type`].
+    pub fn exclude_type(mut self, exclude_type: &str) -> Sorter {
+        self.exclude_type = Filter::from_types(exclude_type).map(|filter| Filter::Not(Box::new(filter)));
+        self
+    }
+
+    /// Only sort the files with the extensions in `only_type`, separated by
+    /// `-`, as in `"jpg-png"`. This is a [`Filter::Extension`]; see
+    /// [`Sorter::filter`]. It overrides [`Sorter::exclude_type`].
+    pub fn only_type(mut self, only_type: &str) -> Sorter {
+        self.only_type = Filter::from_types(only_type);
+        self
+    }
+
+    /// Only sort the files that match `filter`. Filters add up, so files have
+    /// to match all of them to be sorted; see [`crate::filter`] for how to
+    /// combine them otherwise.
+    pub fn filter(mut self, filter: Filter) -> Sorter {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Return all the filters the files have to match: the one for
+    /// [`Sorter::only_type`], or else the one for [`Sorter::exclude_type`],
+    /// and then the ones given to [`Sorter::filter`].
+    pub(crate) fn all_filters(&self) -> Vec<Filter> {
+        let type_filter = self.only_type.as_ref().or(self.exclude_type.as_ref());
+        type_filter.into_iter().chain(self.filters.iter()).cloned().collect()
+    }
+
+    /// Leave the paths inside the source that match `pattern` out of the sort,
+    /// along with everything in them if they are directories, which aren't
+    /// even looked into. See [`PathPattern`] for the syntax; this can be
+    /// called more than once to exclude more paths.
+    pub fn exclude_path(mut self, pattern: &str) -> Sorter {
+        self.exclude_paths.push(String::from(pattern));
+        self
+    }
+
+    /// If `gitignore` is [`true`], honour the `.gitignore` files in the source
+    /// as well as the `.sorteryignore` files, which are always honoured; see
+    /// [`crate::ignore`].
+    pub fn gitignore(mut self, gitignore: bool) -> Sorter {
+        self.gitignore = gitignore;
+        self
+    }
+
+    /// Only sort the files within `depth` of the source, as in
+    /// `Depth { min_depth: None, max_depth: Some(1) }` for only the files
+    /// directly in it. The whole source is sorted by default.
+    pub fn depth(mut self, depth: Depth) -> Sorter {
+        self.depth = depth;
+        self
+    }
+
+    /// If `one_file_system` is [`true`], don't look into directories on other
+    /// filesystems than the source, like mount points for network shares.
+    pub fn one_file_system(mut self, one_file_system: bool) -> Sorter {
+        self.one_file_system = one_file_system;
+        self
+    }
+
+    /// If `follow_symlinks` is [`true`], look into the directories that
+    /// symlinks in the source point to. Symlink loops are reported and left out.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Sorter {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Set what to do with the symlinks in the source; see [`SymlinkPolicy`].
+    pub fn symlinks(mut self, symlinks: SymlinkPolicy) -> Sorter {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// If `force` is [`true`], sort even if the source is inside the target,
+    /// or is the target. This isn't allowed by default, since the files that
+    /// were already sorted would be sorted again. A target inside the source
+    /// is always left out of the sort.
+    pub fn force(mut self, force: bool) -> Sorter {
+        self.force = force;
+        self
+    }
+
+    /// Only sort `paths`, and the files in the directories among them, instead
+    /// of the whole source. The paths are inside the source, starting with it
+    /// as the source was given; the other options still apply to them.
+    pub fn paths(mut self, paths: &[PathBuf]) -> Sorter {
+        self.paths = Some(paths.iter().cloned().collect());
+        self
+    }
+
+    /// Only sort the files within `limits` of size and date, adding a filter for
+    /// each limit that is given. The dates are the ones the files are sorted
+    /// by; see [`Sorter::date_type`].
+    pub fn limits(mut self, limits: Limits) -> Sorter {
+        self.filters.extend(limits.filters());
+        self
+    }
+
+    /// Set what to do when a new file name is already taken by a file that
+    /// exists in the target. New file names that collide with each other always
+    /// get sequential names.
+    pub fn on_conflict(mut self, conflict_policy: ConflictPolicy) -> Sorter {
+        self.conflict_policy = conflict_policy;
+        self
+    }
+
+    /// Set whether to sort the files by date, or into the directories of their
+    /// categories (keeping their names), in which case the date and naming
+    /// options are ignored.
+    pub fn sort_by(mut self, sort_by: SortBy) -> Sorter {
+        self.sort_by = sort_by;
+        self
+    }
+
+    /// Set a map of category names to extensions that extends and overrides
+    /// the [`crate::structs::BUILTIN_CATEGORIES`] when sorting by category; see
+    /// [`Categories::new`]. An extension can only be listed for one category.
+    pub fn categories(mut self, categories: HashMap<String, Vec<String>>) -> Sorter {
+        self.categories = categories;
+        self
+    }
+
+    /// Set the routing [`Rule`]s. A file matching a rule is sorted by date into
+    /// the rule's destination, whatever [`Sorter::sort_by`] is; the first rule
+    /// that matches wins. Files that match no rule are sorted as usual.
+    pub fn rules(mut self, rules: Vec<Rule>) -> Sorter {
+        self.rules = rules;
+        self
+    }
+
+    /// Set what to do with files whose contents are identical to a file already
+    /// in the target, or to an earlier file in this sort.
+    pub fn duplicates(mut self, duplicate_policy: DuplicatePolicy) -> Sorter {
+        self.duplicate_policy = duplicate_policy;
+        self
+    }
+
+    /// Set whether and how to look for images that look like another image in
+    /// this sort with a higher resolution. The image with the highest
+    /// resolution is sorted as usual, and the others are either quarantined or
+    /// only reported, depending on the [`Similarity`]'s `action`.
+    pub fn similarity(mut self, similarity: Similarity) -> Sorter {
+        self.similarity = similarity;
+        self
+    }
+
+    /// Set whether to move the files to their new paths, or to copy or link
+    /// them there, leaving the originals untouched. Copies keep the permissions
+    /// and times of the originals.
+    pub fn operation(mut self, operation: Operation) -> Sorter {
+        self.operation = operation;
+        self
+    }
+
+    /// Return everything that is wrong with the options; if it is empty, the
+    /// sort can be planned.
+    pub fn validate(&self) -> Vec<SortError> {
+        let mut errors: Vec<SortError> = Vec::new();
+        for path in [&self.source, &self.target].iter() {
+            if !path.exists() {
+                errors.push(SortError::PathDoesNotExist(path.copy()));
+            }
+        }
+        if !self.force && self.source.is_inside(&self.target) {
+            errors.push(SortError::SourceInTarget { source: self.source.copy(), target: self.target.copy() });
+        }
+        for format in [&self.date_format, &self.dir_format].iter() {
+            if !is_valid_format(format) {
+                errors.push(SortError::InvalidFormat(format.to_string()));
+            }
+        }
+        for date_type in [&self.date_type, &self.name_fallback].iter() {
+            if !is_valid_date_type(date_type) {
+                errors.push(SortError::InvalidDateType(date_type.to_string()));
+            }
+        }
+        if !self.name_template.is_empty() {
+            if let Err(reason) = Template::parse(&self.name_template) {
+                errors.push(SortError::InvalidTemplate { template: self.name_template.clone(), reason });
+            }
+        }
+        for filter in self.filters.iter() {
+            if let Err(reason) = filter.validate() {
+                errors.push(SortError::InvalidFilter { filter: filter.to_string(), reason });
+            }
+        }
+        for pattern in self.exclude_paths.iter() {
+            if let Err(reason) = PathPattern::parse(pattern) {
+                errors.push(SortError::InvalidExcludePath { pattern: pattern.clone(), reason });
+            }
+        }
+        if let Err(reason) = self.depth.validate() {
+            errors.push(SortError::InvalidDepth(reason));
+        }
+        for (i, rule) in self.rules.iter().enumerate() {
+            let reason = match rule.validate() {
+                Err(reason) => Some(reason),
+                Ok(_) if !is_valid_format(&rule.destination) => Some(format!("invalid destination \"{}\"", rule.destination)),
+                Ok(_) if rule.date_type.as_deref().is_some_and(|date_type| !is_valid_date_type(date_type)) => {
+                    Some(format!("invalid date type \"{}\"", rule.date_type.as_deref().unwrap_or("")))
+                },
+                Ok(_) => match rule.name_template.as_deref().map(Template::parse) {
+                    Some(Err(reason)) => Some(reason),
+                    _ => None,
+                },
+            };
+            if let Some(reason) = reason {
+                errors.push(SortError::InvalidRule { rule: rule.display_name(i), reason });
+            }
+        }
+        if self.similarity.distance > Similarity::MAX_DISTANCE {
+            errors.push(SortError::InvalidDistance(self.similarity.distance));
+        }
+        if let Err(reason) = Categories::validate(&self.categories) {
+            errors.push(SortError::InvalidCategories(reason));
+        }
+        errors
+    }
+
+    /// Plan the sort, without moving anything, and return the [`SortPlan`], or
+    /// everything that is wrong with the options, as from [`Sorter::validate`].
+    pub fn plan(&self) -> Result<SortPlan, Vec<SortError>> {
+        self.plan_with(&mut SilentObserver)
+    }
+
+    /// Plan the sort as [`Sorter::plan`] does, telling `observer` about the
+    /// scan and the plan.
+    pub fn plan_with(&self, observer: &mut dyn SortObserver) -> Result<SortPlan, Vec<SortError>> {
+        let errors = self.validate();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let plan = SortPlan {
+            source: self.source.to_path_buf(),
+            target: self.target.to_path_buf(),
+            operation: self.operation,
+            sort_by: Some(self.sort_by),
+            moves: get_sorting_results(self, observer).map_err(|error| vec![error])?,
+        };
+        observer.on_planned(&plan.moves);
+        Ok(plan)
+    }
+}
+
+/// Something wrong with the options of a [`Sorter`].
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum SortError {
+    /// The source or target directory doesn't exist.
+    PathDoesNotExist(File),
+    /// The source is inside the target, or is the target, and the sort isn't forced.
+    SourceInTarget { source: File, target: File },
+    /// A date or directory format isn't a valid strftime format.
+    InvalidFormat(String),
+    /// A date type chain contains a date source that doesn't exist.
+    InvalidDateType(String),
+    /// The name template isn't valid, for `reason`.
+    InvalidTemplate { template: String, reason: String },
+    /// A filter isn't valid, for `reason`.
+    InvalidFilter { filter: String, reason: String },
+    /// A pattern of paths to exclude isn't valid, for `reason`.
+    InvalidExcludePath { pattern: String, reason: String },
+    /// The depths to sort at aren't valid, for the reason given.
+    InvalidDepth(String),
+    /// A routing rule isn't valid, for `reason`.
+    InvalidRule { rule: String, reason: String },
+    /// The categories overriding the built-in ones aren't valid, for the
+    /// reason given.
+    InvalidCategories(String),
+    /// The distance for similar images is more than 64 bits.
+    InvalidDistance(u32),
+}
+impl SortError {
+
+    /// Return the full, colorized error message as a string.
+    pub fn to_string(&self) -> String {
+        match self {
+            SortError::PathDoesNotExist(path) => error_messages::PathDoesNotExistError { path }.to_string(),
+            SortError::SourceInTarget { source, target } => error_messages::SourceInTargetError { source, target }.to_string(),
+            SortError::InvalidFormat(format) => error_messages::InvalidFormatError { format }.to_string(),
+            SortError::InvalidDateType(date_type) => error_messages::InvalidDateTypeError { date_type }.to_string(),
+            SortError::InvalidTemplate { template, reason } => error_messages::InvalidTemplateError { template, reason }.to_string(),
+            SortError::InvalidFilter { filter, reason } => error_messages::InvalidFilterError { filter, reason }.to_string(),
+            SortError::InvalidExcludePath { pattern, reason } => error_messages::InvalidExcludePathError { pattern, reason }.to_string(),
+            SortError::InvalidDepth(reason) => error_messages::InvalidDepthError { reason }.to_string(),
+            SortError::InvalidRule { rule, reason } => error_messages::InvalidRuleError { rule, reason }.to_string(),
+            SortError::InvalidCategories(reason) => error_messages::InvalidCategoriesError { reason }.to_string(),
+            SortError::InvalidDistance(distance) => error_messages::InvalidDistanceError { distance: &distance.to_string() }.to_string(),
+        }
+    }
+}
+
+/// What is going to happen to one file in a [`SortPlan`]: `from` is going to be
+/// moved (or copied, or linked) to `to` as told by `action`. `date_source` is
+/// the date source used for `from` (none when sorting by category), `rule` is the
+/// name of the routing rule it matched, `duplicate_of` is where the file it
+/// duplicates will be, `similar_to` is where the higher-resolution image it
+/// looks like will be, `skip_reason` is why it is left where it is, and
+/// `resolved` is the file that `from`, a symlink, points to, which is copied to
+/// `to` in its place, if there are any.
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub struct PlannedMove {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub action: SortAction,
+    #[serde(default)]
+    pub date_source: Option<String>,
+    #[serde(default)]
+    pub rule: Option<String>,
+    #[serde(default)]
+    pub duplicate_of: Option<PathBuf>,
+    #[serde(default)]
+    pub similar_to: Option<PathBuf>,
+    #[serde(default)]
+    pub skip_reason: Option<String>,
+    #[serde(default)]
+    pub resolved: Option<PathBuf>,
+}
+
+/// A planned sort of `source` into `target`, made by [`Sorter::plan`], or a
+/// planned extraction, made by [`crate::tools::extract`]. `moves` has what is
+/// going to happen to each file, in the order it is going to happen,
+/// `operation` is whether the files are going to be moved, copied or linked,
+/// and `sort_by` is what they are sorted by, or [`None`] for extractions.
+/// Nothing is moved until the plan is executed.
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub struct SortPlan {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub operation: Operation,
+    pub sort_by: Option<SortBy>,
+    pub moves: Vec<PlannedMove>,
+}
+impl SortPlan {
+
+    /// Return the plan as a pretty-printed json [`String`], or the error if
+    /// it can't be, as for paths that aren't valid UTF-8.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Return the plan in the json `json`, as from [`SortPlan::to_json`].
+    pub fn from_json(json: &str) -> serde_json::Result<SortPlan> {
+        serde_json::from_str(json)
+    }
+
+    /// Print the plan, without acutally sorting; this is the dry-run output.
+    /// The paths left out by ignore files aren't printed.
+    pub fn print(&self) {
+        self.print_moves(false);
+    }
+
+    /// Print the plan as [`SortPlan::print`] does, along with the paths left
+    /// out by ignore files and the patterns that ignore them.
+    pub fn print_verbose(&self) {
+        self.print_moves(true);
+    }
+
+    /// Print the planned moves, including the ignored paths if `verbose` is [`true`].
+    fn print_moves(&self, verbose: bool) {
+        for planned in self.moves.iter() {
+            if planned.action == SortAction::SkipIgnored && !verbose { continue }
+            println!("{}", DryRunMessage {
+                from_file: File::from_pathbuf(&planned.from),
+                to_file: File::from_pathbuf(&planned.to),
+                action: planned.action,
+                date_source: planned.date_source.clone(),
+                rule: planned.rule.clone(),
+                operation: self.operation,
+                duplicate_of: planned.duplicate_of.as_ref().map(File::from_pathbuf),
+                similar_to: planned.similar_to.as_ref().map(File::from_pathbuf),
+                skip_reason: planned.skip_reason.clone(),
+                resolved: planned.resolved.as_ref().map(File::from_pathbuf),
+            }.to_string());
+        }
+    }
+
+    /// Sort the files as planned, showing the progress bar, and recording
+    /// every move in the undo journal of the target. Return the summary of
+    /// the sort, or the error if the journal can't be written, in which case
+    /// the file that couldn't be recorded is put back and the sort stops, so
+    /// that nothing is sorted that can't be undone.
+    pub fn execute(&self) -> io::Result<SortSummary> {
+        self.execute_with(&mut TerminalObserver::for_plan(self))
+    }
+
+    /// Sort the files as [`SortPlan::execute`] does, telling `observer` about
+    /// each of them instead of showing the progress bar. Files that can't be
+    /// moved are left where they are, and reported to `observer`.
+    pub fn execute_with(&self, observer: &mut dyn SortObserver) -> io::Result<SortSummary> {
+
+        // The undo journal for this run
+        let journal = Journal::new(&File::from_pathbuf(&self.target));
+
+        // The number of items and bytes moved so far, and what was done
+        let mut progress = Progress {
+            completed: 0,
+            total: self.moves.len(),
+            bytes: 0,
+            total_bytes: self.moves.iter()
+                .filter(|planned| planned.action.moves())
+                .map(|planned| transfer::size_of(planned.resolved.as_ref().unwrap_or(&planned.from)))
+                .sum(),
+        };
+        let mut summary = SortSummary {
+            target: self.target.clone(),
+            operation: self.operation,
+            sort_by: self.sort_by,
+            sorted: 0,
+            skipped: 0,
+            taken: self.moves.iter().filter(|planned| planned.action == SortAction::Skip).count(),
+            duplicates: self.moves.iter().filter(|planned| planned.duplicate_of.is_some()).count(),
+            filtered: self.moves.iter().filter(|planned| planned.action == SortAction::SkipFiltered).count(),
+            ignored: self.moves.iter().filter(|planned| planned.action == SortAction::SkipIgnored).count(),
+            special: self.moves.iter().filter(|planned| planned.action == SortAction::SkipSpecial).count(),
+            failed: 0,
+        };
+
+        for planned in self.moves.iter() {
+
+            // Leave skipped files where they are
+            let action = planned.action;
+            if !action.moves() {
+                progress.completed += 1;
+                summary.skipped += 1;
+                observer.on_skipped(planned, &progress);
+                continue;
+            }
+
+            // The file paths
+            let old_file = planned.from.as_path();
+            let new_file = planned.to.as_path();
+
+            // Create the directory for the file, if it doesn't exist already,
+            // and move, copy or link the file. Duplicates to be hardlinked are
+            // linked to their original instead, and then removed if they are
+            // being moved, so that undoing the move puts the link back.
+            let dir = new_file.parent().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "no parent directory"));
+            let result = dir.and_then(fs::create_dir_all).and_then(|_| match (action, &planned.duplicate_of) {
+                (SortAction::LinkDuplicate, Some(original)) => {
+                    transfer::hard_link(original, new_file)
+                        .and_then(|_| match self.operation.keeps_source() {
+                            true => Ok(()),
+                            false => fs::remove_file(old_file),
+                        })
+                        .map(|_| progress.bytes += transfer::size_of(new_file))
+                        .map(|_| match self.operation {
+                            Operation::Move => (Operation::Move, None),
+                            _ => (Operation::HardLink, None),
+                        })
+                },
+                _ => {
+
+                    // Resolved symlinks are replaced by what they point to,
+                    // which is copied rather than moved, since it can be
+                    // anywhere; the symlink is removed instead, and what it
+                    // points to is recorded, so that undoing puts it back
+                    let (operation, from) = match (&planned.resolved, self.operation) {
+                        (Some(resolved), Operation::Move) => (Operation::Copy, resolved.as_path()),
+                        (Some(resolved), operation) => (operation, resolved.as_path()),
+                        (None, operation) => (operation, old_file),
+                    };
+                    let link = match planned.resolved.is_some() && !self.operation.keeps_source() {
+                        true => Some(fs::read_link(old_file)?),
+                        false => None,
+                    };
+                    transfer::perform(operation, from, new_file, &mut |bytes| {
+                        progress.bytes += bytes;
+                        observer.on_progress(&progress);
+                    })
+                    .and_then(|_| match link {
+                        Some(_) => fs::remove_file(old_file),
+                        None => Ok(()),
+                    })
+                    .map(|_| (self.operation, link))
+                },
+            });
+
+            // Record the move, or report the error
+            progress.completed += 1;
+            match result {
+                Ok((recorded_operation, link)) => {
+                    if let Err(error) = journal.record(&File::from_path(old_file), &File::from_path(new_file), recorded_operation, link.as_deref()) {
+                        let _ = SortPlan::put_back(old_file, new_file, recorded_operation, link.as_deref());
+                        summary.failed += 1;
+                        observer.on_error(planned, &error);
+                        observer.on_complete(&summary);
+                        return Err(error);
+                    }
+                    summary.sorted += 1;
+                    observer.on_moved(planned, &progress);
+                },
+                Err(error) => {
+                    summary.failed += 1;
+                    observer.on_error(planned, &error);
+                },
+            }
+        }
+        observer.on_complete(&summary);
+        Ok(summary)
+    }
+
+    /// Undo sorting `old` to `new` with `operation`, as undoing the journal
+    /// entry for it would, for when the entry can't be written: copies and
+    /// links are removed, and moved files are moved back, or made the symlink
+    /// `link` again if they were what it pointed to.
+    fn put_back(old: &Path, new: &Path, operation: Operation, link: Option<&Path>) -> io::Result<()> {
+        match (operation.keeps_source(), link) {
+            (true, _) => transfer::remove_path(new),
+            (false, Some(link)) => unix::fs::symlink(link, old).and_then(|_| transfer::remove_path(new)),
+            (false, None) => transfer::move_path(new, old, &mut |_| {}),
+        }
+    }
+}
@@ -1,15 +1,19 @@
 //! Commonly-used structs that multiple modules use.
 
+use crate::filter::{Filter, Limits};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
+use std::{collections::{BTreeMap, HashMap}, env, fs, io::{self, Read, Write}, path::{Path, PathBuf}, process, time::UNIX_EPOCH};
 
 #[cfg(test)]
 /// Tests for the structs. Each test is named after the function and/or struct
 /// it tests, prefixed with test.
 mod tests {
 
-    use std::{env, fs, path::Path};
-    use super::{ConfigData, File};
+    use chrono::{Duration, Local, TimeZone};
+    use crate::filter::{Filter, Limits};
+    use std::{collections::HashMap, env, fs, path::Path};
+    use super::{glob_match, glob_match_path, parse_age, parse_date, parse_size, Categories, ConfigData, ConflictPolicy, Depth, DuplicatePolicy, File, Journal, Operation, PerceptualHash, Rule, SimilarPolicy, Similarity, SortBy, SymlinkPolicy, OTHER_CATEGORY};
     
     #[test]
     /// Test the [`ConfigData`] struct
@@ -23,13 +27,232 @@ mod tests {
         // Create the ConfigData instance and test it's fields
         let config_data = ConfigData::from_json(&json_string);
         assert_eq!(config_data.date_format, String::from("%Y-%m-%d %Hh%Mm%Ss"));
+        assert_eq!(config_data.dir_format, String::from("%Y/%m/"));
         assert_eq!(config_data.date_type, String::from("m"));
+        assert_eq!(config_data.name_patterns, vec![String::from("%d.%m.%Y")]);
+        assert_eq!(config_data.name_fallback, String::from("m"));
         assert_eq!(config_data.exclude_type.len(), 1);
         assert_eq!(config_data.exclude_type[0], String::from("png"));
         assert_eq!(config_data.only_type.len(), 2);
         assert_eq!(config_data.only_type[0], String::from("json"));
         assert_eq!(config_data.only_type[1], String::from("py"));
+        assert_eq!(config_data.filter, None);
+        assert_eq!(config_data.limits, Limits::default());
+        assert!(!config_data.gitignore);
+        assert_eq!(config_data.depth, Depth::default());
+        assert!(!config_data.one_file_system);
+        assert!(!config_data.follow_symlinks);
+        assert_eq!(config_data.symlinks, SymlinkPolicy::Move);
+        assert!(!config_data.force);
+        assert!(config_data.exclude_paths.is_empty());
         assert_eq!(config_data.preserve_name, false);
+        assert_eq!(config_data.name_template, String::from(""));
+        assert_eq!(config_data.on_conflict, ConflictPolicy::Rename);
+        assert_eq!(config_data.duplicates, DuplicatePolicy::Keep);
+        assert_eq!(config_data.similar, Similarity::default());
+        assert_eq!(config_data.sort_by, SortBy::Date);
+        assert!(config_data.categories.is_empty());
+        assert!(config_data.rules.is_empty());
+        assert_eq!(config_data.operation, Operation::Move);
+
+        // The options the template leaves out or at their defaults, from a
+        // config that sets them
+        let json_string = fs::read_to_string(current_dir.join("fixtures/config.json")).expect("Failed to read config.");
+        let config_data = ConfigData::from_json(&json_string);
+        assert_eq!(config_data.operation, Operation::Copy);
+        assert_eq!(config_data.duplicates, DuplicatePolicy::Skip);
+        assert_eq!(config_data.similar, Similarity { action: SimilarPolicy::Report, hash: PerceptualHash::DHash, distance: 6 });
+        assert_eq!(config_data.depth, Depth { min_depth: None, max_depth: Some(8) });
+        assert!(config_data.one_file_system);
+        assert_eq!(config_data.symlinks, SymlinkPolicy::Skip);
+        assert_eq!(config_data.filter, Some(Filter::parse("not glob:**/thumbnails/** and size:1..").unwrap()));
+        assert_eq!(config_data.limits, Limits {
+            max_size: Some(String::from("4 GB")),
+            since: Some(String::from("2000-01-01")),
+            ..Limits::default()
+        });
+        assert_eq!(config_data.exclude_paths, vec![String::from("**/.git"), String::from("regex:(^|/)@eaDir$")]);
+        assert_eq!(config_data.categories["Photos"], vec![String::from("jpg"), String::from("heic")]);
+        assert_eq!(config_data.rules.len(), 2);
+        assert_eq!(config_data.rules[0].name, String::from("Recent PDFs"));
+        assert_eq!(config_data.rules[1].pattern, String::from("*.iso"));
+    }
+
+    #[test]
+    /// Test the [`Rule`] struct
+    fn test_rule() {
+        let rule: Rule = serde_json::from_str(r#"{
+            "extensions": ["JSON"],
+            "pattern": "templ*.????",
+            "max_size": "1 KB",
+            "newer_than": "30d",
+            "destination": "Config/%Y"
+        }"#).expect("Failed to parse rule.");
+
+        let filter = rule.filter().compile().expect("Invalid rule.");
+        let matches = |name: &str, days: i64| filter.matches(&File::new(name), Path::new(name), &mut || Some(Local::now() - Duration::days(days)));
+        assert!(matches("template.json", 3));
+        assert!(!matches("Cargo.toml", 3));
+        assert!(!matches("LICENSE-MIT.txt", 3));
+        assert!(!matches("template.json", 31));
+        assert_eq!(rule.display_name(1), "#2");
+        assert!(rule.validate().is_ok());
+        assert!(Rule { min_size: Some(String::from("big")), ..rule.clone() }.validate().is_err());
+    }
+
+    #[test]
+    /// Test [`glob_match`] and [`glob_match_path`]
+    fn test_glob_match() {
+        assert!(glob_match("*.iso", "ubuntu.iso"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("IMG_????.jpg", "IMG_0001.jpg"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(!glob_match("*.iso", "ubuntu.iso.part"));
+        assert!(!glob_match("IMG_???.jpg", "IMG_0001.jpg"));
+        assert!(glob_match("IMG_[0-9][0-9]*.jpg", "IMG_0001.jpg"));
+        assert!(glob_match("[!.]*", "photo.jpg"));
+        assert!(!glob_match("[!.]*", ".hidden"));
+        assert!(glob_match("[]a]", "]"));
+        assert!(glob_match("[a", "[a"));
+        assert!(!glob_match("*.[jJ][pP]g", "photo.png"));
+        assert!(glob_match_path("*.jpg", Path::new("photos/2021/a.jpg")));
+        assert!(glob_match_path("photos/*/a.jpg", Path::new("photos/2021/a.jpg")));
+        assert!(glob_match_path("**/thumbnails/**", Path::new("photos/thumbnails/small/a.jpg")));
+        assert!(glob_match_path("**/thumbnails/**", Path::new("thumbnails/a.jpg")));
+        assert!(!glob_match_path("photos/*.jpg", Path::new("photos/2021/a.jpg")));
+        assert!(!glob_match_path("**/thumbnails/**", Path::new("photos/thumbnails.jpg")));
+    }
+
+    #[test]
+    /// Test [`parse_size`], [`parse_age`] and [`parse_date`]
+    fn test_parse_size_and_age() {
+        assert_eq!(parse_size("1048576"), Some(1048576));
+        assert_eq!(parse_size("10 KB"), Some(10_000));
+        assert_eq!(parse_size("1.5GiB"), Some(1_610_612_736));
+        assert_eq!(parse_size("1 MB"), Some(1_000_000));
+        assert_eq!(parse_size("big"), None);
+        assert_eq!(parse_age("30d"), Some(Duration::days(30)));
+        assert_eq!(parse_age("2 w"), Some(Duration::weeks(2)));
+        assert_eq!(parse_age("90min"), Some(Duration::minutes(90)));
+        assert_eq!(parse_age("30"), None);
+        assert_eq!(parse_age("9223372036854775y"), None);
+        assert_eq!(parse_age("9223372036854776s"), None);
+        assert_eq!(parse_date("2021-02-03"), Some(Local.ymd(2021, 2, 3).and_hms(0, 0, 0)));
+        assert_eq!(parse_date("2021-02-03 14:22"), Some(Local.ymd(2021, 2, 3).and_hms(14, 22, 0)));
+        assert_eq!(parse_date("2021-02-03T14:22:33"), Some(Local.ymd(2021, 2, 3).and_hms(14, 22, 33)));
+        assert_eq!(parse_date("03.02.2021"), None);
+    }
+
+    #[test]
+    /// Test the [`Categories`] struct
+    fn test_categories() {
+        let mut overrides = HashMap::new();
+        overrides.insert(String::from("Photos"), vec![String::from("jpg")]);
+        overrides.insert(String::from("Ebooks"), vec![String::from(".EPUB"), String::from("mobi")]);
+        overrides.insert(String::from("Code"), vec![String::from("rs"), String::from("py")]);
+        let categories = Categories::new(&overrides);
+        assert!(Categories::validate(&overrides).is_ok());
+
+        assert_eq!(categories.category(&File::new("a/b.png")), "Images");
+        assert_eq!(categories.category(&File::new("b.JPG")), "Photos");
+        assert_eq!(categories.category(&File::new("b.epub")), "Ebooks");
+        assert_eq!(categories.category(&File::new("b.mobi")), "Ebooks");
+        assert_eq!(categories.category(&File::new("b.rs")), "Code");
+        assert_eq!(categories.category(&File::new("b.js")), OTHER_CATEGORY);
+        assert_eq!(categories.category(&File::new("b")), OTHER_CATEGORY);
+
+        // An extension in two categories goes to the one that sorts last, and
+        // isn't valid
+        overrides.insert(String::from("Books"), vec![String::from("epub")]);
+        assert_eq!(Categories::new(&overrides).category(&File::new("b.epub")), "Ebooks");
+        assert_eq!(Categories::validate(&overrides), Err(String::from("\"epub\" is in both \"Books\" and \"Ebooks\"")));
+        assert_eq!(SortBy::from_name("category"), Some(SortBy::Category));
+    }
+
+    #[test]
+    /// Test the [`Operation`] enum
+    fn test_operation() {
+        assert_eq!(serde_json::to_string(&Operation::Copy).unwrap(), "\"copy\"");
+        assert_eq!(serde_json::from_str::<Operation>("\"move\"").unwrap(), Operation::Move);
+        assert_eq!(serde_json::to_string(&Operation::RelativeSymLink).unwrap(), "\"relative-symlink\"");
+        assert!(serde_json::from_str::<Operation>("\"teleport\"").is_err());
+        assert_eq!(Operation::from_link_name("hard", true), Some(Operation::HardLink));
+        assert_eq!(Operation::from_link_name("symbolic", false), Some(Operation::SymLink));
+        assert_eq!(Operation::from_link_name("symbolic", true), Some(Operation::RelativeSymLink));
+        assert_eq!(Operation::from_link_name("soft", false), None);
+        assert!(Operation::Copy.keeps_source() && !Operation::Move.keeps_source());
+
+        // Journal entries written before there were operations are moves
+        let entry: super::JournalEntry = serde_json::from_str(
+            r#"{"run_id": "1", "timestamp": "", "old": "/a", "new": "/b", "size": 0, "mtime": 0}"#
+        ).expect("Failed to parse journal entry.");
+        assert_eq!(entry.operation, Operation::Move);
+    }
+
+    #[test]
+    /// Test the [`ConflictPolicy`] enum
+    fn test_conflictpolicy() {
+        for name in ConflictPolicy::NAMES.iter() {
+            let policy = ConflictPolicy::from_name(name).expect("Failed to get policy.");
+            assert_eq!(serde_json::to_string(&policy).unwrap(), format!("\"{}\"", name));
+        }
+        assert_eq!(ConflictPolicy::from_name("keep-newer"), Some(ConflictPolicy::KeepNewer));
+        assert_eq!(ConflictPolicy::from_name("replace"), None);
+        assert_eq!(ConflictPolicy::default(), ConflictPolicy::Rename);
+    }
+
+    #[test]
+    /// Test the [`DuplicatePolicy`] enum
+    fn test_duplicatepolicy() {
+        for name in DuplicatePolicy::NAMES.iter() {
+            let policy = DuplicatePolicy::from_name(name).expect("Failed to get policy.");
+            assert_eq!(serde_json::to_string(&policy).unwrap(), format!("\"{}\"", name));
+        }
+        assert_eq!(DuplicatePolicy::from_name("hardlink"), Some(DuplicatePolicy::HardLink));
+        assert_eq!(DuplicatePolicy::from_name("merge"), None);
+        assert_eq!(DuplicatePolicy::default(), DuplicatePolicy::Keep);
+    }
+
+    #[test]
+    /// Test the [`SymlinkPolicy`] enum
+    fn test_symlinkpolicy() {
+        for name in SymlinkPolicy::NAMES.iter() {
+            let policy = SymlinkPolicy::from_name(name).expect("Failed to get policy.");
+            assert_eq!(serde_json::to_string(&policy).unwrap(), format!("\"{}\"", name));
+        }
+        assert_eq!(SymlinkPolicy::from_name("follow"), None);
+        assert_eq!(SymlinkPolicy::default(), SymlinkPolicy::Move);
+    }
+
+    #[test]
+    /// Test the [`Depth`] struct
+    fn test_depth() {
+        assert!(Depth::default().validate().is_ok());
+        assert!(Depth { min_depth: Some(2), max_depth: Some(2) }.validate().is_ok());
+        assert!(Depth { min_depth: Some(3), max_depth: None }.validate().is_ok());
+        assert!(Depth { min_depth: Some(3), max_depth: Some(2) }.validate().is_err());
+        assert!(Depth { min_depth: Some(0), max_depth: None }.validate().is_err());
+        assert!(Depth { min_depth: None, max_depth: Some(0) }.validate().is_err());
+    }
+
+    #[test]
+    /// Test the [`Similarity`] struct, and the [`SimilarPolicy`] and
+    /// [`PerceptualHash`] enums
+    fn test_similarity() {
+        for name in SimilarPolicy::NAMES.iter() {
+            let policy = SimilarPolicy::from_name(name).expect("Failed to get policy.");
+            assert_eq!(serde_json::to_string(&policy).unwrap(), format!("\"{}\"", name));
+        }
+        for name in PerceptualHash::NAMES.iter() {
+            let hash = PerceptualHash::from_name(name).expect("Failed to get hash.");
+            assert_eq!(serde_json::to_string(&hash).unwrap(), format!("\"{}\"", name));
+        }
+        assert_eq!(SimilarPolicy::from_name("delete"), None);
+        assert_eq!(PerceptualHash::from_name("phash"), None);
+
+        let similarity: Similarity = serde_json::from_str(r#"{"action": "quarantine"}"#).expect("Failed to parse similarity.");
+        assert_eq!(similarity, Similarity { action: SimilarPolicy::Quarantine, ..Similarity::default() });
+        assert_eq!(Similarity::default(), Similarity { action: SimilarPolicy::Off, hash: PerceptualHash::DHash, distance: 5 });
     }
 
     #[test]
@@ -43,6 +266,13 @@ mod tests {
 
         // Test the methods
         assert!(!file.exists());
+        assert_eq!(file.content_hash(), None);
+        assert_eq!(File::new("testing/test.jpg").content_hash(), Some(0xcbf29ce484222325));
+        assert_eq!(File::new("template.json").content_hash(), File::new("template.json").content_hash());
+        assert!(File::new("testing/files").is_inside(&File::new("testing")));
+        assert!(File::new("testing/files/../files").is_inside(&File::new("./testing/files")));
+        assert!(!File::new("testing").is_inside(&File::new("testing/files")));
+        assert!(!File::new("testing/nothing").is_inside(&File::new("testing")));
         assert_eq!(file.copy(), File { pathbuf: path.to_path_buf() });
         assert_eq!(File::from_path(path), File { pathbuf: path.to_path_buf() });
         assert_eq!(File::from_pathbuf(&path.to_path_buf()), File { pathbuf: path.to_path_buf() });
@@ -55,17 +285,95 @@ mod tests {
         assert_eq!(file.to_path_buf(), path.to_path_buf());
         assert_eq!(file.to_string(), String::from("my_file.txt"));
     }
+
+    #[test]
+    /// Test the [`Journal`] struct
+    fn test_journal() {
+
+        // A fresh target directory to keep the journal in
+        let target = File::from_pathbuf(&env::temp_dir().join("sortery_test_journal"));
+        if target.exists() {
+            fs::remove_dir_all(target.to_path_buf()).expect("Failed to remove dir.");
+        }
+        fs::create_dir_all(target.to_path_buf()).expect("Failed to create dir.");
+        let moved = target.join(Path::new("moved.txt"));
+        fs::write(moved.to_path_buf(), "journal").expect("Failed to write file.");
+
+        // Record a move and read it back
+        let journal = Journal::new(&target);
+        assert!(Journal::read(&target).expect("Failed to read journal.").is_empty());
+        journal.record(&File::new("/old/moved.txt"), &moved, Operation::Copy, None).expect("Failed to record move.");
+        let entries = Journal::read(&target).expect("Failed to read journal.");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].operation, Operation::Copy);
+        assert_eq!(entries[0].run_id, journal.run_id);
+        assert_eq!(entries[0].old, Path::new("/old/moved.txt").to_path_buf());
+        assert_eq!(entries[0].new, moved.to_path_buf());
+        assert_eq!(entries[0].size, 7);
+        assert!(!entries[0].is_modified());
+
+        // Rewriting the journal with no entries removes it
+        Journal::write(&target, &Vec::new()).expect("Failed to write journal.");
+        assert!(!target.join_string(&String::from(super::JOURNAL_FILE_NAME)).exists());
+
+        fs::remove_dir_all(target.to_path_buf()).expect("Failed to remove dir.");
+    }
 }
 
+/// The name of the undo journal kept in the root of every target directory.
+pub const JOURNAL_FILE_NAME: &str = ".sortery_journal";
+
 /// The struct used for getting the config data from a json file
 #[derive(Debug)]
 #[derive(Serialize, Deserialize)]
 pub struct ConfigData {
     pub date_format: String,
+    #[serde(default = "ConfigData::default_dir_format")]
+    pub dir_format: String,
     pub date_type: String,
+    #[serde(default)]
+    pub name_patterns: Vec<String>,
+    #[serde(default = "ConfigData::default_name_fallback")]
+    pub name_fallback: String,
+    #[serde(default)]
     pub exclude_type: Vec<String>,
+    #[serde(default)]
     pub only_type: Vec<String>,
-    pub preserve_name: bool
+    #[serde(default)]
+    pub filter: Option<Filter>,
+    #[serde(flatten)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub exclude_paths: Vec<String>,
+    #[serde(default)]
+    pub gitignore: bool,
+    #[serde(flatten)]
+    pub depth: Depth,
+    #[serde(default)]
+    pub one_file_system: bool,
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+    #[serde(default)]
+    pub force: bool,
+    pub preserve_name: bool,
+    #[serde(default)]
+    pub name_template: String,
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
+    #[serde(default)]
+    pub duplicates: DuplicatePolicy,
+    #[serde(default)]
+    pub similar: Similarity,
+    #[serde(default)]
+    pub sort_by: SortBy,
+    #[serde(default)]
+    pub categories: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub operation: Operation,
 }
 impl ConfigData {
 
@@ -76,12 +384,857 @@ impl ConfigData {
 
         ConfigData {
             date_format: json_data.date_format,
+            dir_format: json_data.dir_format,
             date_type: json_data.date_type,
+            name_patterns: json_data.name_patterns,
+            name_fallback: json_data.name_fallback,
             exclude_type: json_data.exclude_type,
             only_type: json_data.only_type,
-            preserve_name: json_data.preserve_name
+            filter: json_data.filter,
+            limits: json_data.limits,
+            exclude_paths: json_data.exclude_paths,
+            gitignore: json_data.gitignore,
+            depth: json_data.depth,
+            one_file_system: json_data.one_file_system,
+            follow_symlinks: json_data.follow_symlinks,
+            symlinks: json_data.symlinks,
+            force: json_data.force,
+            preserve_name: json_data.preserve_name,
+            name_template: json_data.name_template,
+            on_conflict: json_data.on_conflict,
+            duplicates: json_data.duplicates,
+            similar: json_data.similar,
+            sort_by: json_data.sort_by,
+            categories: json_data.categories,
+            rules: json_data.rules,
+            operation: json_data.operation,
+        }
+    }
+
+    /// Return the default `dir_format`: year and month directories.
+    fn default_dir_format() -> String {
+        String::from("%Y/%m/")
+    }
+
+    /// Return the default `name_fallback`: the modification time.
+    fn default_name_fallback() -> String {
+        String::from("m")
+    }
+}
+
+/// A routing rule from the json config. A file matches the rule if it matches
+/// all of the rule's conditions; rules without conditions match every file.
+/// The conditions are:
+/// 
+/// <ul>
+/// <li>
+/// 
+/// `extensions`: the file's extension is one of these, ignoring case.
+/// </li>
+/// <li>
+/// 
+/// `pattern`: the file name matches this glob, as in `"*.iso"`. See [`glob_match`].
+/// </li>
+/// <li>
+/// 
+/// `min_size` and `max_size`: the file's size is in this range. See [`parse_size`].
+/// </li>
+/// <li>
+/// 
+/// `newer_than` and `older_than`: the file's date is in this range. See [`parse_age`].
+/// </li>
+/// </ul>
+/// 
+/// The first rule a file matches decides where it goes: `destination` is the
+/// directory format for it inside the target, and `name_template` and
+/// `date_type`, if given, override the ones in the rest of the config.
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub struct Rule {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    #[serde(default)]
+    pub pattern: String,
+    pub min_size: Option<String>,
+    pub max_size: Option<String>,
+    pub newer_than: Option<String>,
+    pub older_than: Option<String>,
+    pub destination: String,
+    pub name_template: Option<String>,
+    pub date_type: Option<String>,
+}
+impl Rule {
+
+    /// Return the name of the rule for messages: its `name`, or its position
+    /// in the list of rules (starting at 1) if it doesn't have one.
+    pub fn display_name(&self, index: usize) -> String {
+        match self.name.is_empty() {
+            true => format!("#{}", index + 1),
+            false => self.name.clone(),
+        }
+    }
+
+    /// Return a [`Filter`] matching the files that meet all the conditions of
+    /// the rule. The `pattern` becomes a [`Filter::Glob`], so the filter is to
+    /// be matched with the file's name as its path inside the source.
+    pub fn filter(&self) -> Filter {
+        let mut filters: Vec<Filter> = Vec::new();
+        if !self.extensions.is_empty() {
+            filters.push(Filter::Extension(self.extensions.clone()));
+        }
+        if !self.pattern.is_empty() {
+            filters.push(Filter::Glob(self.pattern.clone()));
+        }
+        filters.extend(Limits {
+            min_size: self.min_size.clone(),
+            max_size: self.max_size.clone(),
+            newer_than: self.newer_than.clone(),
+            older_than: self.older_than.clone(),
+            ..Limits::default()
+        }.filters());
+        Filter::And(filters)
+    }
+
+    /// Return a [`String`] describing what is wrong with the sizes and ages of
+    /// the rule, if anything. The other fields are checked where they are used.
+    pub fn validate(&self) -> Result<(), String> {
+        self.filter().validate()
+    }
+}
+
+/// Return [`true`] if `name` matches the glob `pattern`, where `*` matches any
+/// number of characters, `?` matches exactly one, and `[...]` matches one of
+/// the characters or ranges in the brackets, as in `[a-z0-9_]`, or one that
+/// isn't if they start with `!` or `^`.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // Whether `c` matches the character class starting at `pattern[p]`, and
+    // the length of the class, if there is one there
+    let class = |p: usize, c: char| -> Option<(bool, usize)> {
+        if pattern.get(p) != Some(&'[') { return None }
+        let negated = matches!(pattern.get(p + 1), Some('!') | Some('^'));
+        let start = p + 1 + negated as usize;
+        let end = start + 1 + pattern.get(start + 1..)?.iter().position(|c| *c == ']')?;
+        let mut matched = false;
+        let mut i = start;
+        while i < end {
+            if i + 2 < end && pattern[i + 1] == '-' {
+                matched |= pattern[i] <= c && c <= pattern[i + 2];
+                i += 3;
+            } else {
+                matched |= pattern[i] == c;
+                i += 1;
+            }
+        }
+        Some((matched != negated, end + 1 - p))
+    };
+
+    // The position of the last `*`, and of the name when it was reached
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        let step = match class(p, name[n]) {
+            Some((true, length)) => Some(length),
+            Some((false, _)) => None,
+            None if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) => Some(1),
+            None => None,
+        };
+        if let Some(length) = step {
+            p += length;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Return [`true`] if `path` matches the glob `pattern`, component by
+/// component, as in `"**/thumbnails/*.jpg"`. Each component is matched with
+/// [`glob_match`], except `**`, which matches any number of components. A
+/// pattern without a `/` matches `path` if it matches its last component, so
+/// `"*.jpg"` matches JPEG images at any depth.
+pub fn glob_match_path(pattern: &str, path: &Path) -> bool {
+    let pattern = pattern.trim_start_matches('/').trim_end_matches('/');
+    let components: Vec<String> = path.iter().map(|component| component.to_string_lossy().to_string()).collect();
+    if !pattern.contains('/') {
+        return components.last().is_some_and(|name| glob_match(pattern, name));
+    }
+
+    // Match the pattern's components against the path's, trying every number
+    // of components for each `**`
+    fn match_from(pattern: &[&str], components: &[String]) -> bool {
+        match pattern.split_first() {
+            None => components.is_empty(),
+            Some((&"**", rest)) => (0..=components.len()).any(|skip| match_from(rest, &components[skip..])),
+            Some((first, rest)) => components.split_first()
+                .is_some_and(|(component, components)| glob_match(first, component) && match_from(rest, components)),
+        }
+    }
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    match_from(&pattern, &components)
+}
+
+/// Return the number of bytes represented by `size`, as in `"1048576"`,
+/// `"10 KB"` or `"1.5GiB"`, or [`None`] if it isn't a valid size. The units
+/// `K`/`KB`, `M`/`MB`, `G`/`GB` and `T`/`TB` are powers of 1000, and `KiB`,
+/// `MiB`, `GiB` and `TiB` are powers of 1024. Units are case-insensitive.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let split = size.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(size.len());
+    let number: f64 = size[..split].parse().ok()?;
+    let multiplier: u64 = match size[split..].trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return None,
+    };
+    Some((number * multiplier as f64) as u64)
+}
+
+/// Return the length of time represented by `age`, as in `"30d"` or `"2 w"`,
+/// or [`None`] if it isn't a valid age. The units are `s` (seconds), `min`
+/// (minutes), `h` (hours), `d` (days), `w` (weeks), `mo` (30-day months) and
+/// `y` (365-day years).
+pub fn parse_age(age: &str) -> Option<Duration> {
+    let age = age.trim();
+    let split = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let number: i64 = age[..split].parse().ok()?;
+    let secs: i64 = match age[split..].trim().to_lowercase().as_str() {
+        "s" => 1,
+        "min" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 7 * 86400,
+        "mo" => 30 * 86400,
+        "y" => 365 * 86400,
+        _ => return None,
+    };
+    // Ages longer than a Duration can hold aren't valid either
+    let secs = number.checked_mul(secs).filter(|secs| *secs <= Duration::max_value().num_seconds())?;
+    Some(Duration::seconds(secs))
+}
+
+/// Return the local date and time represented by `date`, as in `"2021-02-03"`,
+/// `"2021-02-03 14:22"` or `"2021-02-03T14:22:33"`, or [`None`] if it isn't a
+/// valid date. Dates without a time are at midnight.
+pub fn parse_date(date: &str) -> Option<DateTime<Local>> {
+    let date = date.trim();
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok().map(|day| day.and_hms(0, 0, 0)))?;
+    Local.from_local_datetime(&naive).earliest()
+}
+
+/// What files are sorted by.
+#[derive(Debug)]
+#[derive(Clone, Copy, Default, PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortBy {
+    /// Sort files into directories by date, and rename them after their date.
+    #[default]
+    Date,
+    /// Sort files into directories by their [`Categories`], keeping their names.
+    Category,
+}
+impl SortBy {
+
+    /// The names of the sort modes, as used on the command line and in json.
+    pub const NAMES: [&'static str; 2] = ["date", "category"];
+
+    /// Return the [`SortBy`] called `name`, or [`None`] if there is none.
+    pub fn from_name(name: &str) -> Option<SortBy> {
+        match name {
+            "date" => Some(SortBy::Date),
+            "category" => Some(SortBy::Category),
+            _ => None,
+        }
+    }
+}
+
+/// What is done with each file once its new path is known.
+#[derive(Debug)]
+#[derive(Clone, Copy, Default, PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    /// Move the file to its new path.
+    #[default]
+    Move,
+    /// Copy the file to its new path, leaving the original where it is.
+    Copy,
+    /// Make a hard link to the file at its new path.
+    #[serde(rename = "hardlink")]
+    HardLink,
+    /// Make a symbolic link to the absolute path of the file at its new path.
+    #[serde(rename = "symlink")]
+    SymLink,
+    /// Make a symbolic link to the path of the file relative to its new path.
+    #[serde(rename = "relative-symlink")]
+    RelativeSymLink,
+}
+impl Operation {
+
+    /// The kinds of link, as used on the command line.
+    pub const LINK_NAMES: [&'static str; 2] = ["hard", "symbolic"];
+
+    /// Return the [`Operation`] making links of the kind called `name`, or
+    /// [`None`] if there is none. `relative` is whether symbolic links are
+    /// relative.
+    pub fn from_link_name(name: &str, relative: bool) -> Option<Operation> {
+        match (name, relative) {
+            ("hard", _) => Some(Operation::HardLink),
+            ("symbolic", false) => Some(Operation::SymLink),
+            ("symbolic", true) => Some(Operation::RelativeSymLink),
+            _ => None,
         }
     }
+
+    /// Return [`true`] if the original files stay where they are.
+    pub fn keeps_source(&self) -> bool {
+        *self != Operation::Move
+    }
+}
+
+/// The built-in file categories, and the extensions that belong to them.
+pub const BUILTIN_CATEGORIES: [(&str, &[&str]); 6] = [
+    ("Images", &["jpg", "jpeg", "png", "gif", "bmp", "tif", "tiff", "webp", "heic", "heif", "svg", "raw", "dng", "cr2", "nef", "arw"]),
+    ("Videos", &["mp4", "mov", "m4v", "avi", "mkv", "webm", "wmv", "flv", "3gp", "mpg", "mpeg"]),
+    ("Audio", &["mp3", "wav", "flac", "aac", "m4a", "ogg", "opus", "wma", "aiff"]),
+    ("Documents", &["pdf", "doc", "docx", "odt", "rtf", "txt", "md", "xls", "xlsx", "ods", "csv", "ppt", "pptx", "odp", "epub"]),
+    ("Archives", &["zip", "tar", "gz", "tgz", "bz2", "xz", "7z", "rar", "iso", "dmg", "deb", "rpm"]),
+    ("Code", &["rs", "py", "js", "ts", "c", "h", "cpp", "hpp", "java", "go", "rb", "sh", "html", "css", "json", "toml", "yaml", "yml", "xml"]),
+];
+
+/// The category of files that aren't in any other category.
+pub const OTHER_CATEGORY: &str = "Other";
+
+/// The table of file categories by extension used when sorting by category.
+#[derive(Debug)]
+pub struct Categories {
+    pub by_extension: HashMap<String, String>,
+}
+impl Categories {
+
+    /// Return the [`BUILTIN_CATEGORIES`], extended and overridden by `overrides`,
+    /// a map of category names to their extensions. A category in `overrides`
+    /// has just the extensions listed for it, even if it is built in, and an
+    /// extension listed in `overrides` belongs to that category, even if it is
+    /// also built in. If an extension is listed for more than one category,
+    /// which [`Categories::validate`] rejects, the category whose name sorts
+    /// last gets it.
+    pub fn new(overrides: &HashMap<String, Vec<String>>) -> Categories {
+        let mut by_extension = HashMap::new();
+        for (category, extensions) in BUILTIN_CATEGORIES.iter().filter(|(category, _)| !overrides.contains_key(*category)) {
+            for extension in extensions.iter() {
+                by_extension.insert(extension.to_string(), category.to_string());
+            }
+        }
+        let overrides: BTreeMap<&String, &Vec<String>> = overrides.iter().collect();
+        for (category, extensions) in overrides.into_iter() {
+            for extension in extensions.iter() {
+                by_extension.insert(Categories::normalize(extension), category.clone());
+            }
+        }
+        Categories { by_extension }
+    }
+
+    /// Return [`Ok`] if no extension is listed for more than one category in
+    /// `overrides`, as in [`Categories::new`], or a [`String`] telling which
+    /// one is otherwise.
+    pub fn validate(overrides: &HashMap<String, Vec<String>>) -> Result<(), String> {
+        let overrides: BTreeMap<&String, &Vec<String>> = overrides.iter().collect();
+        let mut categories: HashMap<String, &String> = HashMap::new();
+        for (category, extensions) in overrides.into_iter() {
+            for extension in extensions.iter().map(|extension| Categories::normalize(extension)) {
+                match categories.insert(extension.clone(), category) {
+                    Some(other) if other != category => {
+                        return Err(format!("\"{}\" is in both \"{}\" and \"{}\"", extension, other, category));
+                    },
+                    _ => {},
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Return `extension` as the extensions are looked up: lowercase, without a dot.
+    fn normalize(extension: &str) -> String {
+        extension.trim_start_matches('.').to_lowercase()
+    }
+
+    /// Return the name of the category of `file`, by its extension, or
+    /// [`OTHER_CATEGORY`] if it isn't in any.
+    pub fn category(&self, file: &File) -> &str {
+        match self.by_extension.get(&file.extension().to_lowercase()) {
+            Some(category) => category,
+            None => OTHER_CATEGORY,
+        }
+    }
+}
+
+/// What to do when a file's new path is already taken by a file that exists
+/// in the target directory.
+#[derive(Debug)]
+#[derive(Clone, Copy, Default, PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    /// Give the file a sequential name, as in `file_2.txt`.
+    #[default]
+    Rename,
+    /// Leave the file where it is.
+    Skip,
+    /// Replace the existing file.
+    Overwrite,
+    /// Replace the existing file only if ours was modified more recently.
+    KeepNewer,
+    /// Leave the file where it is if its contents are identical to the existing
+    /// file; otherwise give it a sequential name.
+    SkipIdentical,
+}
+impl ConflictPolicy {
+
+    /// The names of the policies, as used on the command line and in json.
+    pub const NAMES: [&'static str; 5] = ["rename", "skip", "overwrite", "keep-newer", "skip-identical"];
+
+    /// Return the [`ConflictPolicy`] called `name`, or [`None`] if there is none.
+    pub fn from_name(name: &str) -> Option<ConflictPolicy> {
+        match name {
+            "rename" => Some(ConflictPolicy::Rename),
+            "skip" => Some(ConflictPolicy::Skip),
+            "overwrite" => Some(ConflictPolicy::Overwrite),
+            "keep-newer" => Some(ConflictPolicy::KeepNewer),
+            "skip-identical" => Some(ConflictPolicy::SkipIdentical),
+            _ => None,
+        }
+    }
+}
+
+/// What to do with files whose contents are identical to a file already in the
+/// target directory, or to another file in the same sort.
+#[derive(Debug)]
+#[derive(Clone, Copy, Default, PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    /// Don't look for duplicates, and sort them like any other file.
+    #[default]
+    Keep,
+    /// Leave duplicates where they are.
+    Skip,
+    /// Delete duplicates, by moving them into [`TRASH_DIR_NAME`] in the
+    /// target, so that the run can still be undone. Duplicates are left where
+    /// they are if the source is left untouched.
+    Delete,
+    /// Move duplicates into [`DUPLICATES_DIR_NAME`] in the target, keeping their names.
+    Move,
+    /// Sort duplicates as usual, but as hard links to the file they duplicate.
+    #[serde(rename = "hardlink")]
+    HardLink,
+}
+impl DuplicatePolicy {
+
+    /// The names of the policies, as used on the command line and in json.
+    pub const NAMES: [&'static str; 5] = ["keep", "skip", "delete", "move", "hardlink"];
+
+    /// Return the [`DuplicatePolicy`] called `name`, or [`None`] if there is none.
+    pub fn from_name(name: &str) -> Option<DuplicatePolicy> {
+        match name {
+            "keep" => Some(DuplicatePolicy::Keep),
+            "skip" => Some(DuplicatePolicy::Skip),
+            "delete" => Some(DuplicatePolicy::Delete),
+            "move" => Some(DuplicatePolicy::Move),
+            "hardlink" => Some(DuplicatePolicy::HardLink),
+            _ => None,
+        }
+    }
+}
+
+/// The directory in the target that duplicates are moved into with
+/// [`DuplicatePolicy::Move`].
+pub const DUPLICATES_DIR_NAME: &str = "duplicates";
+
+/// The directory in the target that duplicates are moved into with
+/// [`DuplicatePolicy::Delete`]. It can be emptied once the run doesn't need
+/// to be undone anymore.
+pub const TRASH_DIR_NAME: &str = ".sortery_trash";
+
+/// What to do with images that look like a higher-resolution image in the
+/// same sort, but aren't identical to it.
+#[derive(Debug)]
+#[derive(Clone, Copy, Default, PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SimilarPolicy {
+    /// Don't look for similar images.
+    #[default]
+    Off,
+    /// Sort similar images as usual, but tell which image they look like.
+    Report,
+    /// Move similar images into [`SIMILAR_DIR_NAME`] in the target, keeping their names.
+    Quarantine,
+}
+impl SimilarPolicy {
+
+    /// The names of the policies, as used on the command line and in json.
+    pub const NAMES: [&'static str; 3] = ["off", "report", "quarantine"];
+
+    /// Return the [`SimilarPolicy`] called `name`, or [`None`] if there is none.
+    pub fn from_name(name: &str) -> Option<SimilarPolicy> {
+        match name {
+            "off" => Some(SimilarPolicy::Off),
+            "report" => Some(SimilarPolicy::Report),
+            "quarantine" => Some(SimilarPolicy::Quarantine),
+            _ => None,
+        }
+    }
+}
+
+/// The perceptual hash used to tell whether two images look alike. See
+/// [`crate::perceptual`].
+#[derive(Debug)]
+#[derive(Clone, Copy, Default, PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PerceptualHash {
+    /// The average hash, which compares each part of the image with the
+    /// average brightness.
+    #[serde(rename = "ahash")]
+    AHash,
+    /// The difference hash, which compares each part of the image with its
+    /// neighbor, and is less fooled by changes in brightness and contrast.
+    #[default]
+    #[serde(rename = "dhash")]
+    DHash,
+}
+impl PerceptualHash {
+
+    /// The names of the hashes, as used on the command line and in json.
+    pub const NAMES: [&'static str; 2] = ["ahash", "dhash"];
+
+    /// Return the [`PerceptualHash`] called `name`, or [`None`] if there is none.
+    pub fn from_name(name: &str) -> Option<PerceptualHash> {
+        match name {
+            "ahash" => Some(PerceptualHash::AHash),
+            "dhash" => Some(PerceptualHash::DHash),
+            _ => None,
+        }
+    }
+}
+
+/// How to find and handle similar images: `action` is what to do with them,
+/// `hash` is the perceptual hash used to compare them, and `distance` is the
+/// most bits two hashes can differ by for their images to count as similar.
+/// In the json config, this is an object with the same keys, as in
+/// `"similar": {"action": "quarantine", "distance": 6}`.
+#[derive(Debug)]
+#[derive(Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub struct Similarity {
+    #[serde(default)]
+    pub action: SimilarPolicy,
+    #[serde(default)]
+    pub hash: PerceptualHash,
+    #[serde(default = "Similarity::default_distance")]
+    pub distance: u32,
+}
+impl Similarity {
+
+    /// The most that `distance` can be; hashes are 64 bits long.
+    pub const MAX_DISTANCE: u32 = 64;
+
+    /// Return the default `distance`, which allows for resizing and
+    /// re-encoding, but not for cropping or editing.
+    fn default_distance() -> u32 {
+        5
+    }
+}
+impl Default for Similarity {
+    fn default() -> Similarity {
+        Similarity {
+            action: SimilarPolicy::default(),
+            hash: PerceptualHash::default(),
+            distance: Similarity::default_distance(),
+        }
+    }
+}
+
+/// The directory in the target that similar images are moved into with
+/// [`SimilarPolicy::Quarantine`].
+pub const SIMILAR_DIR_NAME: &str = "similar";
+
+/// What to do with the symlinks in the source.
+#[derive(Debug)]
+#[derive(Clone, Copy, Default, PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkPolicy {
+    /// Sort the symlinks themselves, like any other file.
+    #[default]
+    Move,
+    /// Leave the symlinks where they are.
+    Skip,
+    /// Sort a copy of the file each symlink points to in its place, removing
+    /// the symlink unless the source is being left untouched. Symlinks to
+    /// directories that aren't followed, and broken symlinks, are left where they are.
+    Resolve,
+}
+impl SymlinkPolicy {
+
+    /// The names of the policies, as used on the command line and in json.
+    pub const NAMES: [&'static str; 3] = ["move", "skip", "resolve"];
+
+    /// Return the [`SymlinkPolicy`] called `name`, or [`None`] if there is none.
+    pub fn from_name(name: &str) -> Option<SymlinkPolicy> {
+        match name {
+            "move" => Some(SymlinkPolicy::Move),
+            "skip" => Some(SymlinkPolicy::Skip),
+            "resolve" => Some(SymlinkPolicy::Resolve),
+            _ => None,
+        }
+    }
+}
+
+/// How deep into SOURCE to look, where the paths directly in SOURCE are at
+/// depth 1: paths shallower than `min_depth` or deeper than `max_depth` are
+/// left where they are, and either can be left out. In the json config, these
+/// are the `"min_depth"` and `"max_depth"` keys.
+#[derive(Debug)]
+#[derive(Clone, Copy, Default, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub struct Depth {
+    #[serde(default)]
+    pub min_depth: Option<usize>,
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+}
+impl Depth {
+
+    /// Return [`Ok`] if the depths are valid, or a [`String`] describing what
+    /// is wrong with them.
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.min_depth, self.max_depth) {
+            (Some(0), _) | (_, Some(0)) => Err(String::from("depths start at 1, for the paths directly in SOURCE")),
+            (Some(min), Some(max)) if min > max => Err(format!("the min depth {} is more than the max depth {}", min, max)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What the sorting algorithm is going to do with a file.
+#[derive(Debug)]
+#[derive(Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortAction {
+    /// Move the file to its new path.
+    Move,
+    /// Move the file to a sequential name, because its new path was taken.
+    Rename,
+    /// Move the file to its new path, replacing the file already there.
+    Overwrite,
+    /// Leave the file where it is, because its new path was taken.
+    Skip,
+    /// Leave the file where it is, because it is a duplicate.
+    SkipDuplicate,
+    /// Move the file into [`TRASH_DIR_NAME`], because it is a duplicate to be
+    /// deleted.
+    #[serde(alias = "delete-duplicate")]
+    TrashDuplicate,
+    /// Move the file into [`DUPLICATES_DIR_NAME`], because it is a duplicate.
+    MoveDuplicate,
+    /// Make the new path a hard link to the file this one duplicates, instead
+    /// of moving the file there.
+    LinkDuplicate,
+    /// Move the image into [`SIMILAR_DIR_NAME`], because it looks like an
+    /// image with a higher resolution.
+    QuarantineSimilar,
+    /// Leave the file where it is, because it doesn't match the filters.
+    SkipFiltered,
+    /// Leave the path where it is, because an ignore file ignores it.
+    SkipIgnored,
+    /// Leave the path where it is, because it isn't a file that can be sorted,
+    /// like a FIFO, or because it is a symlink that is to be left alone.
+    SkipSpecial,
+}
+impl SortAction {
+
+    /// Return [`true`] if the file is actually going to end up at its new path.
+    pub fn moves(&self) -> bool {
+        !matches!(self, SortAction::Skip | SortAction::SkipDuplicate | SortAction::SkipFiltered | SortAction::SkipIgnored | SortAction::SkipSpecial)
+    }
+
+    /// Return a short description of the action, for dry-run output. Plain
+    /// moves have an empty description. The descriptions of the actions for
+    /// duplicates are followed by the path of the file they duplicate.
+    pub fn description(&self) -> &'static str {
+        match self {
+            SortAction::Move => "",
+            SortAction::Rename => "renamed, target exists",
+            SortAction::Overwrite => "overwrites existing file",
+            SortAction::Skip => "skipped, target exists",
+            SortAction::SkipDuplicate => "skipped, duplicate of",
+            SortAction::TrashDuplicate => "trashed, duplicate of",
+            SortAction::MoveDuplicate => "moved, duplicate of",
+            SortAction::LinkDuplicate => "hardlinked, duplicate of",
+            SortAction::QuarantineSimilar => "quarantined",
+            SortAction::SkipFiltered => "filtered out",
+            SortAction::SkipIgnored => "ignored",
+            SortAction::SkipSpecial => "not sorted",
+        }
+    }
+}
+
+/// A single move recorded in the undo journal. `old` is where the path was
+/// before the move, and `new` is where it was moved to. `size` and `mtime` are
+/// the size and modification time of `new` right after the move, and are used
+/// to tell whether the file was changed before it is moved back. `operation` is
+/// how the path got to `new`; copies are undone by removing `new`. `link` is
+/// what `old` pointed to if it was a symlink replaced by a copy of the file it
+/// points to; moves of those are undone by making the symlink again.
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub run_id: String,
+    pub timestamp: String,
+    pub old: PathBuf,
+    pub new: PathBuf,
+    pub size: u64,
+    pub mtime: i64,
+    #[serde(default)]
+    pub operation: Operation,
+    #[serde(default)]
+    pub link: Option<PathBuf>,
+}
+impl JournalEntry {
+
+    /// Return [`true`] if `new` is a file that has been changed since it was
+    /// moved. Directories are never reported as modified.
+    pub fn is_modified(&self) -> bool {
+        match self.new.metadata() {
+            Ok(metadata) if metadata.is_file() => {
+                let (size, mtime) = size_and_mtime(&self.new);
+                size != self.size || mtime != self.mtime
+            },
+            _ => false,
+        }
+    }
+}
+
+/// The undo journal of a target directory. Every move made by a single run
+/// is recorded with the same `run_id`, so that the whole run can be undone at
+/// once. The journal is stored as one JSON entry per line in
+/// [`JOURNAL_FILE_NAME`] inside the target directory.
+#[derive(Debug)]
+pub struct Journal {
+    pub file: File,
+    pub run_id: String,
+}
+impl Journal {
+
+    /// Return a new [`Journal`] for `target`, with a fresh run id. Nothing is
+    /// written until the first call to [`Journal::record`].
+    pub fn new(target: &File) -> Journal {
+        Journal {
+            file: target.join_string(&String::from(JOURNAL_FILE_NAME)),
+            run_id: format!("{}-{}", Local::now().format("%Y%m%d%H%M%S%.6f"), process::id()),
+        }
+    }
+
+    /// Append the move (or other `operation`) of `old` to `new` to the journal.
+    /// `new` must already exist, as its size and modification time are recorded.
+    /// Both paths are recorded as absolute paths, so that the run can be undone
+    /// from anywhere. `link` is what `old` pointed to, if it was a symlink
+    /// replaced by a copy of that. Return the error if the journal can't be written.
+    pub fn record(&self, old: &File, new: &File, operation: Operation, link: Option<&Path>) -> io::Result<()> {
+        let (size, mtime) = size_and_mtime(&new.pathbuf);
+        let current_dir = env::current_dir()?;
+        let entry = JournalEntry {
+            run_id: self.run_id.clone(),
+            timestamp: Local::now().to_rfc3339(),
+            old: current_dir.join(&old.pathbuf),
+            new: current_dir.join(&new.pathbuf),
+            size,
+            mtime,
+            operation,
+            link: link.map(Path::to_path_buf),
+        };
+
+        let mut journal = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file.pathbuf)?;
+        writeln!(journal, "{}", serde_json::to_string(&entry)?)
+    }
+
+    /// Return all the entries in the journal of `target`, oldest first, or
+    /// the error if it can't be read.
+    pub fn read(target: &File) -> io::Result<Vec<JournalEntry>> {
+        let path = target.join_string(&String::from(JOURNAL_FILE_NAME));
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        fs::read_to_string(path.to_path_buf())?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(io::Error::from))
+            .collect()
+    }
+
+    /// Replace the journal of `target` with `entries`. If `entries` is empty,
+    /// the journal file is removed. Return the error if it can't be written.
+    pub fn write(target: &File, entries: &[JournalEntry]) -> io::Result<()> {
+        let path = target.join_string(&String::from(JOURNAL_FILE_NAME));
+        if entries.is_empty() {
+            if path.exists() {
+                fs::remove_file(path.to_path_buf())?;
+            }
+            return Ok(());
+        }
+        let mut contents = String::new();
+        for entry in entries {
+            contents.push_str(&serde_json::to_string(entry)?);
+            contents.push('\n');
+        }
+        fs::write(path.to_path_buf(), contents)
+    }
+}
+
+/// Return the size and modification time (in seconds since the UNIX epoch) of
+/// `path`, or `(0, 0)` if they can't be read.
+fn size_and_mtime(path: &Path) -> (u64, i64) {
+    match path.metadata() {
+        Ok(metadata) => {
+            let mtime = metadata.modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs() as i64)
+                .unwrap_or(0);
+            (metadata.len(), mtime)
+        },
+        Err(_) => (0, 0),
+    }
 }
 
 /// The struct used in all the cross-function path functionality
@@ -107,6 +1260,34 @@ impl File {
         File { pathbuf: path.to_path_buf() }
     }
 
+    /// Return a 64-bit FNV-1a hash of the contents of our path, or [`None`] if
+    /// it can't be read. This is fast, but not cryptographically secure.
+    pub fn content_hash(&self) -> Option<u64> {
+        let mut file = fs::File::open(&self.pathbuf).ok()?;
+        let mut buffer = [0u8; 65536];
+        let mut hash: u64 = 0xcbf29ce484222325;
+        loop {
+            let read = file.read(&mut buffer).ok()?;
+            if read == 0 {
+                return Some(hash);
+            }
+            for byte in buffer[..read].iter() {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+    }
+
+    /// Return [`true`] if our path is `dir`, or is inside it, once symlinks
+    /// and relative parts like `..` are resolved. Paths that don't exist are
+    /// never inside anything.
+    pub fn is_inside(&self, dir: &File) -> bool {
+        match (fs::canonicalize(&self.pathbuf), fs::canonicalize(&dir.pathbuf)) {
+            (Ok(path), Ok(dir)) => path.starts_with(dir),
+            _ => false,
+        }
+    }
+
     /// Return [`true`] if our path exists
     pub fn exists(&self) -> bool {
         if self.pathbuf.exists() {
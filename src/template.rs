@@ -0,0 +1,258 @@
+//! The template language used for naming sorted files, as in
+//! `{date:%Y%m%d}_{stem}_{counter:04}.{ext|lower}`.
+//!
+//! A template is literal text with placeholders in braces. Each placeholder has
+//! a name, an optional argument after a `:`, and any number of filters, each
+//! after a `|`. Literal braces are written as `{{` and `}}`. The placeholders are:
+//!
+//! <ul>
+//! <li><code>{date}</code>: the file's date, in the date format; <code>{date:FORMAT}</code> uses FORMAT instead</li>
+//! <li><code>{stem}</code>: the original file name, without its extension</li>
+//! <li><code>{ext}</code>: the original extension, without the dot</li>
+//! <li><code>{name}</code>: the original file name</li>
+//! <li><code>{parent}</code>: the name of the directory the file was in</li>
+//! <li><code>{size}</code>: the size of the file in bytes</li>
+//! <li><code>{hash}</code>: the first 8 hex digits of a hash of the contents; <code>{hash:N}</code> uses N digits, up to 16</li>
+//! <li><code>{camera}</code>: the camera model from the EXIF metadata, or <code>unknown</code></li>
+//! <li><code>{counter}</code>: the number of the file in its target directory, starting at 1; <code>{counter:04}</code> pads it to 4 digits</li>
+//! </ul>
+//!
+//! The filters are `lower` and `upper`.
+
+use chrono::{format::{Item, StrftimeItems}, DateTime, Local};
+use crate::{metadata::exif, structs::File};
+
+#[cfg(test)]
+/// Tests for the template language. Each test is named after the function it
+/// tests, prefixed with test.
+mod tests {
+
+    use chrono::{Local, TimeZone};
+    use crate::structs::File;
+    use super::Template;
+
+    #[test]
+    /// Test [`Template::parse`]
+    fn test_parse() {
+        assert!(Template::parse("{date:%Y%m%d}_{stem}_{counter:04}.{ext|lower}").is_ok());
+        assert!(Template::parse("{{literal}} {camera|upper|lower}").is_ok());
+        assert!(Template::parse("{hash:16}").is_ok());
+        assert!(Template::parse("{stem").is_err());
+        assert!(Template::parse("stem}").is_err());
+        assert!(Template::parse("{color}").is_err());
+        assert!(Template::parse("{stem|reverse}").is_err());
+        assert!(Template::parse("{date:%Q}").is_err());
+        assert!(Template::parse("{counter:four}").is_err());
+        assert!(Template::parse("{hash:17}").is_err());
+        assert!(Template::parse("{stem:4}").is_err());
+        assert!(Template::parse("{parent}/{stem}").is_err());
+        assert!(Template::parse("..").is_err());
+        assert!(Template::parse(".").is_err());
+        assert!(Template::parse("..{stem}").is_ok());
+    }
+
+    #[test]
+    /// Test [`Template::render`]
+    fn test_render() {
+        let file = File::new("testing/files/Test.JPG");
+        let datetime = Local.ymd(2021, 2, 3).and_hms(14, 22, 33);
+        let render = |template: &str, counter: usize| {
+            Template::parse(template).unwrap().render(&file, &datetime, "%Y-%m-%d", counter)
+        };
+
+        assert_eq!(render("{date:%Y%m%d}_{stem}_{counter:04}.{ext|lower}", 7), "20210203_Test_0007.jpg");
+        assert_eq!(render("{date} {name|lower}", 1), "2021-02-03 test.jpg");
+        assert_eq!(render("{parent|upper}-{counter}", 12), "FILES-12");
+        assert_eq!(render("{{{camera}}}", 1), "{unknown}");
+        assert_eq!(render("{size}", 1), "0");
+        assert_eq!(render("{hash:4}", 1), "0000");
+        assert_eq!(render("{date:..}", 1), "__");
+    }
+}
+
+/// The name of a placeholder in a [`Template`].
+#[derive(Debug)]
+#[derive(Clone, Copy, PartialEq)]
+enum Placeholder {
+    Date,
+    Stem,
+    Ext,
+    Name,
+    Parent,
+    Size,
+    Hash,
+    Camera,
+    Counter,
+}
+
+/// A filter applied to the value of a placeholder.
+#[derive(Debug)]
+#[derive(Clone, Copy, PartialEq)]
+enum Filter {
+    Lower,
+    Upper,
+}
+
+/// A part of a [`Template`]: either literal text, or a placeholder with its
+/// argument and filters.
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder, Option<String>, Vec<Filter>),
+}
+
+/// A parsed file name template. See the module documentation for the syntax.
+#[derive(Debug)]
+#[derive(Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+impl Template {
+
+    /// Return the [`Template`] represented by `template`, or a [`String`]
+    /// describing why it isn't valid.
+    pub fn parse(template: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                },
+                '{' => {
+
+                    // Read up to the closing brace
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => return Err(format!("unclosed placeholder \"{{{}\"", placeholder)),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(literal));
+                        literal = String::new();
+                    }
+                    parts.push(Template::parse_placeholder(&placeholder)?);
+                },
+                '}' => return Err(String::from("unmatched \"}\"; use \"}}\" for a literal brace")),
+                '/' => return Err(String::from("file names can't contain \"/\"")),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        if template == "." || template == ".." {
+            return Err(format!("file names can't be \"{}\"", template));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Return the [`Part`] represented by `placeholder`, the text between the
+    /// braces of a placeholder.
+    fn parse_placeholder(placeholder: &str) -> Result<Part, String> {
+        let mut pieces = placeholder.split('|');
+        let head = pieces.next().unwrap_or("");
+        let (name, arg) = match head.find(':') {
+            Some(i) => (&head[..i], Some(String::from(&head[i + 1..]))),
+            None => (head, None),
+        };
+
+        // The placeholder name, and its argument
+        let name = match name.trim() {
+            "date" => Placeholder::Date,
+            "stem" => Placeholder::Stem,
+            "ext" => Placeholder::Ext,
+            "name" => Placeholder::Name,
+            "parent" => Placeholder::Parent,
+            "size" => Placeholder::Size,
+            "hash" => Placeholder::Hash,
+            "camera" => Placeholder::Camera,
+            "counter" => Placeholder::Counter,
+            name => return Err(format!("unknown placeholder \"{}\"", name)),
+        };
+        if let Some(arg) = &arg {
+            let valid = match name {
+                Placeholder::Date => !StrftimeItems::new(arg).any(|item| item == Item::Error),
+                Placeholder::Counter => arg.parse::<usize>().map(|width| width <= 20).unwrap_or(false),
+                Placeholder::Hash => arg.parse::<usize>().map(|digits| (1..=16).contains(&digits)).unwrap_or(false),
+                _ => false,
+            };
+            if !valid {
+                return Err(format!("invalid argument \"{}\" in \"{{{}}}\"", arg, placeholder));
+            }
+        }
+
+        // The filters
+        let mut filters = Vec::new();
+        for filter in pieces {
+            filters.push(match filter.trim() {
+                "lower" => Filter::Lower,
+                "upper" => Filter::Upper,
+                filter => return Err(format!("unknown filter \"{}\"", filter)),
+            });
+        }
+        Ok(Part::Placeholder(name, arg, filters))
+    }
+
+    /// Return the new file name for `file` dated `datetime`. `date_format` is
+    /// the format used by `{date}` without an argument, and `counter` is the
+    /// number of the file in its target directory. Any `/` in the values of
+    /// placeholders is replaced with `_`.
+    pub fn render(&self, file: &File, datetime: &DateTime<Local>, date_format: &str, counter: usize) -> String {
+        let mut rendered = String::new();
+        for part in self.parts.iter() {
+            let (name, arg, filters) = match part {
+                Part::Literal(literal) => {
+                    rendered.push_str(literal);
+                    continue;
+                },
+                Part::Placeholder(name, arg, filters) => (name, arg.as_deref(), filters),
+            };
+
+            let mut value = match name {
+                Placeholder::Date => datetime.format(arg.unwrap_or(date_format)).to_string(),
+                Placeholder::Stem => file.file_stem(),
+                Placeholder::Ext => file.extension(),
+                Placeholder::Name => file.file_name(),
+                Placeholder::Parent => match file.pathbuf.parent() {
+                    Some(parent) => File::from_path(parent).file_name(),
+                    None => String::new(),
+                },
+                Placeholder::Size => file.pathbuf.metadata().map(|metadata| metadata.len()).unwrap_or(0).to_string(),
+                Placeholder::Hash => {
+                    let digits = arg.and_then(|arg| arg.parse().ok()).unwrap_or(8);
+                    format!("{:016x}", file.content_hash().unwrap_or(0))[..digits].to_string()
+                },
+                Placeholder::Camera => exif::get_camera_model(file).unwrap_or_else(|| String::from("unknown")),
+                Placeholder::Counter => {
+                    let width = arg.and_then(|arg| arg.parse().ok()).unwrap_or(0);
+                    format!("{:0width$}", counter, width = width)
+                },
+            };
+            for filter in filters.iter() {
+                value = match filter {
+                    Filter::Lower => value.to_lowercase(),
+                    Filter::Upper => value.to_uppercase(),
+                };
+            }
+            rendered.push_str(&value.replace('/', "_"));
+        }
+
+        // Names that are made of placeholders can still come out as . or ..
+        match rendered.as_str() {
+            "." | ".." => rendered.replace('.', "_"),
+            _ => rendered,
+        }
+    }
+}
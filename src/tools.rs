@@ -1,26 +1,214 @@
 //! All the sorting tools used by the command-line interface. Contains the main 
 //! sorting algorithm.
 
-use crate::structs::File;
-use super::messages::{error_messages, DryRunMessage, ProgressBar};
-use std::fs;
+use crate::{ignore::IgnoreFiles, observer::{JsonLinesObserver, SortObserver}, sorter::{PlannedMove, SortPlan}, structs::{Depth, File, Journal, JournalEntry, Operation, SortAction, JOURNAL_FILE_NAME}, transfer};
+use super::messages::{error_messages, DryRunMessage, ProgressBar, RemoveMessage};
+use std::{collections::HashSet, fs, io, os::unix::{self, fs::MetadataExt}, path::{Component, Path, PathBuf}};
+use walkdir::WalkDir;
+
+#[cfg(test)]
+mod tests {
+    /// Tests for the tools outside the sorting algorithm, named after the
+    /// function they test, with the test_ prefix.
+
+    use crate::observer::SilentObserver;
+    use std::env;
+    use super::*;
+
+    /// Return a new directory for a test called `name`, with each of the
+    /// `files` in it, holding its own name.
+    pub(super) fn test_dir(name: &str, files: &[&str]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("sortery_test_{}", name));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+        }
+        fs::create_dir_all(&dir).expect("Failed to create dir.");
+        for file in files.iter() {
+            fs::create_dir_all(dir.join(file).parent().unwrap()).expect("Failed to create dir.");
+            fs::write(dir.join(file), file).expect("Failed to write file.");
+        }
+        dir
+    }
+
+    #[test]
+    fn test_extract() {
+        let dir = test_dir("extract", &["source/a.txt", "source/one/b.txt", "source/one/two/c.txt", "source/other/b.txt", "target/d.txt"]);
+        let (source, target) = (File::from_pathbuf(&dir.join("source")), File::from_pathbuf(&dir.join("target")));
+        let extract = |operation: Operation, min_depth: Option<usize>, max_depth: Option<usize>| -> Vec<(PathBuf, PathBuf, SortAction)> {
+            let plan = extract(&source, &target, operation, false, Depth { min_depth, max_depth }, &mut SilentObserver);
+            let mut moves: Vec<(PathBuf, PathBuf, SortAction)> = plan.moves.into_iter()
+                .map(|planned| (planned.from.strip_prefix(&dir).unwrap().to_path_buf(), planned.to.strip_prefix(&dir).unwrap().to_path_buf(), planned.action))
+                .collect();
+            moves.sort_by(|a, b| a.0.cmp(&b.0));
+            moves
+        };
+        let planned = |from: &str, to: &str, action: SortAction| (PathBuf::from(from), PathBuf::from(to), action);
+
+        // Only the entries directly in the source by default
+        assert_eq!(extract(Operation::Move, None, None), vec![
+            planned("source/a.txt", "target/a.txt", SortAction::Move),
+            planned("source/one", "target/one", SortAction::Move),
+            planned("source/other", "target/other", SortAction::Move),
+        ]);
+
+        // Everything two levels down, into the target itself
+        assert_eq!(extract(Operation::Move, Some(2), None), vec![
+            planned("source/one/b.txt", "target/b.txt", SortAction::Move),
+            planned("source/one/two", "target/two", SortAction::Move),
+            planned("source/other/b.txt", "target/b.txt", SortAction::Skip),
+        ]);
+
+        // The files down to the max depth, keeping the directories
+        assert_eq!(extract(Operation::Move, None, Some(3)), vec![
+            planned("source/a.txt", "target/a.txt", SortAction::Move),
+            planned("source/one/b.txt", "target/one/b.txt", SortAction::Move),
+            planned("source/one/two/c.txt", "target/one/two/c.txt", SortAction::Move),
+            planned("source/other/b.txt", "target/other/b.txt", SortAction::Move),
+        ]);
+
+        // Directories can't be hard linked, so they are skipped
+        assert_eq!(extract(Operation::HardLink, None, None), vec![
+            planned("source/a.txt", "target/a.txt", SortAction::Move),
+            planned("source/one", "target/one", SortAction::Skip),
+            planned("source/other", "target/other", SortAction::Skip),
+        ]);
+
+        fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+    }
+
+    #[test]
+    fn test_refresh_view() {
+        let dir = test_dir("refresh_view", &["source/a.txt", "source/b.txt", "source/c.txt"]);
+        fs::create_dir_all(dir.join("target/view")).expect("Failed to create dir.");
+        let target = File::from_pathbuf(&dir.join("target"));
+        let journal = Journal::new(&target);
+        for (name, operation) in [("a.txt", Operation::SymLink), ("b.txt", Operation::RelativeSymLink), ("c.txt", Operation::RelativeSymLink)].iter() {
+            let (old, new) = (dir.join("source").join(name), dir.join("target/view").join(name));
+            transfer::symbolic_link(&old, &new, *operation == Operation::RelativeSymLink).expect("Failed to make symlink.");
+            journal.record(&File::from_pathbuf(&old), &File::from_pathbuf(&new), *operation, None).expect("Failed to record.");
+        }
+
+        // Links to removed files are removed, but links that now point to
+        // other files are left alone
+        fs::remove_file(dir.join("source/b.txt")).expect("Failed to remove file.");
+        fs::remove_file(dir.join("target/view/c.txt")).expect("Failed to remove link.");
+        unix::fs::symlink(dir.join("source/a.txt"), dir.join("target/view/c.txt")).expect("Failed to make symlink.");
+        refresh_view(&target, false, false);
+        assert!(fs::symlink_metadata(dir.join("target/view/a.txt")).is_err());
+        assert!(fs::symlink_metadata(dir.join("target/view/b.txt")).is_err());
+        assert!(fs::symlink_metadata(dir.join("target/view/c.txt")).is_ok());
+        assert_eq!(Journal::read(&target).expect("Failed to read journal.").len(), 1);
+
+        // Empty directories are removed up to the target, however it's written
+        let (old, new) = (dir.join("source/a.txt"), dir.join("target/other/d.txt"));
+        fs::create_dir_all(new.parent().unwrap()).expect("Failed to create dir.");
+        transfer::symbolic_link(&old, &new, false).expect("Failed to make symlink.");
+        journal.record(&File::from_pathbuf(&old), &File::from_pathbuf(&new), Operation::SymLink, None).expect("Failed to record.");
+        refresh_view(&File::from_pathbuf(&dir.join("source/../target")), false, false);
+        assert!(!dir.join("target/other").exists());
+        assert!(dir.join("target").exists());
+        assert_eq!(Journal::read(&target).expect("Failed to read journal.").len(), 1);
+
+        fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+    }
+
+    #[test]
+    fn test_undo() {
+        let dir = test_dir("undo", &["source/one/a.txt", "source/two/b.txt"]);
+        let target = File::from_pathbuf(&dir.join("target"));
+        let journal = Journal::new(&target);
+        for name in ["one/a.txt", "two/b.txt"].iter() {
+            let (old, new) = (dir.join("source").join(name), dir.join("target").join(name));
+            fs::create_dir_all(new.parent().unwrap()).expect("Failed to create dir.");
+            fs::rename(&old, &new).expect("Failed to move file.");
+            journal.record(&File::from_pathbuf(&old), &File::from_pathbuf(&new), Operation::Move, None).expect("Failed to record.");
+        }
+
+        // A file that can't be moved back is skipped and kept in the journal
+        fs::remove_dir_all(dir.join("source/one")).expect("Failed to remove dir.");
+        fs::write(dir.join("source/one"), "one").expect("Failed to write file.");
+        undo(&target, None, false, false);
+        assert!(dir.join("target/one/a.txt").exists());
+        assert!(dir.join("source/two/b.txt").exists());
+        assert_eq!(Journal::read(&target).expect("Failed to read journal.").len(), 1);
+
+        fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+    }
+}
 
 /// The main sorting algorithm, and all the functions it needs to operate.
 pub mod sort {
 
     // use super::super::messages::error_messages;
-    use chrono::{DateTime, TimeZone, Utc, Local};
-    use crate::{error_messages, messages::{DryRunMessage, ProgressBar}, structs::{ConfigData, File}};
-    use std::{fs, path::Path, time::UNIX_EPOCH};
-    use walkdir::WalkDir;
+    use chrono::{format::{Item, StrftimeItems}, DateTime, Datelike, TimeZone, Utc, Local};
+    use crate::{filter::{CompiledFilter, PathPattern}, ignore::IgnoreFiles, metadata::{exif, filename, quicktime}, observer::SortObserver, perceptual, sorter::{PlannedMove, SortError, Sorter}, structs::{Categories, ConflictPolicy, DuplicatePolicy, File, Rule, SimilarPolicy, Similarity, SortAction, SortBy, SymlinkPolicy, DUPLICATES_DIR_NAME, JOURNAL_FILE_NAME, SIMILAR_DIR_NAME, TRASH_DIR_NAME}, template::Template};
+    use std::{cmp::Reverse, collections::{HashMap, HashSet}, fs, io::{ErrorKind, Read}, os::unix::fs::FileTypeExt, path::{Component, Path, PathBuf}, time::UNIX_EPOCH};
+    use walkdir::{DirEntry, WalkDir};
 
     #[cfg(test)]
     mod tests {
         /// Tests for tools. Each test function is named after the function in
         /// tools it tests, with the test_ prefix.
 
-        use std::{env, path::Path};
-        use super::*;
+        use crate::{filter::Filter, observer::SilentObserver, structs::Depth};
+        use std::{env, fs, path::Path};
+        use super::{super::tests::test_dir, *};
+
+        #[test]
+        fn test_format_dir() {
+            let ctime = Local.ymd(2021, 8, 3).and_hms(14, 22, 33);
+            assert_eq!(format_dir(&ctime, DEFAULT_DIR_FORMAT), "2021/08/");
+            assert_eq!(format_dir(&ctime, "%Y/%Y-%m-%d"), "2021/2021-08-03");
+            assert_eq!(format_dir(&ctime, "%Y/Q{quarter}"), "2021/Q3");
+            assert_eq!(format_dir(&ctime, "%G/H{half}/W{week}"), "2021/H2/W31");
+            assert_eq!(format_dir(&Local.ymd(2021, 1, 1).and_hms(0, 0, 0), "%G/W{week}"), "2020/W53");
+            assert_eq!(format_dir(&ctime, "%Y/W%V"), "2021/W31");
+            assert_eq!(format_dir(&ctime, ""), "");
+        }
+
+        #[test]
+        fn test_is_valid_format() {
+            assert!(is_valid_format("%Y/Q{quarter}"));
+            assert!(is_valid_format(""));
+            assert!(!is_valid_format("%Y/%Q"));
+            assert!(!is_valid_format("/%Y/%m"));
+            assert!(!is_valid_format("%Y/../../%m"));
+        }
+
+        #[test]
+        fn test_get_new_date_path() {
+            let path = File::from_pathbuf(&env::current_dir().expect("Failed to get current dir.")).join(Path::new("testing/test.jpg"));
+            let target = File::new("target");
+            let no_patterns: Vec<String> = Vec::new();
+            let template = Template::parse("{date:%Y}_{counter:02}.{ext|upper}").unwrap();
+            let mut counters = HashMap::new();
+            let naming = Naming {
+                date_format: "%Y",
+                dir_format: "",
+                date_type: "m",
+                name_patterns: &no_patterns,
+                name_fallback: "m",
+                preserve_name: true,
+                name_template: None,
+            };
+            let mut new_path = |name_template| {
+                get_new_date_path(&target, &path, &Naming { name_template, ..naming }, &mut counters).0
+            };
+
+            let year = get_datetime(&path, "m", &no_patterns, "m").unwrap().0.format("%Y").to_string();
+            assert_eq!(new_path(None), target.join_string(&format!("{} test.jpg", year)));
+            assert_eq!(new_path(Some(&template)), target.join_string(&format!("{}_01.JPG", year)));
+            assert_eq!(new_path(Some(&template)), target.join_string(&format!("{}_02.JPG", year)));
+        }
+
+        #[test]
+        fn test_get_new_category_path() {
+            let categories = Categories::new(&HashMap::new());
+            let target = File::new("target");
+
+            assert_eq!(get_new_category_path(&target, &File::new("a/b.jpg"), &categories), File::new("target/Images/b.jpg"));
+            assert_eq!(get_new_category_path(&target, &File::new("a/b.xyz"), &categories), File::new("target/Other/b.xyz"));
+        }
 
         #[test]
         fn test_get_sequential_name() {
@@ -37,27 +225,20 @@ pub mod sort {
         #[test]
         fn test_get_sorting_results() {
 
-            // The parameters for testing
+            // The sorter for testing
             let source = File::from_pathbuf(&env::current_dir().expect("Failed to get current dir"));
             let source = source.join(Path::new("testing"));
             let target = source.join(Path::new("target"));
-            let date_format = "%Y";
-            let date_type = "m";
-            let preserve_name = true;
-            let exclude_type = ("txt", true);
-            let only_type = ("", false);
-            
+            let sorter = Sorter::new(source.copy(), target)
+                .date_format("%Y")
+                .date_type("m")
+                .preserve_name(true)
+                .exclude_type("txt");
+
             // Get the sorting results
-            let results = get_sorting_results(
-                &source,
-                &target,
-                &date_format,
-                &date_type,
-                &preserve_name,
-                exclude_type,
-                only_type
-            );
-            let (old, new) = (&results.1, &results.2);
+            let results = get_sorting_results(&sorter, &mut SilentObserver).unwrap();
+            let old: Vec<File> = results.iter().map(|planned| File::from_pathbuf(&planned.from)).collect();
+            let new: Vec<File> = results.iter().map(|planned| File::from_pathbuf(&planned.to)).collect();
             
             // Print all the options in case of a test failure
             for i in 0..4 {
@@ -69,90 +250,618 @@ pub mod sort {
             assert_eq!((old[1].copy(), new[1].copy()), (source.join(Path::new("test")), source.join(Path::new("target/2021/02/2021 test."))));
             assert_eq!((old[2].copy(), new[2].copy()), (source.join(Path::new("files/test")), source.join(Path::new("target/2021/02/2021 test_2."))));
             assert_eq!((old[3].copy(), new[3].copy()), (source.join(Path::new("test.png")), source.join(Path::new("target/2021/02/2021 test.png"))));
-            assert_eq!(results.0, 4);
-            assert_eq!(old.len(), 4);
-            assert_eq!(new.len(), 4);
+            assert_eq!(results.len(), 5);
+            assert_eq!((results[4].from.clone(), results[4].action), (source.join(Path::new("test.txt")).to_path_buf(), SortAction::SkipFiltered));
+            assert_eq!(results[4].skip_reason.as_deref(), Some("matches ext:txt"));
+            let results = &results[..4];
+            assert!(results.iter().all(|planned| planned.action == SortAction::Move));
+            assert!(results.iter().all(|planned| planned.date_source.as_deref() == Some("m")));
+            assert!(results.iter().all(|planned| planned.rule.is_none()));
+            assert!(results.iter().all(|planned| planned.duplicate_of.is_none()));
+            assert!(results.iter().all(|planned| planned.similar_to.is_none()));
+        }
+
+        #[test]
+        fn test_get_sorting_results_exclude_paths() {
+            let dir = test_dir("exclude_paths", &["source/a.jpg", "source/.git/objects/b", "source/web/node_modules/c.js", "source/photos/@eaDir/d.jpg", "source/photos/e.jpg"]);
+            let sorter = Sorter::new(File::from_pathbuf(&dir.join("source")), File::from_pathbuf(&dir.join("target")))
+                .exclude_path(".git")
+                .exclude_path("**/node_modules")
+                .exclude_path("regex:(^|/)@eaDir$");
+
+            let results = get_sorting_results(&sorter, &mut SilentObserver).unwrap();
+            let mut sorted: Vec<PathBuf> = results.iter().map(|planned| planned.from.clone()).collect();
+            sorted.sort();
+            assert_eq!(sorted, vec![dir.join("source/a.jpg"), dir.join("source/photos/e.jpg")]);
+
+            fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+        }
+
+        #[test]
+        fn test_get_sorting_results_ignore_files() {
+            let dir = test_dir("ignore_files", &["source/a.jpg", "source/b.tmp", "source/build/c.jpg", "source/photos/d.tmp", "source/photos/e.log"]);
+            fs::write(dir.join("source/.sorteryignore"), "*.tmp\n/build/\n").expect("Failed to write file.");
+            fs::write(dir.join("source/photos/.sorteryignore"), "!d.tmp\n").expect("Failed to write file.");
+            fs::write(dir.join("source/photos/.gitignore"), "*.log\n").expect("Failed to write file.");
+            let sorter = Sorter::new(File::from_pathbuf(&dir.join("source")), File::from_pathbuf(&dir.join("target")));
+
+            let results = get_sorting_results(&sorter, &mut SilentObserver).unwrap();
+            let actions: Vec<(PathBuf, SortAction, Option<String>)> = results.into_iter()
+                .map(|planned| (planned.from, planned.action, planned.skip_reason))
+                .collect();
+            assert_eq!(actions.len(), 6);
+            assert!(actions.contains(&(dir.join("source/photos/d.tmp"), SortAction::Move, None)));
+            assert!(actions.contains(&(dir.join("source/photos/e.log"), SortAction::Move, None)));
+            assert!(actions.contains(&(dir.join("source/photos/.gitignore"), SortAction::Move, None)));
+            assert!(actions.contains(&(dir.join("source/b.tmp"), SortAction::SkipIgnored, Some(String::from("matches *.tmp in .sorteryignore")))));
+            assert!(actions.contains(&(dir.join("source/build"), SortAction::SkipIgnored, Some(String::from("matches /build/ in .sorteryignore")))));
+
+            let results = get_sorting_results(&sorter.gitignore(true), &mut SilentObserver).unwrap();
+            let ignored = results.iter().find(|planned| planned.from == dir.join("source/photos/e.log")).unwrap();
+            assert_eq!((ignored.action, ignored.skip_reason.as_deref()), (SortAction::SkipIgnored, Some("matches *.log in photos/.gitignore")));
+            assert!(results.iter().all(|planned| planned.from != dir.join("source/photos/.gitignore")));
+
+            fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+        }
+
+        #[test]
+        fn test_get_sorting_results_depth() {
+            let dir = test_dir("depth", &["source/a.jpg", "source/one/b.jpg", "source/one/two/c.jpg"]);
+            let sorted = |min_depth: Option<usize>, max_depth: Option<usize>| -> Vec<PathBuf> {
+                let sorter = Sorter::new(File::from_pathbuf(&dir.join("source")), File::from_pathbuf(&dir.join("target")))
+                    .depth(Depth { min_depth, max_depth });
+                let mut sorted: Vec<PathBuf> = get_sorting_results(&sorter, &mut SilentObserver).unwrap().into_iter()
+                    .map(|planned| planned.from.strip_prefix(dir.join("source")).unwrap().to_path_buf())
+                    .collect();
+                sorted.sort();
+                sorted
+            };
+
+            assert_eq!(sorted(None, None), vec![PathBuf::from("a.jpg"), PathBuf::from("one/b.jpg"), PathBuf::from("one/two/c.jpg")]);
+            assert_eq!(sorted(None, Some(1)), vec![PathBuf::from("a.jpg")]);
+            assert_eq!(sorted(Some(2), None), vec![PathBuf::from("one/b.jpg"), PathBuf::from("one/two/c.jpg")]);
+            assert_eq!(sorted(Some(2), Some(2)), vec![PathBuf::from("one/b.jpg")]);
+
+            // Directories above the min depth are still left out when they
+            // are excluded, ignored, or the target
+            for name in ["source/.git/HEAD", "source/cache/d.jpg", "source/sorted/2021/e.jpg"].iter() {
+                fs::create_dir_all(dir.join(name).parent().unwrap()).expect("Failed to create dir.");
+                fs::write(dir.join(name), name).expect("Failed to write file.");
+            }
+            fs::write(dir.join("source/.sorteryignore"), "cache/\n").expect("Failed to write file.");
+            let sorter = Sorter::new(File::from_pathbuf(&dir.join("source")), File::from_pathbuf(&dir.join("source/sorted")))
+                .exclude_path("**/.git")
+                .depth(Depth { min_depth: Some(2), max_depth: None });
+            let results = get_sorting_results(&sorter, &mut SilentObserver).unwrap();
+            let mut sorted: Vec<PathBuf> = results.iter()
+                .filter(|planned| planned.action.moves())
+                .map(|planned| planned.from.strip_prefix(dir.join("source")).unwrap().to_path_buf())
+                .collect();
+            sorted.sort();
+            assert_eq!(sorted, vec![PathBuf::from("one/b.jpg"), PathBuf::from("one/two/c.jpg")]);
+            assert!(results.iter().any(|planned| planned.action == SortAction::SkipIgnored && planned.from == dir.join("source/cache")));
+
+            fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+        }
+
+        #[test]
+        fn test_get_sorting_results_symlinks() {
+            let dir = test_dir("symlinks", &["source/a.jpg", "outside/b.jpg", "outside/inner/c.jpg"]);
+            fs::create_dir_all(dir.join("target")).expect("Failed to create dir.");
+            std::os::unix::fs::symlink(dir.join("outside/b.jpg"), dir.join("source/file")).expect("Failed to make symlink.");
+            std::os::unix::fs::symlink(dir.join("outside/inner"), dir.join("source/dir")).expect("Failed to make symlink.");
+            std::os::unix::fs::symlink(dir.join("nothing"), dir.join("source/broken")).expect("Failed to make symlink.");
+            let _socket = std::os::unix::net::UnixListener::bind(dir.join("source/socket")).expect("Failed to make socket.");
+
+            let source = File::from_pathbuf(&dir.join("source"));
+            let sorter = || Sorter::new(source.copy(), File::from_pathbuf(&dir.join("target")));
+            let actions = |sorter: &Sorter| -> HashMap<String, (SortAction, Option<String>, Option<PathBuf>)> {
+                get_sorting_results(sorter, &mut SilentObserver).unwrap().into_iter()
+                    .map(|planned| (
+                        planned.from.strip_prefix(&source.pathbuf).unwrap().to_string_lossy().to_string(),
+                        (planned.action, planned.skip_reason, planned.resolved),
+                    ))
+                    .collect()
+            };
+            let skipped = |reason: &str| (SortAction::SkipSpecial, Some(String::from(reason)), None);
+            let moved = (SortAction::Move, None, None);
+
+            // Symlinks are sorted like any other file by default
+            let results = actions(&sorter());
+            assert_eq!(results.len(), 5);
+            assert_eq!(results["file"], moved);
+            assert_eq!(results["dir"], moved);
+            assert_eq!(results["broken"], moved);
+            assert_eq!(results["socket"], skipped("socket"));
+
+            // Or left where they are
+            let results = actions(&sorter().symlinks(SymlinkPolicy::Skip));
+            assert_eq!(results["a.jpg"], moved);
+            assert_eq!(results["file"], skipped(&format!("symlink to {}", dir.join("outside/b.jpg").display())));
+            assert_eq!(results["broken"], skipped(&format!("symlink to {}", dir.join("nothing").display())));
+
+            // Or resolved, except for directories and broken symlinks
+            let results = actions(&sorter().symlinks(SymlinkPolicy::Resolve));
+            assert_eq!(results["file"], (SortAction::Move, None, Some(fs::canonicalize(dir.join("outside/b.jpg")).unwrap())));
+            assert_eq!(results["dir"], skipped(&format!("symlink to directory {}", dir.join("outside/inner").display())));
+            assert_eq!(results["broken"], skipped(&format!("broken symlink to {}", dir.join("nothing").display())));
+
+            // Followed symlinks are walked into, and loops are left out
+            std::os::unix::fs::symlink(dir.join("source"), dir.join("outside/inner/loop")).expect("Failed to make symlink.");
+            let results = actions(&sorter().follow_symlinks(true));
+            assert_eq!(results["dir/c.jpg"], moved);
+            assert_eq!(results["broken"], skipped("broken symlink"));
+            assert_eq!(results["dir/loop"], skipped(&format!("symlink loop back to {}", dir.join("source").display())));
+            fs::remove_file(dir.join("outside/inner/loop")).expect("Failed to remove symlink.");
+
+            // Resolved symlinks are replaced by a copy of what they point to
+            sorter().symlinks(SymlinkPolicy::Resolve).plan().expect("Failed to plan.").execute_with(&mut SilentObserver).expect("Failed to sort.");
+            let sorted: Vec<PathBuf> = WalkDir::new(dir.join("target")).into_iter()
+                .map(|entry| entry.unwrap().into_path())
+                .filter(|path| path.is_file() && fs::read_to_string(path).is_ok_and(|contents| contents == "outside/b.jpg"))
+                .collect();
+            assert_eq!(sorted.len(), 1);
+            assert!(!sorted[0].is_symlink());
+            assert!(!dir.join("source/file").exists());
+            assert!(dir.join("outside/b.jpg").exists());
+
+            // Undoing that puts the symlink back
+            crate::tools::undo(&File::from_pathbuf(&dir.join("target")), None, false, false);
+            assert_eq!(fs::read_link(dir.join("source/file")).ok(), Some(dir.join("outside/b.jpg")));
+            assert!(!sorted[0].exists());
+
+            fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+        }
+
+        #[test]
+        fn test_get_sorting_results_target_in_source() {
+            let dir = test_dir("target_in_source", &["source/a.jpg", "source/sorted/2021/b.jpg"]);
+            std::os::unix::fs::symlink(dir.join("source/sorted"), dir.join("link")).expect("Failed to make symlink.");
+
+            // The target is left out, however it is written
+            for target in [dir.join("source/sorted"), dir.join("source/../source/sorted/"), dir.join("link")].iter() {
+                let sorter = Sorter::new(File::from_pathbuf(&dir.join("source")), File::from_pathbuf(target));
+                let results = get_sorting_results(&sorter, &mut SilentObserver).unwrap();
+                let sorted: Vec<PathBuf> = results.into_iter().map(|planned| planned.from).collect();
+                assert_eq!(sorted, vec![dir.join("source/a.jpg")]);
+            }
+
+            fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+        }
+
+        #[test]
+        fn test_get_sorting_results_paths() {
+            let dir = test_dir("sorting_paths", &["source/a.jpg", "source/b.jpg", "source/new/c.jpg", "source/new/deeper/d.jpg", "source/old/e.jpg"]);
+            fs::create_dir_all(dir.join("target")).expect("Failed to create dir.");
+
+            // Only the paths, and the files in the directories among them, are sorted
+            let sorter = Sorter::new(File::from_pathbuf(&dir.join("source")), File::from_pathbuf(&dir.join("target")))
+                .paths(&[dir.join("source/b.jpg"), dir.join("source/new"), dir.join("source/missing.jpg")]);
+            let results = get_sorting_results(&sorter, &mut SilentObserver).unwrap();
+            let mut sorted: Vec<PathBuf> = results.into_iter().map(|planned| planned.from).collect();
+            sorted.sort();
+            assert_eq!(sorted, vec![dir.join("source/b.jpg"), dir.join("source/new/c.jpg"), dir.join("source/new/deeper/d.jpg")]);
+
+            fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+        }
+
+        #[test]
+        fn test_find_duplicates() {
+            let dir = test_dir("find_duplicates", &[]);
+            fs::create_dir_all(dir.join("source")).expect("Failed to create dir.");
+            fs::create_dir_all(dir.join("target/2021")).expect("Failed to create dir.");
+            for (name, contents) in [
+                ("target/2021/old.jpg", "photo"),
+                ("source/a.jpg", "photo"),
+                ("source/b.jpg", "other"),
+                ("source/c.jpg", "other"),
+                ("source/d.jpg", "third"),
+                ("source/e.txt", ""),
+                ("source/f.txt", ""),
+            ].iter() {
+                fs::write(dir.join(name), contents).expect("Failed to write file.");
+            }
+            let items: Vec<File> = ["a.jpg", "b.jpg", "c.jpg", "d.jpg", "e.txt", "f.txt"].iter()
+                .map(|name| File::from_pathbuf(&dir.join("source").join(name)))
+                .collect();
+
+            let duplicates = find_duplicates(&items, &File::from_pathbuf(&dir.join("target")));
+            assert_eq!(duplicates[0], Some(Original::Existing(File::from_pathbuf(&dir.join("target/2021/old.jpg")))));
+            assert_eq!(duplicates[1], None);
+            assert_eq!(duplicates[2], Some(Original::Sorted(1)));
+            assert_eq!(duplicates[3], None);
+            assert_eq!(duplicates[4], None);
+            assert_eq!(duplicates[5], None);
+
+            fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+        }
+
+        #[test]
+        fn test_find_similar() {
+            let dir = test_dir("find_similar", &[]);
+
+            // Three sizes of a diagonal gradient, a copy of the largest one and
+            // the gradient the other way round
+            let write_png = |name: &str, size: u32, flip: bool| {
+                let file = fs::File::create(dir.join(name)).expect("Failed to create file.");
+                let mut encoder = png::Encoder::new(file, size, size);
+                encoder.set_color(png::ColorType::Grayscale);
+                let mut writer = encoder.write_header().expect("Failed to write header.");
+                let pixels: Vec<u8> = (0..size * size)
+                    .map(|i| ((i % size + i / size) * 127 / size) as u8)
+                    .map(|value| if flip { 255 - value } else { value })
+                    .collect();
+                writer.write_image_data(&pixels).expect("Failed to write image.");
+            };
+            write_png("small.png", 16, false);
+            write_png("large.png", 64, false);
+            write_png("medium.png", 32, false);
+            write_png("copy.png", 64, false);
+            write_png("flipped.png", 48, true);
+            let items: Vec<File> = ["small.png", "large.png", "medium.png", "copy.png", "flipped.png", "small.png"].iter()
+                .map(|name| File::from_pathbuf(&dir.join(name)))
+                .collect();
+            let mut duplicates: Vec<Option<Original>> = items.iter().map(|_| None).collect();
+            duplicates[3] = Some(Original::Sorted(1));
+
+            let similarity = Similarity { action: SimilarPolicy::Report, ..Similarity::default() };
+            let similar = find_similar(&items, &duplicates, similarity);
+            assert_eq!(similar, vec![Some(1), None, Some(1), None, None, Some(1)]);
+            let similar = find_similar(&items, &duplicates, Similarity { distance: 64, ..similarity });
+            assert_eq!(similar, vec![Some(1), None, Some(1), None, Some(1), Some(1)]);
+
+            fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+        }
+
+        #[test]
+        fn test_group_similar() {
+
+            // The middle image is close to both of the others, but they are too
+            // far apart to go together
+            let images = vec![(0, 0b0000, (10, 0)), (1, 0b0011, (20, 0)), (2, 0b1111, (30, 0))];
+            assert_eq!(group_similar(images, 2), vec![(1, 2)]);
+
+            // Equally good images are kept by their index
+            let images = vec![(3, 0b0000, (10, 5)), (1, 0b0001, (10, 5)), (2, 0b0011, (10, 6))];
+            assert_eq!(group_similar(images, 2), vec![(1, 2), (3, 2)]);
+            assert_eq!(group_similar(Vec::new(), 2), Vec::new());
+        }
+
+        #[test]
+        fn test_get_rule() {
+            let rules: Vec<Rule> = serde_json::from_str(r#"[
+                {"extensions": ["json"], "older_than": "100y", "destination": "Ancient"},
+                {"pattern": "*.toml", "destination": "Config", "date_type": "filename"},
+                {"extensions": ["json", "toml"], "min_size": "1", "newer_than": "100y", "destination": "Data"}
+            ]"#).expect("Failed to parse rules.");
+            let rule_filters: Vec<CompiledFilter> = rules.iter().map(|rule| rule.filter().compile().unwrap()).collect();
+            let no_patterns: Vec<String> = Vec::new();
+            let get_rule = |path: &str| get_rule(&File::new(path), &rules, &rule_filters, "m", &no_patterns, "m");
+
+            assert_eq!(get_rule("template.json"), Some(2));
+            assert_eq!(get_rule("Cargo.toml"), Some(1));
+            assert_eq!(get_rule("README.md"), None);
+            assert_eq!(get_rule("nonexistent.json"), None);
+        }
+
+        #[test]
+        fn test_get_date_sources() {
+            assert_eq!(get_date_sources("exif,filename,c,m", "a"), vec!["exif", "filename", "c", "m"]);
+            assert_eq!(get_date_sources(" exif , m ", "a"), vec!["exif", "m"]);
+            assert_eq!(get_date_sources("exif", "a"), vec!["exif", "m"]);
+            assert_eq!(get_date_sources("filename", "a"), vec!["filename", "a"]);
+            assert_eq!(get_date_sources("c", "a"), vec!["c"]);
+        }
+
+        #[test]
+        fn test_get_datetime() {
+            let path = File::from_pathbuf(&env::current_dir().expect("Failed to get current dir.")).join(Path::new("testing/test.jpg"));
+            let no_patterns: Vec<String> = Vec::new();
+
+            assert_eq!(get_datetime(&path, "exif,video,m", &no_patterns, "m").map(|dated| dated.1), Some("m"));
+            assert_eq!(get_datetime(&path, "exif,video,filename", &no_patterns, "m"), None);
+            let naming = Naming {
+                date_format: "%Y",
+                dir_format: DEFAULT_DIR_FORMAT,
+                date_type: "exif,video",
+                name_patterns: &no_patterns,
+                name_fallback: "m",
+                preserve_name: false,
+                name_template: None,
+            };
+            assert!(get_new_date_path(&File::new("target"), &path, &naming, &mut HashMap::new())
+                == (File::new("target/undated/test.jpg"), UNDATED_DIR_NAME));
+        }
+
+        #[test]
+        fn test_is_valid_date_type() {
+            assert!(is_valid_date_type("exif,filename,c,m"));
+            assert!(is_valid_date_type("video"));
+            assert!(!is_valid_date_type("exif,modified"));
+            assert!(!is_valid_date_type(""));
+        }
+
+        #[test]
+        fn test_get_conflict_action() {
+            let parent_dir = File::from_pathbuf(&env::current_dir().expect("Failed to get current dir."));
+            let path = parent_dir.join(Path::new("testing/test.jpg"));
+            let identical = parent_dir.join(Path::new("testing/test.png"));
+            let different = parent_dir.join(Path::new("template.json"));
+
+            assert_eq!(get_conflict_action(&path, &different, ConflictPolicy::Rename), SortAction::Rename);
+            assert_eq!(get_conflict_action(&path, &different, ConflictPolicy::Skip), SortAction::Skip);
+            assert_eq!(get_conflict_action(&path, &different, ConflictPolicy::Overwrite), SortAction::Overwrite);
+            assert_eq!(get_conflict_action(&path, &identical, ConflictPolicy::SkipIdentical), SortAction::Skip);
+            assert_eq!(get_conflict_action(&path, &different, ConflictPolicy::SkipIdentical), SortAction::Rename);
         }
 
         #[test]
-        fn test_is_sortable() {
-            let path = File::new("file.txt");
+        fn test_is_identical() {
+            let parent_dir = File::from_pathbuf(&env::current_dir().expect("Failed to get current dir."));
+            let path = parent_dir.join(Path::new("testing/test.jpg"));
 
-            assert!(is_sortable(&path, &(&"", false), &(&"txt", true)));
-            assert!(is_sortable(&path, &(&"txt", true), &(&"txt", true)));
-            assert!(is_sortable(&path, &(&"", false), &(&"", false)));
-            assert!(!is_sortable(&path, &(&"txt", true), &(&"", false)));
+            assert!(is_identical(&path, &parent_dir.join(Path::new("testing/test.png"))));
+            assert!(is_identical(&path, &path));
+            assert!(!is_identical(&path, &parent_dir.join(Path::new("template.json"))));
         }
 
         #[test]
-        fn test_is_type() {
-            let path = File::new("file.txt");
-            assert!(is_type(&path, &"txt"));
+        fn test_get_skip_reason() {
+            let path = File::new("testing/files/test");
+            let relative = Path::new("files/test");
+            let no_patterns: Vec<String> = Vec::new();
+            let get_skip_reason = |filters: &[Filter]| {
+                let filters: Vec<CompiledFilter> = filters.iter().map(|filter| filter.compile().unwrap()).collect();
+                get_skip_reason(&path, relative, &filters, "m", &no_patterns, "m")
+            };
+
+            assert_eq!(get_skip_reason(&[]), None);
+            assert_eq!(get_skip_reason(&[Filter::Not(Box::new(Filter::from_types("txt").unwrap()))]), None);
+            assert_eq!(get_skip_reason(&[Filter::from_types("txt").unwrap()]), Some((String::from("doesn't match ext:txt"), None)));
+            assert_eq!(get_skip_reason(&[Filter::Glob(String::from("files/*")), Filter::Extension(vec![String::new()])]), None);
+            assert_eq!(
+                get_skip_reason(&[Filter::Glob(String::from("files/*")), Filter::Since(String::from("9999-01-01"))]),
+                Some((String::from("before 9999-01-01"), Some("m")))
+            );
         }
     }
     
+    /// The date sources that can be used in a `date_type` chain.
+    pub const DATE_SOURCES: [&str; 6] = ["c", "a", "m", "exif", "video", "filename"];
+
+    /// The default directory layout, as in `2021/02/`.
+    pub const DEFAULT_DIR_FORMAT: &str = "%Y/%m/";
+
+    /// The tokens that can be used in directory formats in addition to the
+    /// strftime ones.
+    pub const DIR_FORMAT_TOKENS: [&str; 3] = ["{quarter}", "{half}", "{week}"];
+
+    /// The name of the directory in the target that files are sorted into when
+    /// none of the date sources have a date for them.
+    pub const UNDATED_DIR_NAME: &str = "undated";
+
     /// Return the access date and time of `path` as the number of seconds since the
-    /// UNIX epoch.
-    fn get_epoch_secs_access(path: &File) -> i64 {
-        let ctime_system = path.pathbuf.metadata().unwrap().accessed().expect("Failed to get atime");
-        let secs: i64 = ctime_system.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    /// UNIX epoch, or [`None`] if it isn't available.
+    fn get_epoch_secs_access(path: &File) -> Option<i64> {
+        let ctime_system = path.pathbuf.metadata().ok()?.accessed().ok()?;
+        let secs: i64 = ctime_system.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
 
-        secs
+        Some(secs)
     }
     
     /// Return the creation date and time of `path` as the number of seconds since the
-    /// UNIX epoch.
-    fn get_epoch_secs_creation(path: &File) -> i64 {
-        let ctime_system = path.pathbuf.metadata().unwrap().created().expect("Failed to get ctime");
-        let secs: i64 = ctime_system.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    /// UNIX epoch, or [`None`] if the filesystem doesn't keep creation times.
+    fn get_epoch_secs_creation(path: &File) -> Option<i64> {
+        let ctime_system = path.pathbuf.metadata().ok()?.created().ok()?;
+        let secs: i64 = ctime_system.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
 
-        secs
+        Some(secs)
     }
 
     /// Return the modification date and time of `path` as the number of seconds since the
-    /// UNIX epoch.
-    fn get_epoch_secs_modified(path: &File) -> i64 {
-        let ctime_system = path.pathbuf.metadata().unwrap().modified().expect("Failed to get mtime");
-        let secs: i64 = ctime_system.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    /// UNIX epoch, or [`None`] if it isn't available.
+    fn get_epoch_secs_modified(path: &File) -> Option<i64> {
+        let ctime_system = path.pathbuf.metadata().ok()?.modified().ok()?;
+        let secs: i64 = ctime_system.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
 
-        secs
+        Some(secs)
     }
 
-    /// Return a [`DateTime`] instance representing the creation, modification,
-    /// or access time of `path` according to `date_type`.
+    /// Return the [`SortAction`] to take when moving `path` to `existing`, which
+    /// already exists, according to `conflict_policy`.
+    fn get_conflict_action(path: &File, existing: &File, conflict_policy: ConflictPolicy) -> SortAction {
+        match conflict_policy {
+            ConflictPolicy::Rename => SortAction::Rename,
+            ConflictPolicy::Skip => SortAction::Skip,
+            ConflictPolicy::Overwrite => SortAction::Overwrite,
+            ConflictPolicy::KeepNewer => {
+                if get_epoch_secs_modified(path) > get_epoch_secs_modified(existing) {
+                    SortAction::Overwrite
+                } else {
+                    SortAction::Skip
+                }
+            },
+            ConflictPolicy::SkipIdentical => {
+                if is_identical(path, existing) {
+                    SortAction::Skip
+                } else {
+                    SortAction::Rename
+                }
+            },
+        }
+    }
+
+    /// Return the date sources in the `date_type` chain, in the order they are
+    /// tried. A chain is a comma-separated list of [`DATE_SOURCES`], as in
+    /// `"exif,filename,c,m"`.
     /// 
-    /// `date_type` must be one of `"c"` (created), `"a"` (accessed), or `"m"` (modified).
-    /// Note that creation time is not available on all filesystems.
-    fn get_datetime(path: &File, date_type: &str) -> DateTime<Local> {
-        let secs: i64;
-        if date_type == "m" {
-            secs = get_epoch_secs_modified(path);
-        } else if date_type == "a" {
-            secs = get_epoch_secs_access(path);
-        } else {
-            secs = get_epoch_secs_creation(path);
+    /// For compatibility, a chain of just `"exif"` or `"video"` falls back to
+    /// the modification time, and a chain of just `"filename"` falls back to
+    /// `name_fallback`.
+    fn get_date_sources<'a>(date_type: &'a str, name_fallback: &'a str) -> Vec<&'a str> {
+        let mut sources: Vec<&str> = date_type.split(',')
+            .map(|source| source.trim())
+            .filter(|source| !source.is_empty())
+            .collect();
+        if sources.len() == 1 {
+            match sources[0] {
+                "exif" | "video" => sources.push("m"),
+                "filename" => sources.push(name_fallback),
+                _ => {},
+            }
         }
+        sources
+    }
+
+    /// Return [`true`] if every date source in the `date_type` chain is one of
+    /// the [`DATE_SOURCES`].
+    pub fn is_valid_date_type(date_type: &str) -> bool {
+        let sources = get_date_sources(date_type, "m");
+        !sources.is_empty() && sources.iter().all(|source| DATE_SOURCES.contains(source))
+    }
+
+    /// Return the date of `path` according to the single date `source`, or
+    /// [`None`] if `source` has no date for `path`.
+    /// 
+    /// `source` is one of `"c"` (created), `"a"` (accessed), `"m"` (modified),
+    /// `"exif"` (captured, from the EXIF metadata of JPEG and TIFF images),
+    /// `"video"` (recorded, from the metadata of MP4 and QuickTime movies), or
+    /// `"filename"` (the date in the file name, using `name_patterns` before the
+    /// built-in patterns). Note that creation time is not available on all filesystems.
+    fn get_source_datetime(path: &File, source: &str, name_patterns: &[String]) -> Option<DateTime<Local>> {
+        let secs = match source {
+            "exif" => return exif::get_datetime(path),
+            "video" => return quicktime::get_datetime(path),
+            "filename" => return filename::get_datetime(path, name_patterns),
+            "m" => get_epoch_secs_modified(path)?,
+            "a" => get_epoch_secs_access(path)?,
+            "c" => get_epoch_secs_creation(path)?,
+            _ => return None,
+        };
         let ctime = Utc.timestamp(secs, 0);
-        let mytime = Local.from_utc_datetime(&ctime.naive_utc());
+        Some(Local.from_utc_datetime(&ctime.naive_utc()))
+    }
+
+    /// Return a [`DateTime`] instance representing the date of `path` according
+    /// to `date_type`, along with the date source it came from. Each source in
+    /// the `date_type` chain is tried in turn (see [`get_date_sources`]), and
+    /// [`None`] is returned if none of them has a date for `path`.
+    /// 
+    /// `name_patterns` are the chrono-style patterns tried before the built-in
+    /// ones when reading dates from file names, and `name_fallback` is the
+    /// date source used for files without a date in their name when `date_type`
+    /// is just `"filename"`.
+    fn get_datetime<'a>(
+        path: &File,
+        date_type: &'a str,
+        name_patterns: &[String],
+        name_fallback: &'a str) -> Option<(DateTime<Local>, &'a str)> {
+
+        get_date_sources(date_type, name_fallback)
+            .into_iter()
+            .find_map(|source| Some((get_source_datetime(path, source, name_patterns)?, source)))
+    }
 
-        mytime
+    /// Return the directory path for `ctime` according to `dir_format`. This is
+    /// a strftime format, as in `"%Y/%m/"`, with the extra tokens `{quarter}`
+    /// (1 to 4), `{half}` (the half-year, 1 or 2) and `{week}` (the two-digit
+    /// ISO week, like `%V`). The ISO week goes with the ISO year, `%G`, rather
+    /// than `%Y`: 2021-01-01 is in week 53 of 2020, so `"%G/W{week}"` puts it in
+    /// `2020/W53`, where `"%Y/W{week}"` would put it in `2021/W53`. An empty
+    /// format puts all files directly in the target.
+    fn format_dir(ctime: &DateTime<Local>, dir_format: &str) -> String {
+        let dir_format = dir_format
+            .replace("{quarter}", &((ctime.month() - 1) / 3 + 1).to_string())
+            .replace("{half}", &((ctime.month() - 1) / 6 + 1).to_string())
+            .replace("{week}", &format!("{:02}", ctime.iso_week().week()));
+        ctime.format(&dir_format).to_string()
     }
 
-    /// Move `file` into a set of directories in yyyy/mm/ format according to its
-    /// creation time. 
+    /// Return [`true`] if `format` is a valid strftime format, once the extra
+    /// [`DIR_FORMAT_TOKENS`] are taken out, that stays inside the target: it
+    /// can't be an absolute path, or have `..` in it.
+    pub fn is_valid_format(format: &str) -> bool {
+        if Path::new(format).components().any(|component| matches!(component, Component::RootDir | Component::ParentDir)) {
+            return false;
+        }
+        let mut format = String::from(format);
+        for token in DIR_FORMAT_TOKENS.iter() {
+            format = format.replace(token, "");
+        }
+        !StrftimeItems::new(&format).any(|item| item == Item::Error)
+    }
+
+    /// The file that a duplicate found by [`find_duplicates`] duplicates:
+    /// either a file already in the target, or an earlier file in the same
+    /// sort, by its index.
+    #[derive(Debug)]
+    #[derive(PartialEq)]
+    enum Original {
+        Existing(File),
+        Sorted(usize),
+    }
+    impl Original {
+
+        /// Return an instance of [`Original`] referring to the same file as ours
+        fn copy(&self) -> Original {
+            match self {
+                Original::Existing(file) => Original::Existing(file.copy()),
+                Original::Sorted(i) => Original::Sorted(*i),
+            }
+        }
+    }
+
+    /// The options that files sorted by date are placed and named with: the
+    /// `dir_format` of the directories and the `date_format` of the names, the
+    /// date to sort by, as in [`get_datetime`], and whether to
+    /// `preserve_name` or name the files after `name_template` instead.
+    #[derive(Clone, Copy)]
+    struct Naming<'a> {
+        date_format: &'a str,
+        dir_format: &'a str,
+        date_type: &'a str,
+        name_patterns: &'a [String],
+        name_fallback: &'a str,
+        preserve_name: bool,
+        name_template: Option<&'a Template>,
+    }
+
+    /// Move `file` into a set of directories in the directory format of
+    /// `naming` according to its creation time. See [`format_dir`] for the format.
     /// 
     /// Create any required directories that don't already exist.
     /// Also rename the file according to its creation date.
-    fn get_new_date_path(
+    /// 
+    /// If `naming` has a name template, the file is named after it instead, with
+    /// `counters` keeping count of the files in each directory for `{counter}`.
+    /// 
+    /// Return the new path along with the date source used. Files with no date
+    /// keep their name and go into [`UNDATED_DIR_NAME`], with the source
+    /// reported as [`UNDATED_DIR_NAME`].
+    fn get_new_date_path<'a>(
         target: &File,
         old_file: &File,
-        date_format: &str,
-        date_type: &str,
-        preserve_name: bool) -> File {
+        naming: &Naming<'a>,
+        counters: &mut HashMap<PathBuf, usize>) -> (File, &'a str) {
+        let Naming { date_format, dir_format, date_type, name_patterns, name_fallback, preserve_name, name_template } = *naming;
         
         // Get the time of old_file and set the names of the directories
-        let ctime = get_datetime(old_file, &date_type);
-        let dir = target.join(Path::new(&ctime.format("%Y/%m/").to_string()));
+        let (ctime, source) = match get_datetime(old_file, date_type, name_patterns, name_fallback) {
+            Some(dated) => dated,
+            None => {
+                let dir = target.join(Path::new(UNDATED_DIR_NAME));
+                return (dir.join_string(&old_file.file_name()), UNDATED_DIR_NAME);
+            }
+        };
+        let dir = target.join(Path::new(&format_dir(&ctime, dir_format)));
+
+        // Name the file from the template, if there is one
+        if let Some(name_template) = name_template {
+            let counter = counters.entry(dir.to_path_buf()).or_insert(0);
+            *counter += 1;
+            let name = name_template.render(old_file, &ctime, date_format, *counter);
+            return (dir.join_string(&name), source);
+        }
 
         // Preserve the original file name, if we're supposed to.
         let mut name_to_preserve = String::from("");
@@ -171,15 +880,43 @@ pub mod sort {
             old_file.extension()
         )));
 
-        new_file
+        (new_file, source)
+    }
+
+    /// Return the index of the first rule in `rules` that `path` matches, or
+    /// [`None`] if it matches none of them. `rule_filters` are the compiled
+    /// [`Rule::filter`]s of the rules. The age conditions of a rule are
+    /// checked against the date of `path` according to the rule's date type, or
+    /// `date_type` if it doesn't have one, and undated files never match them.
+    /// `name_patterns` and `name_fallback` are as in [`get_datetime`].
+    fn get_rule(
+        path: &File,
+        rules: &[Rule],
+        rule_filters: &[CompiledFilter],
+        date_type: &str,
+        name_patterns: &[String],
+        name_fallback: &str) -> Option<usize> {
+
+        let name = PathBuf::from(path.file_name());
+        rules.iter().zip(rule_filters).position(|(rule, filter)| {
+            let date_type = rule.date_type.as_deref().unwrap_or(date_type);
+            let mut date = || get_datetime(path, date_type, name_patterns, name_fallback).map(|(datetime, _)| datetime);
+            filter.matches(path, &name, &mut date)
+        })
+    }
+
+    /// Return the path of `old_file` in the directory of its category in
+    /// `target`, keeping its name.
+    fn get_new_category_path(target: &File, old_file: &File, categories: &Categories) -> File {
+        target.join_string(&String::from(categories.category(old_file))).join_string(&old_file.file_name())
     }
 
-    
     /// Return a [`File`] representing the renamed version of `path`.
     /// 
     /// This function is called only if `path` already exists, but can't/shouldn't
     /// be replaced. The naming logic: if `/path/to/file` already exists, return
     /// `/path/to/file_2`. If `/path/to/file_2` already exists, return `/path/to/file_3`, etc.
+    /// A name is taken if it is in `vec` or if it exists on disk.
     fn get_sequential_name(path: &File, vec: &Vec<File>) -> File {
 
         let mut num = 2;
@@ -188,405 +925,915 @@ pub mod sort {
 
             // Create the new path name
             let mut new_pathbuf = path.to_path_buf();
-            new_pathbuf.set_file_name(&format!(
-                "{}_{}.{}",
-                path.pathbuf.file_stem().unwrap().to_str().unwrap(),
-                num,
-                path.pathbuf.extension().unwrap().to_str().unwrap()
-            ));
+            let stem = path.pathbuf.file_stem().unwrap().to_str().unwrap();
+            new_pathbuf.set_file_name(&match path.pathbuf.extension() {
+                Some(extension) => format!("{}_{}.{}", stem, num, extension.to_str().unwrap()),
+                None => format!("{}_{}", stem, num),
+            });
             let new_file = File::from_pathbuf(&new_pathbuf);
 
             // Check if it exists, and if so, continue the loop
-            if !vec.contains(&new_file) {
+            if !vec.contains(&new_file) && !new_file.exists() {
                 return new_file;
             }
             num += 1;
         }
     }
 
-    /// The main sorting algorithm; this checks files for validity and shows
-    /// the progress bar.
-    /// 
-    /// The parameters are as follows:
-    /// 
-    /// <ul>
-    /// <li>
-    /// 
-    /// `source` is the directory from which to get all the files to sort.
-    /// </li>
+    /// The main sorting algorithm: plan where each file in the source of
+    /// `sorter` is going to go. See [`Sorter`] for what each of its options does.
     /// 
-    /// <li>
-    /// 
-    /// `target` is the directory into which to sort all the files.
-    /// </li>
-    /// 
-    /// <li>
-    /// 
-    /// `date_format` is the date *format* with which to rename the files. It shares
-    /// the formatting rules with the [`chrono::format::strftime`] module.
-    /// </li>
-    /// 
-    /// <li>
-    /// 
-    /// `date_type` is the date to sort the files by; one of `"c"` (created),
-    /// `"a"` (accessed), or `"m"` modified. Note that creation time is not
-    /// available on all filesystems.
-    /// </li>
-    /// 
-    /// <li>
-    /// 
-    /// `preserve_name`, if set to [`true`], will add the original filename after
-    /// the date, separated by a space. For example, sorting a file `test.txt` with
-    /// `preserve_name=true` will rename `test.txt` to `2021-04-21 06h34m02s test.txt`
-    /// </li>
-    /// 
-    /// <li>
-    /// 
-    /// `exclude_type` is a [`tuple`] containing two items. One is a [`str`] representing
-    /// the extension of a file type to exclude from sorting. For example, if
-    /// `"jpg"` is passed, all files ending in `.jpg` will be ignored during sorting.
-    /// The other item is a [`bool`] telling whether `exclude_type` should take effect
-    /// or not.
-    /// </li>
-    /// 
-    /// <li>
-    /// 
-    /// `only_type` is [`tuple`] containing two items. One is a [`str`] representing
-    /// the extension of a file type to exclusively sort. For example, if `"jpg"`
-    /// is passed, *only* files ending in `.jpg` will be sorted; all others will be
-    /// ignored. Overrides the `exclude_type` option. The other item is a [`bool`]
-    /// telling whether `only_type` shoud take effect or not.
-    /// </li>
-    /// </ul>
-    /// 
-    /// This returns a three-item tuple containing: a [`usize`] representing the
-    /// number of items to be sorted, a [`Vec<File>`] of the old file names, and
-    /// a [`Vec<File>`] of the new file names. Each item in the old file names
-    /// corresponds with the item of the same index in the new file names. So
-    /// `old_names[0]` will be renamed to `new_names[0]`, `old_names[1]` will be
-    /// renamed to `new_names[1]`, etc.
-    fn get_sorting_results(
-        source: &File,
-        target: &File,
-        date_format: &str,
-        date_type: &str,
-        preserve_name: &bool,
-        exclude_type: (&str, bool),
-        only_type: (&str, bool)) -> (usize, Vec<File>, Vec<File>) {
-
-        // The vector to return: a tuple of (old_filename, new_filename)
+    /// Every file is planned, in the order it is going to be sorted, even if it
+    /// is going to be left where it is, as with [`SortAction::Skip`]. See
+    /// [`find_duplicates`] and [`find_similar`] for how duplicates and similar
+    /// images are found. `observer` is told about each file found in the source.
+    /// Return the first thing wrong with the options if they aren't valid,
+    /// which [`Sorter::validate`] would have told.
+    pub(crate) fn get_sorting_results(sorter: &Sorter, observer: &mut dyn SortObserver) -> Result<Vec<PlannedMove>, SortError> {
+
+        // The options, as the rest of the algorithm uses them
+        let (source, target) = (&sorter.source, &sorter.target);
+        let (date_format, dir_format, date_type) = (sorter.date_format.as_str(), sorter.dir_format.as_str(), sorter.date_type.as_str());
+        let (name_patterns, name_fallback) = (&sorter.name_patterns, sorter.name_fallback.as_str());
+        let filters = sorter.all_filters().iter()
+            .map(|filter| filter.compile().map_err(|reason| SortError::InvalidFilter { filter: filter.to_string(), reason }))
+            .collect::<Result<Vec<CompiledFilter>, SortError>>()?;
+        let exclude_paths = sorter.exclude_paths.iter()
+            .map(|pattern| PathPattern::parse(pattern).map_err(|reason| SortError::InvalidExcludePath { pattern: pattern.clone(), reason }))
+            .collect::<Result<Vec<PathPattern>, SortError>>()?;
+        let rules = &sorter.rules;
+        let rule_filters = rules.iter()
+            .enumerate()
+            .map(|(i, rule)| rule.filter().compile().map_err(|reason| SortError::InvalidRule { rule: rule.display_name(i), reason }))
+            .collect::<Result<Vec<CompiledFilter>, SortError>>()?;
+        let (duplicate_policy, similarity) = (sorter.duplicate_policy, sorter.similarity);
+
+        // The old file names, new file names, actions, date sources, matched
+        // rules, originals of duplicates and the images similar images look
+        // like, by their index until they are all sorted
         let mut vec_old: Vec<File> = Vec::new();
         let mut vec_new: Vec<File> = Vec::new();
+        let mut vec_actions: Vec<SortAction> = Vec::new();
+        let mut vec_sources: Vec<String> = Vec::new();
+        let mut vec_rules: Vec<Option<String>> = Vec::new();
+        let mut vec_duplicates: Vec<Option<File>> = Vec::new();
+        let mut vec_similar: Vec<Option<usize>> = Vec::new();
+
+        // The name template, and the number of files in each directory
+        let name_template = match sorter.name_template.is_empty() {
+            true => None,
+            false => Some(Template::parse(&sorter.name_template)
+                .map_err(|reason| SortError::InvalidTemplate { template: sorter.name_template.clone(), reason })?),
+        };
+        let rule_templates = rules.iter()
+            .enumerate()
+            .map(|(i, rule)| rule.name_template.as_deref().map(Template::parse).transpose()
+                .map_err(|reason| SortError::InvalidRule { rule: rule.display_name(i), reason }))
+            .collect::<Result<Vec<Option<Template>>, SortError>>()?;
+        let mut counters: HashMap<PathBuf, usize> = HashMap::new();
+        let naming = Naming {
+            date_format,
+            dir_format,
+            date_type,
+            name_patterns,
+            name_fallback,
+            preserve_name: sorter.preserve_name,
+            name_template: name_template.as_ref(),
+        };
 
-        // Count the number of items we are going to sort
-        let mut items_to_sort = 0;
-        for entry in WalkDir::new(source.to_string()) {
-
-            let entry = entry.unwrap();
-            if !entry.metadata().expect("Failed to get dir metadata").is_dir() {
-                if is_sortable(&File::from_path(entry.path()), &exclude_type, &only_type) {
-                    items_to_sort += 1;
-               }
+        // The table of file categories, when sorting by category
+        let categories = Categories::new(&sorter.categories);
+
+        // Find the items we are going to sort, excluding the directories, the
+        // special files and the files the filters leave out, and the
+        // duplicates and similar images among them. The target, if it is in
+        // the source, and excluded and ignored paths aren't walked into at
+        // all, and the ignored ones are kept to be planned as such.
+        let mut items: Vec<File> = Vec::new();
+        let mut filtered: Vec<(File, String, Option<&str>)> = Vec::new();
+        let mut ignore_files = IgnoreFiles::new(&source.pathbuf, sorter.gitignore);
+        let ignore_file_names = ignore_files.file_names().to_vec();
+        let mut ignored: Vec<(File, String)> = Vec::new();
+        let mut special: Vec<(File, String)> = Vec::new();
+        let canonical_target = fs::canonicalize(&target.pathbuf).ok();
+        let on_the_way: HashSet<&Path> = sorter.paths.iter().flatten().flat_map(|path| path.ancestors().skip(1)).collect();
+        let min_depth = sorter.depth.min_depth.unwrap_or(0);
+        let walk = WalkDir::new(source.to_string())
+            .max_depth(sorter.depth.max_depth.unwrap_or(usize::MAX))
+            .same_file_system(sorter.one_file_system)
+            .follow_links(sorter.follow_symlinks)
+            .into_iter()
+            .filter_entry(|entry| {
+                let relative = entry.path().strip_prefix(&source.pathbuf).unwrap_or(entry.path());
+                if entry.depth() == 0 { return true }
+                if let Some(paths) = &sorter.paths {
+                    if !on_the_way.contains(entry.path()) && !entry.path().ancestors().any(|path| paths.contains(path)) {
+                        return false;
+                    }
+                }
+                if entry.file_type().is_dir() && canonical_target.as_ref().is_some_and(|target| fs::canonicalize(entry.path()).is_ok_and(|path| &path == target)) {
+                    return false;
+                }
+                if exclude_paths.iter().any(|pattern| pattern.matches(relative)) { return false }
+                match ignore_files.reason(relative, entry.file_type().is_dir()) {
+                    Some(reason) => {
+                        if entry.file_type().is_dir() || entry.depth() >= min_depth {
+                            ignored.push((File::from_path(entry.path()), reason));
+                        }
+                        false
+                    },
+                    None => true,
+                }
+            });
+        for entry in walk {
+
+            // Paths that can't be read, like symlink loops, are left out
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(error) => {
+                    if let Some(path) = error.path() {
+                        special.push((File::from_path(path), get_walk_error_reason(&error)));
+                    }
+                    continue;
+                },
+            };
+            if entry.file_name() == JOURNAL_FILE_NAME || ignore_file_names.iter().any(|name| entry.file_name() == *name) { continue }
+
+            // The files above the min depth are walked past rather than left
+            // out of the walk, so that the directories they are in are still
+            // pruned as above
+            if entry.depth() < min_depth { continue }
+            if !entry.file_type().is_dir() {
+
+                // Make sure that we only sort the regular files and symlinks
+                // that the filters let through
+                let path = File::from_path(entry.path());
+                let relative = entry.path().strip_prefix(&source.pathbuf).unwrap_or(entry.path());
+                if let Some(reason) = get_special_reason(&entry, sorter.symlinks) {
+                    special.push((path, reason));
+                } else {
+                    match get_skip_reason(&path, relative, &filters, date_type, name_patterns, name_fallback) {
+                        Some((reason, date_source)) => filtered.push((path, reason, date_source)),
+                        None => items.push(path),
+                    }
+                }
+                observer.on_scan_progress(items.len() + filtered.len() + special.len());
             }
         }
-        
-        // Sort the everything, excluding the directories
-        for entry in WalkDir::new(source.to_string()) {
-            
-            let entry = entry.unwrap();
-            if !entry.metadata().expect("Failed to get dir metadata").is_dir() {
-
-                // The File instance we are sorting
-                let path = File::from_path(entry.path());
+        let duplicates = match duplicate_policy {
+            DuplicatePolicy::Keep => items.iter().map(|_| None).collect(),
+            _ => find_duplicates(&items, target),
+        };
+        let similar = match similarity.action {
+            SimilarPolicy::Off => items.iter().map(|_| None).collect(),
+            _ => find_similar(&items, &duplicates, similarity),
+        };
 
-                // Make sure that we sort according to the exclude-type and
-                // only-type arguments
-                if is_sortable(&File::from_path(entry.path()), &exclude_type, &only_type) {
+        // Sort the everything
+        for ((path, duplicate), image) in items.into_iter().zip(duplicates).zip(similar) {
+
+            // Where the file this one duplicates will be: where it's going,
+            // unless it isn't going to move
+            let original = duplicate.map(|original| match original {
+                Original::Existing(file) => file,
+                Original::Sorted(i) if vec_actions[i].moves() => vec_new[i].copy(),
+                Original::Sorted(i) => vec_old[i].copy(),
+            });
+
+            let rule = get_rule(&path, rules, &rule_filters, date_type, name_patterns, name_fallback);
+            let (mut new_file, date_source) = match (rule, sorter.sort_by) {
+                (Some(i), _) => get_new_date_path(target, &path, &Naming {
+                    dir_format: &rules[i].destination,
+                    date_type: rules[i].date_type.as_deref().unwrap_or(date_type),
+                    name_template: rule_templates[i].as_ref().or(name_template.as_ref()),
+                    ..naming
+                }, &mut counters),
+                (None, SortBy::Date) => get_new_date_path(target, &path, &naming, &mut counters),
+                (None, SortBy::Category) => (get_new_category_path(target, &path, &categories), ""),
+            };
+            let mut action = SortAction::Move;
+
+            // Duplicates that aren't sorted as usual stay where they are, or go
+            // into the duplicates directory under their own names
+            if original.is_some() {
+                action = match duplicate_policy {
+                    DuplicatePolicy::Skip => SortAction::SkipDuplicate,
+                    DuplicatePolicy::Delete if sorter.operation.keeps_source() => SortAction::SkipDuplicate,
+                    DuplicatePolicy::Delete => SortAction::TrashDuplicate,
+                    DuplicatePolicy::Move => SortAction::MoveDuplicate,
+                    _ => SortAction::LinkDuplicate,
+                };
+                new_file = match action {
+                    SortAction::MoveDuplicate => target.join(Path::new(DUPLICATES_DIR_NAME)).join_string(&path.file_name()),
+                    SortAction::TrashDuplicate => target.join(Path::new(TRASH_DIR_NAME)).join_string(&path.file_name()),
+                    SortAction::LinkDuplicate => new_file,
+                    _ => path.copy(),
+                };
+            }
 
-                    let mut new_file = get_new_date_path(&target, &path, date_format, date_type, *preserve_name);
+            // Images that look like a better one go into the similar directory,
+            // if they are to be quarantined
+            if image.is_some() && similarity.action == SimilarPolicy::Quarantine {
+                action = SortAction::QuarantineSimilar;
+                new_file = target.join(Path::new(SIMILAR_DIR_NAME)).join_string(&path.file_name());
+            }
 
-                    // Get the sequential file name if new_file already exists
-                    if vec_new.contains(&new_file) {
-                        new_file = get_sequential_name(&new_file, &vec_new);
-                    }
+            // Get the sequential file name if new_file is already
+            // taken by another file in this sort
+            if action.moves() && vec_new.contains(&new_file) {
+                new_file = get_sequential_name(&new_file, &vec_new);
+            }
 
-                    // Push the new and old file names to their respective vectors
-                    vec_old.push(path.copy());
-                    vec_new.push(new_file);
+            // Apply the conflict policy if new_file already exists in
+            // the target; duplicates and similar images going to their
+            // directories always get sequential names
+            if action.moves() && new_file.exists() && new_file != path {
+                action = match action {
+                    SortAction::MoveDuplicate => SortAction::MoveDuplicate,
+                    SortAction::TrashDuplicate => SortAction::TrashDuplicate,
+                    SortAction::QuarantineSimilar => SortAction::QuarantineSimilar,
+                    SortAction::LinkDuplicate => match get_conflict_action(&path, &new_file, sorter.conflict_policy) {
+                        SortAction::Skip => SortAction::SkipDuplicate,
+                        _ => SortAction::LinkDuplicate,
+                    },
+                    _ => get_conflict_action(&path, &new_file, sorter.conflict_policy),
+                };
+                if matches!(action, SortAction::Rename | SortAction::MoveDuplicate | SortAction::TrashDuplicate | SortAction::LinkDuplicate | SortAction::QuarantineSimilar) {
+                    new_file = get_sequential_name(&new_file, &vec_new);
                 }
             }
+
+            // Push the new and old file names, the action, the date source,
+            // the rule, the original and the similar image to their respective vectors
+            vec_old.push(path);
+            vec_new.push(new_file);
+            vec_actions.push(action);
+            vec_sources.push(String::from(date_source));
+            vec_rules.push(rule.map(|i| rules[i].display_name(i)));
+            vec_duplicates.push(original);
+            vec_similar.push(image);
         }
-        (items_to_sort, vec_old, vec_new)
-    }
-    
-    /// Return [`true`] if:
-    /// 1) `path`'s type is in `only_type.0` and `only_type.1` is [`true`]
-    /// 2) `path`'s type is not in `exclude_type.0`, and `only_type.1` is [`false`]
-    /// 
-    /// "Type" refers to the file extension, as in `"jpg"`, `"png"`, etc. `exclude_type`
-    /// and `only_type` correspond with `exclude_type` and `only_type` in [`get_sorting_results`],
-    /// respectively.
-    fn is_sortable(path: &File, exclude_type: &(&str, bool), only_type: &(&str, bool)) -> bool {
-
-        if is_type(path, only_type.0) && only_type.1 {
-            return true;
-        } else if !is_type(path, exclude_type.0) && !only_type.1 {
-            return true;
-        } else {
-            return false;
+
+        // Plan each file, with where the image each similar image looks like
+        // will be, now that they have all been sorted
+        let mut plan: Vec<PlannedMove> = Vec::new();
+        for (i, image) in vec_similar.iter().enumerate() {
+            plan.push(PlannedMove {
+                from: vec_old[i].to_path_buf(),
+                to: vec_new[i].to_path_buf(),
+                action: vec_actions[i],
+                date_source: match vec_sources[i].is_empty() {
+                    true => None,
+                    false => Some(vec_sources[i].clone()),
+                },
+                rule: vec_rules[i].clone(),
+                duplicate_of: vec_duplicates[i].as_ref().map(|original| original.to_path_buf()),
+                similar_to: image.map(|i| match vec_actions[i].moves() {
+                    true => vec_new[i].to_path_buf(),
+                    false => vec_old[i].to_path_buf(),
+                }),
+                skip_reason: None,
+                resolved: match sorter.symlinks == SymlinkPolicy::Resolve && vec_old[i].pathbuf.is_symlink() {
+                    true => fs::canonicalize(&vec_old[i].pathbuf).ok(),
+                    false => None,
+                },
+            });
+        }
+
+        // Plan the paths the filters, ignore files and special files left out
+        // too, so that it is clear why they aren't sorted
+        for (path, reason, date_source) in filtered.into_iter() {
+            plan.push(PlannedMove {
+                from: path.to_path_buf(),
+                to: path.to_path_buf(),
+                action: SortAction::SkipFiltered,
+                date_source: date_source.map(String::from),
+                rule: None,
+                duplicate_of: None,
+                similar_to: None,
+                skip_reason: Some(reason),
+                resolved: None,
+            });
         }
+        for (path, reason) in ignored.into_iter() {
+            plan.push(PlannedMove {
+                from: path.to_path_buf(),
+                to: path.to_path_buf(),
+                action: SortAction::SkipIgnored,
+                date_source: None,
+                rule: None,
+                duplicate_of: None,
+                similar_to: None,
+                skip_reason: Some(reason),
+                resolved: None,
+            });
+        }
+        for (path, reason) in special.into_iter() {
+            plan.push(PlannedMove {
+                from: path.to_path_buf(),
+                to: path.to_path_buf(),
+                action: SortAction::SkipSpecial,
+                date_source: None,
+                rule: None,
+                duplicate_of: None,
+                similar_to: None,
+                skip_reason: Some(reason),
+                resolved: None,
+            });
+        }
+        Ok(plan)
     }
 
-    /// Return [`true`] if `path`'s type is one of the types in `types`.
-    /// "Type" refers to the file extension, as in `"jpg"`, `"png"`, etc.
-    fn is_type(path: &File, types: &str) -> bool {
-        let mut to_return: bool = false;
-        for t in types.split("-") {
-            if path.extension() == t {
-                to_return = true;
+    /// Return why the entry `entry` of the walk isn't going to be sorted, as
+    /// in `"named pipe"`, if it is a special file, or a symlink that
+    /// `symlinks` leaves where it is, or [`None`] if it is going to be sorted.
+    fn get_special_reason(entry: &DirEntry, symlinks: SymlinkPolicy) -> Option<String> {
+        if entry.path_is_symlink() {
+            let link = fs::read_link(entry.path()).map(|link| link.display().to_string()).unwrap_or_default();
+            match (symlinks, fs::metadata(entry.path())) {
+                (SymlinkPolicy::Skip, _) => return Some(format!("symlink to {}", link)),
+                (SymlinkPolicy::Resolve, Err(_)) => return Some(format!("broken symlink to {}", link)),
+                (SymlinkPolicy::Resolve, Ok(metadata)) if metadata.is_dir() => return Some(format!("symlink to directory {}", link)),
+                _ => {},
             }
         }
-        to_return
+        let file_type = entry.file_type();
+        match file_type {
+            _ if file_type.is_fifo() => Some(String::from("named pipe")),
+            _ if file_type.is_socket() => Some(String::from("socket")),
+            _ if file_type.is_block_device() => Some(String::from("block device")),
+            _ if file_type.is_char_device() => Some(String::from("character device")),
+            _ => None,
+        }
     }
 
-    /// Print the intended sort, without acutally sorting. Each parameter
-    /// corresponds with the parameter in [`get_sorting_results`] with the same name.
-    pub fn sort_dry_run(
-        source: &File,
-        target: &File,
-        date_format: &str,
-        date_type: &str,
-        preserve_name: &bool,
-        exclude_type: (&str, bool),
-        only_type: (&str, bool)) {
-
-        let results = get_sorting_results(
-            source,
-            target,
-            date_format,
-            date_type,
-            preserve_name,
-            exclude_type,
-            only_type
-        );
-
-        for i in 0..results.0 {
-            println!("{}", DryRunMessage {
-                from_file: results.1[i].copy(),
-                to_file: results.2[i].copy(),
-            }.to_string());
+    /// Return why the path of the walk `error` can't be sorted, as in
+    /// `"symlink loop back to /home/user"`.
+    fn get_walk_error_reason(error: &walkdir::Error) -> String {
+        match (error.loop_ancestor(), error.io_error()) {
+            (Some(ancestor), _) => format!("symlink loop back to {}", ancestor.display()),
+            (None, Some(io_error)) if io_error.kind() == ErrorKind::NotFound => String::from("broken symlink"),
+            (None, Some(io_error)) => io_error.to_string(),
+            (None, None) => error.to_string(),
         }
     }
 
-    /// The main sort function used by the command-line interface. Each parameter
-    /// corresponds with the parameter in [`get_sorting_results`] of the same name,
-    /// with the exception of `dry_run`.
+    /// Return, for each of `items`, the file it is a duplicate of, if it is
+    /// one. A duplicate is a file with exactly the same contents as a file
+    /// already in `target`, or as an earlier file in `items`; files in `target`
+    /// are always the originals. Empty files are never duplicates.
     /// 
-    /// If `dry_run` is [`true`], will print the intended sort without acutally sorting.
-    pub fn sort(
-        source: &File,
-        target: &File,
-        date_format: &str,
-        date_type: &str,
-        preserve_name: &bool,
-        exclude_type: (&str, bool),
-        only_type: (&str, bool),
-        dry_run: bool) {
-
-        // Do a dry run, if specified
-        if dry_run {
-            sort_dry_run(
-                source,
-                target,
-                date_format,
-                date_type,
-                preserve_name,
-                exclude_type,
-                only_type
-            );
-            return;
+    /// Files are grouped by size first, and then by a hash of their contents,
+    /// so that only files that are likely to be duplicates are compared in full.
+    fn find_duplicates(items: &[File], target: &File) -> Vec<Option<Original>> {
+
+        // The sizes of the items; files in the target of other sizes can't
+        // be duplicated by them
+        let sizes: Vec<u64> = items.iter()
+            .map(|item| item.pathbuf.metadata().map(|metadata| metadata.len()).unwrap_or(0))
+            .collect();
+        let item_sizes: HashSet<u64> = sizes.iter().copied().filter(|size| *size > 0).collect();
+        let item_paths: HashSet<&PathBuf> = items.iter().map(|item| &item.pathbuf).collect();
+
+        // Group the files by size, with the files in the target first; the
+        // trashed duplicates aren't originals
+        let mut by_size: HashMap<u64, Vec<Original>> = HashMap::new();
+        let walk = WalkDir::new(target.to_string()).into_iter()
+            .filter_entry(|entry| entry.depth() != 1 || entry.file_name() != TRASH_DIR_NAME);
+        for entry in walk.filter_map(|entry| entry.ok()) {
+            if !entry.file_type().is_file() || entry.file_name() == JOURNAL_FILE_NAME { continue }
+            let file = File::from_path(entry.path());
+            let size = entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            if item_sizes.contains(&size) && !item_paths.contains(&file.pathbuf) {
+                by_size.entry(size).or_default().push(Original::Existing(file));
+            }
+        }
+        for (i, size) in sizes.iter().enumerate().filter(|(_, size)| **size > 0) {
+            by_size.entry(*size).or_default().push(Original::Sorted(i));
         }
 
-        // The results of the sorting algorithm
-        let results = get_sorting_results(
-            source,
-            target,
-            date_format,
-            date_type,
-            preserve_name,
-            exclude_type,
-            only_type
-        );
-
-        // The number of items to sort
-        let items_to_sort = results.0;
-        
-        // The number of items we have sorted
-        let mut items_sorted = 0;
-
-        // The progress bar
-        let progress_bar = ProgressBar {
-            completed_message: String::from("Done."),
-            message: String::from("Sorting..."),
-            total: items_to_sort
+        // Group each group of files of the same size by their hashes, and
+        // compare each item with the originals of the same hash before it
+        let mut duplicates: Vec<Option<Original>> = items.iter().map(|_| None).collect();
+        let file = |original: &Original| match original {
+            Original::Existing(file) => file.copy(),
+            Original::Sorted(i) => items[*i].copy(),
         };
+        for group in by_size.values().filter(|group| group.len() > 1) {
+            let mut by_hash: HashMap<Option<u64>, Vec<&Original>> = HashMap::new();
+            for original in group.iter() {
+                by_hash.entry(file(original).content_hash()).or_default().push(original);
+            }
+            for (_, group) in by_hash.iter().filter(|(hash, group)| hash.is_some() && group.len() > 1) {
+                let mut originals: Vec<&Original> = Vec::new();
+                for candidate in group.iter() {
+                    let found = originals.iter().find(|original| is_identical(&file(candidate), &file(original)));
+                    match (candidate, found) {
+                        (Original::Sorted(i), Some(original)) => duplicates[*i] = Some(original.copy()),
+                        _ => originals.push(candidate),
+                    }
+                }
+            }
+        }
+        duplicates
+    }
+    
+    /// Return, for each of `items`, the index of the image it looks like, if
+    /// there is one. Images look alike if their perceptual hashes, of the kind
+    /// in `similarity`, differ by at most its `distance` bits; see
+    /// [`crate::perceptual`]. The images are kept from the best down, by
+    /// highest resolution and then biggest file, and each image looks like the
+    /// first kept image it is that close to; see [`group_similar`]. Only JPEG
+    /// and PNG images are compared, and items that are `duplicates` are left out.
+    fn find_similar(items: &[File], duplicates: &[Option<Original>], similarity: Similarity) -> Vec<Option<usize>> {
+
+        // The hashes of the images, and how good they are, by their index in items
+        let size = |i: usize| items[i].pathbuf.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        let images: Vec<(usize, u64, (u64, u64))> = items.iter()
+            .zip(duplicates.iter())
+            .enumerate()
+            .filter(|(_, (_, duplicate))| duplicate.is_none())
+            .filter_map(|(i, (item, _))| perceptual::fingerprint(item, similarity.hash).map(|print| (i, print)))
+            .map(|(i, print)| (i, print.hash, (print.pixels, size(i))))
+            .collect();
+
+        let mut similar: Vec<Option<usize>> = items.iter().map(|_| None).collect();
+        for (i, kept) in group_similar(images, similarity.distance) {
+            similar[i] = Some(kept);
+        }
+        similar
+    }
 
-        for (old, new) in results.1.iter().zip(results.2.iter()) {
-
-            // The file paths
-            let old_file = old.pathbuf.as_path();
-            let new_file = new.pathbuf.as_path();
-
-            // Create the directory for the file, if it doesn't exist already
-            let dir = new_file.parent().expect("Failed to get parent dir.");
-            if !dir.exists() {
-                fs::create_dir_all(&dir).expect("Failed to create dirs.");
+    /// Return the `images` that look like another one, each with the one it
+    /// looks like. The images are given by their index, their perceptual hash
+    /// and how good they are, and the best image of those that look alike is
+    /// kept. Each of the others looks like the first kept image whose hash is at
+    /// most `distance` bits off, so images that only look alike through
+    /// another image aren't put together.
+    fn group_similar(mut images: Vec<(usize, u64, (u64, u64))>, distance: u32) -> Vec<(usize, usize)> {
+        images.sort_by_key(|(i, _, quality)| (Reverse(*quality), *i));
+        let mut kept: Vec<(usize, u64)> = Vec::new();
+        let mut similar: Vec<(usize, usize)> = Vec::new();
+        for (i, hash, _) in images {
+            match kept.iter().find(|(_, kept_hash)| perceptual::distance(hash, *kept_hash) <= distance) {
+                Some((kept_i, _)) => similar.push((i, *kept_i)),
+                None => kept.push((i, hash)),
             }
+        }
+        similar
+    }
 
-            // Rename the file
-            fs::rename(&old_file, &new_file).expect(
-                &error_messages::PathMoveFailedError {
-                    source: &File::from_path(old_file),
-                    target: &File::from_path(new_file),
-                }.to_string()
-            );
-            items_sorted += 1;
+    /// Return [`true`] if `path` and `other` have exactly the same contents.
+    fn is_identical(path: &File, other: &File) -> bool {
 
-            // Update the progress bar
-            progress_bar.set_progress(items_sorted);
+        // Files of different sizes can't be identical
+        let (metadata, other_metadata) = match (path.pathbuf.metadata(), other.pathbuf.metadata()) {
+            (Ok(metadata), Ok(other_metadata)) => (metadata, other_metadata),
+            _ => return false,
+        };
+        if metadata.len() != other_metadata.len() {
+            return false;
         }
-        progress_bar.complete();
-        println!("Sucessfully sorted {} items by date into {}.", items_sorted, target.to_string());
-    }
 
-    /// Sort according to configuration data in JSON [`String`] `json`. `source`
-    /// and `target` correspond with the same-name parameters in [`get_sorting_results`].
-    /// See [`crate::structs::ConfigData`] for more information on JSON configuration.
-    /// 
-    /// If `dry_run` is [`true`], will print the intended sort without acutally sorting.
-    pub fn sort_from_json(json: String, source: File, target: File, dry_run: bool) {
-
-        // Get the json data
-        let data = ConfigData::from_json(&json);
-
-        // Make sure that the directories actually exist
-        let mut errors = false;
-        if !source.exists() {
-            println!("{}", error_messages::PathDoesNotExistError { path: &source }.to_string());
-            errors = true;
-        }
-        if !target.exists() {
-            println!("{}", error_messages::PathDoesNotExistError { path: &target }.to_string());
-            errors = true;
-        }
-        if errors { return }
-
-        // Run the sorting algorithm with the data, doing a dry run if specified
-        let exclude_type: (&str, bool) = (&data.exclude_type.join("-"), data.exclude_type.len() > 0);
-        let only_type: (&str, bool) = (&data.only_type.join("-"), data.only_type.len() > 0);
-        if dry_run {
-            sort_dry_run(
-                &source,
-                &target,
-                data.date_format.as_str(),
-                data.date_type.as_str(),
-                &data.preserve_name,
-                exclude_type,
-                only_type
-            );
-        } else {
-            sort(
-                &source,
-                &target,
-                data.date_format.as_str(),
-                data.date_type.as_str(),
-                &data.preserve_name,
-                exclude_type,
-                only_type,
-                false
-            )
+        // Compare the contents chunk by chunk
+        let (mut file, mut other_file) = match (fs::File::open(&path.pathbuf), fs::File::open(&other.pathbuf)) {
+            (Ok(file), Ok(other_file)) => (file, other_file),
+            _ => return false,
+        };
+        let mut buffer = [0u8; 8192];
+        let mut other_buffer = [0u8; 8192];
+        loop {
+            let read = match file.read(&mut buffer) {
+                Ok(read) => read,
+                Err(_) => return false,
+            };
+            if read == 0 {
+                return true;
+            }
+            if other_file.read_exact(&mut other_buffer[..read]).is_err() || buffer[..read] != other_buffer[..read] {
+                return false;
+            }
         }
     }
+
+    /// Return why the first of `filters` that `path` doesn't match leaves it
+    /// out, along with the date source of its date if it was looked up, or
+    /// [`None`] if it matches all of them. `relative` is its path inside the
+    /// source, and its date is looked up according to `date_type`,
+    /// `name_patterns` and `name_fallback` as in [`get_datetime`], at most
+    /// once, and only if one of the filters needs it.
+    fn get_skip_reason<'a>(
+        path: &File,
+        relative: &Path,
+        filters: &[CompiledFilter],
+        date_type: &'a str,
+        name_patterns: &[String],
+        name_fallback: &'a str) -> Option<(String, Option<&'a str>)> {
+
+        let mut datetime: Option<Option<(DateTime<Local>, &str)>> = None;
+        let mut date = || datetime.get_or_insert_with(|| get_datetime(path, date_type, name_patterns, name_fallback)).map(|(datetime, _)| datetime);
+        let reason = filters.iter().find_map(|filter| filter.reason(path, relative, &mut date))?;
+        Some((reason, datetime.flatten().map(|(_, source)| source)))
+    }
 }
 
-/// Move all the contents of SOURCE to TARGET, maintaining subdirectory structure.
-/// If `dry_run` is [`true`], will print the intended sort without acutally sorting.
-pub fn extract(source: &File, target: &File, dry_run: bool) {
+/// Plan moving all the contents of SOURCE to TARGET, maintaining subdirectory
+/// structure, and return the [`SortPlan`]. If `operation` is [`Operation::Copy`]
+/// or one of the link operations, they are going to be copied or linked
+/// instead, leaving SOURCE as it was. The paths ignored by the ignore files in
+/// SOURCE, and by its `.gitignore` files if `gitignore` is [`true`], are left
+/// where they are. `observer` is told about each item found in SOURCE, and about the plan.
+/// 
+/// By default only the entries directly in SOURCE are moved, each as a whole.
+/// With a `depth`, the entries at the max depth are moved as a whole, and the
+/// directories above it are looked into instead, so that only their contents
+/// are moved. The directories above the min depth are left out of TARGET, so
+/// `Depth { min_depth: Some(2), max_depth: None }` moves everything two levels
+/// down into TARGET itself. An entry whose new path was already planned for
+/// another is skipped, and so are the directories moved as a whole if
+/// `operation` is [`Operation::HardLink`], as they can't be hard linked.
+pub fn extract(source: &File, target: &File, operation: Operation, gitignore: bool, depth: Depth, observer: &mut dyn SortObserver) -> SortPlan {
+
+    // The shallowest and deepest entries to move
+    let min_depth = depth.min_depth.unwrap_or(1).max(1);
+    let max_depth = depth.max_depth.unwrap_or(min_depth);
+
+    // Plan moving each entry (file or directory) within the depths, leaving
+    // out the target, and the ignored entries, which are kept to be planned as such
+    let mut moves: Vec<PlannedMove> = Vec::new();
+    let mut new_paths: HashSet<PathBuf> = HashSet::new();
+    let mut ignore_files = IgnoreFiles::new(&source.pathbuf, gitignore);
+    let ignore_file_names = ignore_files.file_names().to_vec();
+    let mut ignored: Vec<(File, String)> = Vec::new();
+    let canonical_target = fs::canonicalize(&target.pathbuf).ok();
+    let walk = WalkDir::new(&source.pathbuf)
+        .min_depth(1)
+        .max_depth(max_depth)
+        .into_iter()
+        .filter_entry(|entry| {
+            if entry.file_type().is_dir() && canonical_target.as_ref().is_some_and(|target| fs::canonicalize(entry.path()).is_ok_and(|path| &path == target)) {
+                return false;
+            }
+            let relative = entry.path().strip_prefix(&source.pathbuf).unwrap_or(entry.path());
+            match ignore_files.reason(relative, entry.file_type().is_dir()) {
+                Some(reason) => {
+                    ignored.push((File::from_path(entry.path()), reason));
+                    false
+                },
+                None => true,
+            }
+        });
+    for entry in walk {
 
-    // The number of items we have moved
-    let mut items_moved = 0;
+        // The entry path, which shouldn't be the journal or an ignore file
+        let entry = entry.expect("Failed to get dir entry.");
+        let old_path = File::from_path(entry.path());
+        if old_path.file_name() == JOURNAL_FILE_NAME || ignore_file_names.iter().any(|name| old_path.file_name() == *name) { continue }
 
-    // Make a dry run, if specified
-    if dry_run {
-        for entry in source.pathbuf.as_path().read_dir().expect("Failed to read dir.") {
+        // Look into the directories above the max depth instead of moving
+        // them, and leave the files above the min depth where they are
+        if entry.file_type().is_dir() && entry.depth() < max_depth { continue }
+        if entry.depth() < min_depth { continue }
 
-            // The entry path
-            let entry = entry.expect("Failed to get dir entry.");
-            let old_path = File::from_pathbuf(&entry.path());
+        // Calculate the new path for the entry
+        let relative: PathBuf = entry.path().strip_prefix(&source.pathbuf)
+            .expect("Failed to get relative path.")
+            .iter()
+            .skip(min_depth - 1)
+            .collect();
+        let new_path = target.pathbuf.join(relative);
+        let skip_reason = match operation == Operation::HardLink && entry.file_type().is_dir() {
+            true => Some(String::from("directories can't be hard linked")),
+            false => None,
+        };
+        moves.push(PlannedMove {
+            from: old_path.to_path_buf(),
+            action: match skip_reason.is_none() && new_paths.insert(new_path.clone()) {
+                true => SortAction::Move,
+                false => SortAction::Skip,
+            },
+            to: new_path,
+            date_source: None,
+            rule: None,
+            duplicate_of: None,
+            similar_to: None,
+            skip_reason,
+            resolved: None,
+        });
+        observer.on_scan_progress(moves.len());
+    }
 
-            // Calculate the new path for the entry
-            let new_path = target.join_string(&old_path.file_name());
+    // Plan the ignored entries too, so that it is clear why they aren't moved
+    for (path, reason) in ignored.into_iter() {
+        moves.push(PlannedMove {
+            from: path.to_path_buf(),
+            to: path.to_path_buf(),
+            action: SortAction::SkipIgnored,
+            date_source: None,
+            rule: None,
+            duplicate_of: None,
+            similar_to: None,
+            skip_reason: Some(reason),
+            resolved: None,
+        });
+    }
+    observer.on_planned(&moves);
+    SortPlan {
+        source: source.to_path_buf(),
+        target: target.to_path_buf(),
+        operation,
+        sort_by: None,
+        moves,
+    }
+}
 
-            // Make sure that the path being moved is not the source or target
-            if &old_path == source || &old_path == target { continue }
+/// Return [`true`] if the symbolic link `link` points to `file`, whether or
+/// not `file` still exists, as the links made by [`transfer::symbolic_link`]
+/// do.
+fn links_to(link: &Path, file: &Path) -> bool {
+    let pointed = match (fs::read_link(link), link.parent().map(fs::canonicalize)) {
+        (Ok(pointed), Some(Ok(dir))) => dir.join(pointed),
+        _ => return false,
+    };
 
-            // Show the output of the dry run
-            println!("{}", DryRunMessage { from_file: old_path, to_file: new_path }.to_string());
+    // The link holds the canonical path of the file, or that path relative to
+    // the directory of the link, so it is only resolved as far as the ..s
+    let mut resolved = PathBuf::new();
+    for component in pointed.components() {
+        match component {
+            Component::ParentDir => { resolved.pop(); },
+            component => resolved.push(component),
         }
-        return;
     }
+    let canonical = fs::canonicalize(file).ok().or_else(|| {
+        let dir = fs::canonicalize(file.parent()?).ok()?;
+        Some(dir.join(file.file_name()?))
+    });
+    canonical == Some(resolved)
+}
 
-    // Count the number of items we are going to move
-    let mut items_to_move = 0;
-    for entry in source.pathbuf.as_path().read_dir().expect("Failed to read dir") {
-
-        // The entry path
-        let entry = entry.expect("Failed to get dir entry.");
-        let old_path = File::from_pathbuf(&entry.path());
-
-        // Make sure that the path being moved is not the source or target
-        if &old_path == source || &old_path == target { continue }
+/// Move the files of a previous run back to where they came from, using the
+/// undo journal kept in `target`. If `run_id` is [`None`], the most recent run
+/// is undone.
+/// 
+/// Files that were modified since they were moved are skipped and reported,
+/// unless `force` is [`true`]. Files whose original path has been taken since
+/// are always skipped. Any source directories emptied by the original run are
+/// recreated. Files that were copied or linked rather than moved are undone by
+/// removing the copies and links. Entries that were undone are removed from the journal.
+/// 
+/// If `dry_run` is [`true`], will print the intended moves without acutally moving.
+/// Paths that can't be moved back or removed are reported and skipped too.
+pub fn undo(target: &File, run_id: Option<&str>, force: bool, dry_run: bool) {
+
+    // The journal entries, and the run to undo
+    let entries = match read_journal(target) {
+        Ok(entries) => entries,
+        Err(error) => {
+            println!("{}", error);
+            return;
+        },
+    };
+    let run_id = match run_id {
+        Some(run_id) => String::from(run_id),
+        None => match entries.last() {
+            Some(entry) => entry.run_id.clone(),
+            None => {
+                println!("Nothing to undo in {}.", target.to_string());
+                return;
+            }
+        }
+    };
+    let to_undo: Vec<usize> = (0..entries.len()).rev().filter(|i| entries[*i].run_id == run_id).collect();
+    if to_undo.is_empty() {
+        println!("No run {} in the journal of {}.", run_id, target.to_string());
+        return;
+    }
 
-        items_to_move += 1;
+    // Make a dry run, if specified
+    if dry_run {
+        for i in to_undo {
+            if entries[i].operation.keeps_source() {
+                println!("{}", RemoveMessage {
+                    file: File::from_pathbuf(&entries[i].new),
+                    original: File::from_pathbuf(&entries[i].old),
+                    operation: entries[i].operation,
+                }.to_string());
+                continue;
+            }
+            println!("{}", DryRunMessage {
+                from_file: File::from_pathbuf(&entries[i].new),
+                to_file: File::from_pathbuf(&entries[i].old),
+                action: SortAction::Move,
+                date_source: None,
+                rule: None,
+                operation: Operation::Move,
+                duplicate_of: None,
+                similar_to: None,
+                skip_reason: None,
+                resolved: None,
+            }.to_string());
+        }
+        return;
     }
 
     // The progress bar
     let progress_bar = ProgressBar {
         completed_message: String::from("Completed."),
-        message: String::from("Extracting..."),
-        total: items_to_move,
+        message: String::from("Undoing..."),
+        total: to_undo.len(),
+        total_bytes: to_undo.iter()
+            .filter(|i| entries[**i].operation == Operation::Move)
+            .map(|i| transfer::size_of(&entries[*i].new))
+            .sum(),
     };
+    let mut bytes_moved = 0;
+
+    // The entries that were successfully undone
+    let mut undone = vec![false; entries.len()];
+    let mut items_undone = 0;
+    let mut items_skipped = 0;
+
+    for (done, i) in to_undo.iter().enumerate() {
+        let entry = &entries[*i];
+        let old_path = File::from_pathbuf(&entry.old);
+        let new_path = File::from_pathbuf(&entry.new);
+
+        // Make sure that the file is still there, unchanged, and that its old
+        // path is free
+        if !new_path.exists() {
+            println!("{}", error_messages::PathDoesNotExistError { path: &new_path }.to_string());
+            items_skipped += 1;
+        } else if entry.is_modified() && !force {
+            println!("{}", error_messages::PathModifiedError { path: &new_path }.to_string());
+            items_skipped += 1;
+        } else if old_path.exists() && !entry.operation.keeps_source() {
+            println!("{}", error_messages::PathExistsError { source: &new_path, target: &old_path }.to_string());
+            items_skipped += 1;
+        } else {
 
-    // Move each entry (file or directory) in the directory
-    for entry in source.pathbuf.as_path().read_dir().expect("Failed to read dir.") {
-
-        // The entry path
-        let entry = entry.expect("Failed to get dir entry.");
-        let old_path = File::from_pathbuf(&entry.path());
-
-        // Calculate the new path for the entry
-        let new_path = target.join_string(&old_path.file_name());
-
-        // Make sure that the path being moved is not the source or target
-        if &old_path == source || &old_path == target { continue }
-
-        // Move the path
-        // println!("Moving {} to {}...", &old_path.to_string(), &new_path.to_string());
-        fs::rename(old_path.to_string(), new_path.to_string())
-            .expect(
-                &error_messages::PathMoveFailedError {
-                    source: &old_path,
-                    target: &new_path,
-                }.to_string()
-            );
-        
-        // Add to the count of items moved
-        items_moved += 1;
+            // Remove the copy or link, as the original is still where it was,
+            // or recreate the source directory if it was emptied, and move the
+            // path back, copying it if it's going to another filesystem, or
+            // make the symlink it was a copy of again
+            let result = match (entry.operation.keeps_source(), &entry.link) {
+                (true, _) => transfer::remove_path(&entry.new),
+                (false, link) => fs::create_dir_all(entry.old.parent().unwrap_or(&entry.old)).and_then(|_| match link {
+                    Some(link) => unix::fs::symlink(link, &entry.old).and_then(|_| transfer::remove_path(&entry.new)),
+                    None => transfer::move_path(&entry.new, &entry.old, &mut |bytes| {
+                        bytes_moved += bytes;
+                        progress_bar.set_progress(done, bytes_moved);
+                    }),
+                }),
+            };
+            match result {
+                Ok(_) => {
+                    undone[*i] = true;
+                    items_undone += 1;
+                },
+                Err(_) => {
+                    println!("{}", error_messages::PathMoveFailedError { source: &new_path, target: &old_path }.to_string());
+                    items_skipped += 1;
+                },
+            }
+        }
 
         // Show the progress
-        progress_bar.set_progress(items_moved);
+        progress_bar.set_progress(done + 1, bytes_moved);
+    }
+
+    // Forget the entries that were undone
+    let remaining: Vec<_> = entries.iter()
+        .zip(undone.iter())
+        .filter(|(_, undone)| !**undone)
+        .map(|(entry, _)| entry.clone())
+        .collect();
+    if let Err(error) = write_journal(target, &remaining) {
+        println!("{}", error);
+        return;
     }
+
     // Show success status
     progress_bar.complete();
-    println!("Successfully moved {} items to {}.", items_moved, target.to_string());
-}
\ No newline at end of file
+    println!("Successfully moved {} items back from {}; {} skipped.", items_undone, target.to_string(), items_skipped);
+}
+
+/// Return the entries in the journal of `target`, or the error message to
+/// show if it can't be read.
+fn read_journal(target: &File) -> Result<Vec<JournalEntry>, String> {
+    Journal::read(target).map_err(|error| {
+        let path = target.join_string(&String::from(JOURNAL_FILE_NAME));
+        error_messages::JournalReadFailedError { path: &path, error: &error.to_string() }.to_string()
+    })
+}
+
+/// Replace the entries in the journal of `target` with `entries`, or return
+/// the error message to show if it can't be written.
+fn write_journal(target: &File, entries: &[JournalEntry]) -> Result<(), String> {
+    Journal::write(target, entries).map_err(|error| {
+        let path = target.join_string(&String::from(JOURNAL_FILE_NAME));
+        error_messages::JournalFailedError { path: &path, error: &error.to_string() }.to_string()
+    })
+}
+
+/// Remove the links that earlier runs linking files into `target` made, so
+/// that a view of files built with links can be built again from scratch.
+/// Links to files that have been removed from the source are removed too, as
+/// are any directories left empty in `target`.
+/// 
+/// Only links recorded in the undo journal of `target` are removed, and only
+/// if they are still links to the same files; the rest are left alone and kept
+/// in the journal, as are links that can't be removed. Links that are removed
+/// are forgotten by the journal.
+/// 
+/// If `dry_run` is [`true`], will print the links to remove without actually removing them.
+/// If `json` is [`true`], the links are printed as json lines, as with
+/// [`JsonLinesObserver::on_unlinked`].
+pub fn refresh_view(target: &File, dry_run: bool, json: bool) {
+
+    // The journal entries, and whether each one is a link that can be removed
+    let mut observer = JsonLinesObserver::new(io::stdout());
+    let entries = match read_journal(target) {
+        Ok(entries) => entries,
+        Err(error) => {
+            match json {
+                true => observer.on_failed(&error),
+                false => println!("{}", error),
+            }
+            return;
+        },
+    };
+    let removable: Vec<bool> = entries.iter().map(|entry| {
+        let metadata = match fs::symlink_metadata(&entry.new) {
+            Ok(metadata) => metadata,
+            Err(_) => return false,
+        };
+        match entry.operation {
+            Operation::SymLink | Operation::RelativeSymLink => metadata.file_type().is_symlink() && links_to(&entry.new, &entry.old),
+            Operation::HardLink => metadata.is_file() && match fs::metadata(&entry.old) {
+                Ok(old) => (old.dev(), old.ino()) == (metadata.dev(), metadata.ino()),
+                Err(_) => true,
+            },
+            _ => false,
+        }
+    }).collect();
+
+    // Make a dry run, if specified
+    if dry_run {
+        for (entry, _) in entries.iter().zip(removable.iter()).filter(|(_, removable)| **removable) {
+            match json {
+                true => observer.on_unlinked(entry, true),
+                false => println!("{}", RemoveMessage {
+                    file: File::from_pathbuf(&entry.new),
+                    original: File::from_pathbuf(&entry.old),
+                    operation: entry.operation,
+                }.to_string()),
+            }
+        }
+        return;
+    }
+
+    // Remove the links, and then the directories they leave empty up to the
+    // target. Links that can't be removed are reported and kept
+    let target_dir = fs::canonicalize(&target.pathbuf).ok();
+    let mut removed = vec![false; entries.len()];
+    let mut items_removed = 0;
+    for (i, entry) in entries.iter().enumerate().filter(|(i, _)| removable[*i]) {
+        if let Err(error) = fs::remove_file(&entry.new) {
+            let error = error_messages::PathRemoveFailedError { path: &File::from_pathbuf(&entry.new), error: &error.to_string() }.to_string();
+            match json {
+                true => observer.on_failed(&error),
+                false => println!("{}", error),
+            }
+            continue;
+        }
+        removed[i] = true;
+        items_removed += 1;
+        if json {
+            observer.on_unlinked(entry, false);
+        }
+        let mut dir = entry.new.parent();
+        while let Some(path) = dir {
+            if target_dir.is_none() || fs::canonicalize(path).ok() == target_dir || fs::remove_dir(path).is_err() { break }
+            dir = path.parent();
+        }
+    }
+
+    // Forget the links that were removed
+    let remaining: Vec<_> = entries.iter()
+        .zip(removed.iter())
+        .filter(|(_, removed)| !**removed)
+        .map(|(entry, _)| entry.clone())
+        .collect();
+    if let Err(error) = write_journal(target, &remaining) {
+        match json {
+            true => observer.on_failed(&error),
+            false => println!("{}", error),
+        }
+        return;
+    }
+    match json {
+        true => observer.on_refreshed(&target.pathbuf, items_removed),
+        false => println!("Removed {} links from {}.", items_removed, target.to_string()),
+    }
+}
@@ -0,0 +1,349 @@
+//! Moving, copying and linking files and directories, including moving them
+//! between filesystems, where they can't just be renamed.
+
+use std::{fs, io::{self, ErrorKind, Read, Write}, os::unix::fs::symlink, path::{Component, Path, PathBuf}};
+use crate::structs::Operation;
+use walkdir::WalkDir;
+
+#[cfg(test)]
+/// Tests for transfer. Each test is named after the function it tests, prefixed
+/// with test.
+mod tests {
+
+    use std::{env, fs, os::unix::fs::PermissionsExt, time::{Duration, SystemTime}};
+    use super::*;
+
+    /// Return a new empty directory for a test called `name`.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("sortery_test_transfer_{}_{}", name, std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).expect("Failed to remove test dir.");
+        }
+        fs::create_dir_all(&dir).expect("Failed to create test dir.");
+        dir
+    }
+
+    #[test]
+    /// Test [`move_by_copy`]
+    fn test_move_by_copy() {
+        let dir = test_dir("move_by_copy");
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+
+        // A file, with its permissions and times
+        let file = dir.join("a.txt");
+        fs::write(&file, vec![7u8; 200_000]).unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+        fs::File::options().write(true).open(&file).unwrap()
+            .set_times(fs::FileTimes::new().set_modified(mtime).set_accessed(mtime)).unwrap();
+        let mut copied = 0;
+        move_by_copy(&file, &dir.join("b.txt"), &mut |bytes| copied += bytes).unwrap();
+        let metadata = fs::metadata(dir.join("b.txt")).unwrap();
+        assert!(!file.exists());
+        assert_eq!(copied, 200_000);
+        assert_eq!(metadata.len(), 200_000);
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+        assert_eq!(metadata.modified().unwrap(), mtime);
+        assert_eq!(metadata.accessed().unwrap(), mtime);
+
+        // A directory with a file and a symlink in it
+        fs::create_dir_all(dir.join("d/e")).unwrap();
+        fs::write(dir.join("d/e/f.txt"), "f").unwrap();
+        symlink("e/f.txt", dir.join("d/link")).unwrap();
+        move_by_copy(&dir.join("d"), &dir.join("g"), &mut |_| {}).unwrap();
+        assert!(!dir.join("d").exists());
+        assert_eq!(fs::read_to_string(dir.join("g/e/f.txt")).unwrap(), "f");
+        assert_eq!(fs::read_link(dir.join("g/link")).unwrap(), PathBuf::from("e/f.txt"));
+
+        // Replacing an existing file leaves no partial copies behind
+        fs::write(dir.join("h.txt"), "new").unwrap();
+        move_by_copy(&dir.join("h.txt"), &dir.join("b.txt"), &mut |_| {}).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("b.txt")).unwrap(), "new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    /// Test [`copy_path`]
+    fn test_copy_path() {
+        let dir = test_dir("copy_path");
+        fs::create_dir(dir.join("d")).unwrap();
+        fs::write(dir.join("d/a.txt"), "abc").unwrap();
+        let mut copied = 0;
+        copy_path(&dir.join("d"), &dir.join("e"), &mut |bytes| copied += bytes).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("d/a.txt")).unwrap(), "abc");
+        assert_eq!(fs::read_to_string(dir.join("e/a.txt")).unwrap(), "abc");
+        assert_eq!(copied, 3);
+
+        // A failed copy leaves nothing behind
+        assert!(copy_path(&dir.join("nonexistent"), &dir.join("f"), &mut |_| {}).is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    /// Test [`hard_link`] and [`symbolic_link`]
+    fn test_links() {
+        let dir = test_dir("links");
+        fs::create_dir_all(dir.join("view/2021")).unwrap();
+        fs::write(dir.join("a.txt"), "abc").unwrap();
+
+        hard_link(&dir.join("a.txt"), &dir.join("view/2021/hard.txt")).unwrap();
+        symbolic_link(&dir.join("a.txt"), &dir.join("view/2021/relative.txt"), true).unwrap();
+        symbolic_link(&dir.join("a.txt"), &dir.join("view/2021/absolute.txt"), false).unwrap();
+        assert_eq!(fs::read_link(dir.join("view/2021/relative.txt")).unwrap(), PathBuf::from("../../a.txt"));
+        assert_eq!(fs::read_link(dir.join("view/2021/absolute.txt")).unwrap(), dir.join("a.txt").canonicalize().unwrap());
+        for name in ["hard.txt", "relative.txt", "absolute.txt"].iter() {
+            assert_eq!(fs::read_to_string(dir.join("view/2021").join(name)).unwrap(), "abc");
+        }
+
+        // Links replace existing files
+        symbolic_link(&dir.join("a.txt"), &dir.join("view/2021/hard.txt"), true).unwrap();
+        assert!(fs::symlink_metadata(dir.join("view/2021/hard.txt")).unwrap().file_type().is_symlink());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    /// Test [`relative_path`]
+    fn test_relative_path() {
+        assert_eq!(relative_path(Path::new("/a/b/c.txt"), Path::new("/a/d/e")), PathBuf::from("../../b/c.txt"));
+        assert_eq!(relative_path(Path::new("/a/b/c.txt"), Path::new("/a/b")), PathBuf::from("c.txt"));
+        assert_eq!(relative_path(Path::new("/a/b/c.txt"), Path::new("/")), PathBuf::from("a/b/c.txt"));
+    }
+
+    #[test]
+    /// Test [`move_path`]
+    fn test_move_path() {
+        let dir = test_dir("move_path");
+        fs::write(dir.join("a.txt"), "abc").unwrap();
+        let mut moved = 0;
+        move_path(&dir.join("a.txt"), &dir.join("b.txt"), &mut |bytes| moved += bytes).unwrap();
+        assert!(!dir.join("a.txt").exists());
+        assert_eq!(fs::read_to_string(dir.join("b.txt")).unwrap(), "abc");
+        assert_eq!(moved, 3);
+        assert!(move_path(&dir.join("a.txt"), &dir.join("c.txt"), &mut |_| {}).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    /// Test [`size_of`]
+    fn test_size_of() {
+        let dir = test_dir("size_of");
+        fs::create_dir(dir.join("d")).unwrap();
+        fs::write(dir.join("a.txt"), "abc").unwrap();
+        fs::write(dir.join("d/b.txt"), "de").unwrap();
+        assert_eq!(size_of(&dir.join("a.txt")), 3);
+        assert_eq!(size_of(&dir), 5);
+        assert_eq!(size_of(&dir.join("nonexistent")), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+/// The size of the buffer used when copying files.
+const BUFFER_SIZE: usize = 1 << 16;
+
+/// The number of bytes copied between calls to the progress callback.
+const PROGRESS_INTERVAL: u64 = 1 << 20;
+
+/// Move, copy or link `from` to `to` according to `operation`, replacing `to`
+/// if it is a file. See [`move_path`], [`copy_path`], [`hard_link`] and
+/// [`symbolic_link`]. `progress` is called with the number of bytes moved or
+/// copied each time some more are; links are reported all at once.
+pub fn perform(operation: Operation, from: &Path, to: &Path, progress: &mut dyn FnMut(u64)) -> io::Result<()> {
+    match operation {
+        Operation::Move => return move_path(from, to, progress),
+        Operation::Copy => return copy_path(from, to, progress),
+        Operation::HardLink => hard_link(from, to)?,
+        Operation::SymLink => symbolic_link(from, to, false)?,
+        Operation::RelativeSymLink => symbolic_link(from, to, true)?,
+    }
+    progress(size_of(from));
+    Ok(())
+}
+
+/// Move `from` to `to`, replacing `to` if it is a file. `from` can be a file,
+/// a directory or a symlink.
+///
+/// `from` is renamed if it can be. If `to` is on another filesystem, `from` is
+/// copied instead, and only removed once the copy is complete and verified; see
+/// [`move_by_copy`]. `progress` is called with the number of bytes moved each
+/// time some more are.
+pub fn move_path(from: &Path, to: &Path, progress: &mut dyn FnMut(u64)) -> io::Result<()> {
+    let size = size_of(from);
+    match fs::rename(from, to) {
+        Ok(_) => {
+            progress(size);
+            Ok(())
+        },
+        Err(error) if error.kind() == ErrorKind::CrossesDevices => move_by_copy(from, to, progress),
+        Err(error) => Err(error),
+    }
+}
+
+/// Move `from` to `to` by copying it with [`copy_path`] and then removing it.
+/// If the copy fails, `from` is left as it was.
+fn move_by_copy(from: &Path, to: &Path, progress: &mut dyn FnMut(u64)) -> io::Result<()> {
+    copy_path(from, to, progress)?;
+    remove_path(from)
+}
+
+/// Copy `from` to `to`, replacing `to` if it is a file. `from` can be a file, a
+/// directory (which is copied with all its contents) or a symlink (which is
+/// copied as a symlink).
+///
+/// The copy is made next to `to` under a temporary name, and renamed to `to`
+/// once it is complete, so an interrupted copy never leaves a partial `to`; if
+/// anything goes wrong the partial copy is removed. See [`copy_tree`] for what
+/// is preserved. `progress` is called with the number of bytes copied each time
+/// some more are.
+pub fn copy_path(from: &Path, to: &Path, progress: &mut dyn FnMut(u64)) -> io::Result<()> {
+    replace_with(to, |partial| copy_tree(from, partial, progress))
+}
+
+/// Copy `from` to `to`, which must not exist, recursively.
+///
+/// The permissions and modification and access times of files and directories
+/// are preserved. Each file is synced to disk and its size checked against the
+/// original.
+fn copy_tree(from: &Path, to: &Path, progress: &mut dyn FnMut(u64)) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    let file_type = metadata.file_type();
+
+    if file_type.is_symlink() {
+        return symlink(fs::read_link(from)?, to);
+    }
+    if file_type.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &to.join(entry.file_name()), progress)?;
+        }
+    } else if file_type.is_file() {
+        copy_file(from, to, metadata.len(), progress)?;
+    } else {
+        return Err(io::Error::new(ErrorKind::Unsupported, format!("can't copy special file {}", from.display())));
+    }
+
+    // Preserve the times and permissions, after the contents so that copying
+    // them doesn't change the times, and the permissions last in case they
+    // don't let us open the copy
+    let times = fs::FileTimes::new().set_accessed(metadata.accessed()?).set_modified(metadata.modified()?);
+    fs::File::open(to)?.set_times(times)?;
+    fs::set_permissions(to, metadata.permissions())
+}
+
+/// Copy the contents of the file `from`, which is `size` bytes long, to the
+/// new file `to`, and sync and check the copy.
+fn copy_file(from: &Path, to: &Path, size: u64, progress: &mut dyn FnMut(u64)) -> io::Result<()> {
+    let mut reader = fs::File::open(from)?;
+    let mut writer = fs::File::options().write(true).create_new(true).open(to)?;
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut copied: u64 = 0;
+    let mut unreported: u64 = 0;
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        writer.write_all(&buffer[..read])?;
+        copied += read as u64;
+        unreported += read as u64;
+        if unreported >= PROGRESS_INTERVAL {
+            progress(unreported);
+            unreported = 0;
+        }
+    }
+    writer.sync_all()?;
+    progress(unreported);
+
+    // Make sure the whole file made it
+    if copied != size || writer.metadata()?.len() != size {
+        return Err(io::Error::other(format!(
+            "copied {} bytes of {} to {}, expected {}",
+            copied,
+            from.display(),
+            to.display(),
+            size
+        )));
+    }
+    Ok(())
+}
+
+/// Make `to` a hard link to the file `from`, replacing `to` if it is a file.
+/// Both must be on the same filesystem.
+pub fn hard_link(from: &Path, to: &Path) -> io::Result<()> {
+    replace_with(to, |partial| fs::hard_link(from, partial))
+}
+
+/// Make `to` a symbolic link to `from`, replacing `to` if it is a file. If
+/// `relative` is [`true`], the link holds the path of `from` relative to the
+/// directory of `to`, so that the link keeps working if both are moved
+/// together; otherwise it holds the absolute path of `from`.
+pub fn symbolic_link(from: &Path, to: &Path, relative: bool) -> io::Result<()> {
+    let from = from.canonicalize()?;
+    let link = match relative {
+        true => {
+            let dir = to.parent().unwrap_or_else(|| Path::new(".")).canonicalize()?;
+            relative_path(&from, &dir)
+        },
+        false => from,
+    };
+    replace_with(to, |partial| symlink(&link, partial))
+}
+
+/// Create a path next to `to` under a temporary name with `create`, and then
+/// rename it to `to`, so that `to` is replaced in one step if it exists.
+fn replace_with(to: &Path, create: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    let partial = partial_path(to);
+    if let Err(error) = create(&partial).and_then(|_| fs::rename(&partial, to)) {
+        remove_path(&partial).ok();
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// Return the path of `path` relative to the directory `dir`. Both must be
+/// absolute, without any `.` or `..` in them.
+fn relative_path(path: &Path, dir: &Path) -> PathBuf {
+    let path: Vec<Component> = path.components().collect();
+    let dir: Vec<Component> = dir.components().collect();
+    let common = path.iter().zip(dir.iter()).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..dir.len() {
+        relative.push("..");
+    }
+    for component in path[common..].iter() {
+        relative.push(component);
+    }
+    relative
+}
+
+/// Return the temporary path used while creating `to`: a hidden file in the
+/// same directory.
+fn partial_path(to: &Path) -> PathBuf {
+    let name = to.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    to.with_file_name(format!(".{}.sortery-part", name))
+}
+
+/// Remove `path`, whether it is a file, a directory or a symlink.
+pub fn remove_path(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path)?.is_dir() {
+        true => fs::remove_dir_all(path),
+        false => fs::remove_file(path),
+    }
+}
+
+/// Return the total size in bytes of the files at `path`: the size of the file,
+/// or of all the files in the directory. Symlinks aren't followed.
+pub fn size_of(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
@@ -0,0 +1,456 @@
+//! Watching a directory, and sorting the files that arrive in it as they do,
+//! with the same options as a one-off sort. A [`Watcher`] is told about
+//! changes by inotify; files are only sorted once nothing has happened to
+//! them for the debounce, so that files that are still being written, as by
+//! a download, are left alone until they are done. Directories that are
+//! created, moved in or renamed while watching are followed, and if the
+//! kernel drops changes because too many happened at once, the whole source
+//! is looked over again.
+
+use crate::{
+    messages::error_messages,
+    observer::SortObserver,
+    sorter::{SortError, SortPlan, Sorter},
+    structs::File,
+};
+use inotify::{EventMask, EventOwned, Inotify, WatchDescriptor, WatchMask, Watches};
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+    mem,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use walkdir::WalkDir;
+
+#[cfg(test)]
+mod tests {
+    /// Tests for the watcher. Each test is named after the struct it tests,
+    /// prefixed with test.
+
+    use crate::{observer::SilentObserver, structs::File, Sorter};
+    use inotify::{EventMask, EventOwned};
+    use std::{env, ffi::OsString, fs, path::{Path, PathBuf}, sync::mpsc, time::Duration};
+    use super::Watcher;
+
+    /// Poll `watcher` until it plans a sort, and return the files it sorts
+    /// after executing the plan, relative to `dir`.
+    fn sort_next(watcher: &mut Watcher, dir: &Path) -> Vec<PathBuf> {
+        for _ in 0..50 {
+            if let Some(plan) = watcher.poll(&mut SilentObserver).expect("Failed to watch.") {
+                plan.execute_with(&mut SilentObserver).expect("Failed to sort.");
+                let mut sorted: Vec<PathBuf> = plan.moves.iter()
+                    .map(|planned| planned.from.strip_prefix(dir).unwrap().to_path_buf())
+                    .collect();
+                sorted.sort();
+                return sorted;
+            }
+        }
+        panic!("Nothing was sorted.");
+    }
+
+    #[test]
+    /// Test the [`Watcher`] struct
+    fn test_watcher() {
+        let dir = env::temp_dir().join("sortery_test_watcher");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+        }
+        fs::create_dir_all(dir.join("source/old")).expect("Failed to create dir.");
+        fs::create_dir_all(dir.join("target")).expect("Failed to create dir.");
+        fs::write(dir.join("source/old/a.jpg"), "a").expect("Failed to write file.");
+        let sorter = || Sorter::new(File::from_pathbuf(&dir.join("source")), File::from_pathbuf(&dir.join("target")))
+            .dir_format("")
+            .name_template("{stem}.{ext}");
+
+        // The files already in the source are left alone, unless they are to
+        // be sorted too, in which case they are sorted first
+        let mut watcher = Watcher::new(sorter(), Duration::from_millis(100), false).expect("Failed to watch.");
+        fs::write(dir.join("source/b.jpg"), "b").expect("Failed to write file.");
+        assert_eq!(sort_next(&mut watcher, &dir), vec![PathBuf::from("source/b.jpg")]);
+        drop(watcher);
+        let mut watcher = Watcher::new(sorter(), Duration::from_millis(100), true).expect("Failed to watch.");
+        assert_eq!(sort_next(&mut watcher, &dir), vec![PathBuf::from("source/old/a.jpg")]);
+
+        // New files, and the files in new directories, once they settle
+        fs::write(dir.join("source/b2.jpg"), "b").expect("Failed to write file.");
+        fs::create_dir_all(dir.join("source/new/deeper")).expect("Failed to create dir.");
+        fs::write(dir.join("source/new/deeper/c.jpg"), "c").expect("Failed to write file.");
+        assert_eq!(sort_next(&mut watcher, &dir), vec![PathBuf::from("source/b2.jpg"), PathBuf::from("source/new/deeper/c.jpg")]);
+
+        // Renamed directories are still watched under their new name
+        fs::rename(dir.join("source/new"), dir.join("source/renamed")).expect("Failed to rename dir.");
+        fs::write(dir.join("source/renamed/deeper/d.jpg"), "d").expect("Failed to write file.");
+        assert_eq!(sort_next(&mut watcher, &dir), vec![PathBuf::from("source/renamed/deeper/d.jpg")]);
+
+        // Directories moved in from elsewhere are sorted too
+        fs::create_dir_all(dir.join("elsewhere")).expect("Failed to create dir.");
+        fs::write(dir.join("elsewhere/e.jpg"), "e").expect("Failed to write file.");
+        fs::rename(dir.join("elsewhere"), dir.join("source/moved")).expect("Failed to rename dir.");
+        assert_eq!(sort_next(&mut watcher, &dir), vec![PathBuf::from("source/moved/e.jpg")]);
+
+        // The two halves of a rename are matched up even if they are read
+        // in different polls, and directories only moved away are forgotten
+        let (sender, events) = mpsc::channel();
+        watcher.events = events;
+        let source_wd = watcher.dirs.iter().find(|(_, path)| **path == dir.join("source")).unwrap().0.clone();
+        let moved = |mask: EventMask, cookie: u32, name: &str| Ok(vec![EventOwned {
+            wd: source_wd.clone(),
+            mask: mask | EventMask::ISDIR,
+            cookie,
+            name: Some(OsString::from(name)),
+        }]);
+        sender.send(moved(EventMask::MOVED_FROM, 1, "renamed")).unwrap();
+        assert!(watcher.poll(&mut SilentObserver).unwrap().is_none());
+        sender.send(moved(EventMask::MOVED_TO, 1, "split")).unwrap();
+        assert!(watcher.poll(&mut SilentObserver).unwrap().is_none());
+        assert!(watcher.dirs.values().any(|path| *path == dir.join("source/split/deeper")));
+        sender.send(moved(EventMask::MOVED_FROM, 2, "split")).unwrap();
+        assert!(watcher.poll(&mut SilentObserver).unwrap().is_none());
+        assert!(watcher.dirs.values().any(|path| path.starts_with(dir.join("source/split"))));
+        assert!(watcher.poll(&mut SilentObserver).unwrap().is_none());
+        assert!(!watcher.dirs.values().any(|path| path.starts_with(dir.join("source/split"))));
+        drop(sender);
+
+        let mut sorted: Vec<PathBuf> = fs::read_dir(dir.join("target")).expect("Failed to read dir.")
+            .map(|entry| PathBuf::from(entry.expect("Failed to read entry.").file_name()))
+            .filter(|name| name.extension().is_some_and(|extension| extension == "jpg"))
+            .collect();
+        sorted.sort();
+        assert_eq!(sorted, ["a.jpg", "b.jpg", "b2.jpg", "c.jpg", "d.jpg", "e.jpg"].iter().map(PathBuf::from).collect::<Vec<PathBuf>>());
+
+        fs::remove_dir_all(&dir).expect("Failed to remove dir.");
+    }
+}
+
+/// The changes that directories are watched for.
+const WATCH_MASK: WatchMask = WatchMask::CREATE
+    .union(WatchMask::MODIFY)
+    .union(WatchMask::CLOSE_WRITE)
+    .union(WatchMask::ATTRIB)
+    .union(WatchMask::DELETE)
+    .union(WatchMask::MOVED_FROM)
+    .union(WatchMask::MOVED_TO)
+    .union(WatchMask::ONLYDIR);
+
+/// Read the changes `inotify` tells about until it fails, or until `sender`
+/// is dropped, sending them to `sender` as they come.
+fn read_events(mut inotify: Inotify, sender: mpsc::Sender<io::Result<Vec<EventOwned>>>) {
+    let mut buffer = [0u8; 4096];
+    loop {
+        let events = match inotify.read_events_blocking(&mut buffer) {
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            events => events.map(|events| events.map(|event| event.to_owned()).collect()),
+        };
+        let failed = events.is_err();
+        if sender.send(events).is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// Something that stops a [`Watcher`].
+#[derive(Debug)]
+pub enum WatchError {
+    /// The options of the sorter aren't valid, or stopped being valid, as when
+    /// the target is removed.
+    Invalid(Vec<SortError>),
+    /// The source couldn't be watched because of `error`.
+    Failed { path: File, error: io::Error },
+}
+impl WatchError {
+
+    /// Return the full, colorized error message as a string, with a line for
+    /// each thing that is wrong.
+    pub fn to_string(&self) -> String {
+        match self {
+            WatchError::Invalid(errors) => errors.iter().map(SortError::to_string).collect::<Vec<String>>().join("\n"),
+            WatchError::Failed { path, error } => error_messages::WatchFailedError { path, error: &error.to_string() }.to_string(),
+        }
+    }
+}
+
+/// Watches the source of a [`Sorter`], and plans the sort of the files that
+/// arrive in it once they settle; see [`crate::watch`]. The files already in
+/// the source are left alone, unless they are to be sorted too, in which
+/// case they are sorted first, once they have settled.
+///
+/// ```no_run
+/// use sortery::{observer::SilentObserver, structs::File, watch::Watcher, Sorter};
+/// use std::time::Duration;
+///
+/// let sorter = Sorter::new(File::new("Downloads"), File::new("Sorted"));
+/// let mut watcher = Watcher::new(sorter, Duration::from_secs(2), false).expect("Failed to watch");
+/// loop {
+///     if let Some(plan) = watcher.poll(&mut SilentObserver).expect("Failed to watch") {
+///         plan.execute().expect("Failed to write the undo journal");
+///     }
+/// }
+/// ```
+pub struct Watcher {
+    sorter: Sorter,
+    debounce: Duration,
+    canonical_target: Option<PathBuf>,
+
+    // When watching started, unless the files already in the source are
+    // sorted too
+    started: Option<SystemTime>,
+
+    // The inotify watches, and the changes they tell about, which are read on
+    // a thread of their own
+    watches: Watches,
+    events: Receiver<io::Result<Vec<EventOwned>>>,
+
+    // The watched directories by their watch descriptors, the files waiting
+    // to settle with when they last changed, and the directories moved away
+    // by their rename cookies, with when they were, until they are found to
+    // be renamed
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    pending: HashMap<PathBuf, SystemTime>,
+    moved_from: HashMap<u32, (PathBuf, SystemTime)>,
+}
+impl Watcher {
+
+    /// How long files have to settle for by default before they are sorted.
+    pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(2);
+
+    /// Return a [`Watcher`] watching the source of `sorter`, which sorts the
+    /// files once nothing has happened to them for `debounce`. If
+    /// `sort_existing` is [`true`], the files already in the source are
+    /// sorted as well as the ones that arrive.
+    pub fn new(sorter: Sorter, debounce: Duration, sort_existing: bool) -> Result<Watcher, WatchError> {
+        let errors = sorter.validate();
+        if !errors.is_empty() {
+            return Err(WatchError::Invalid(errors));
+        }
+        let inotify = Inotify::init().map_err(|error| WatchError::Failed { path: sorter.source.copy(), error })?;
+        let watches = inotify.watches();
+        let (sender, events) = mpsc::channel();
+        thread::spawn(move || read_events(inotify, sender));
+        let mut watcher = Watcher {
+            canonical_target: fs::canonicalize(&sorter.target.pathbuf).ok(),
+            sorter,
+            debounce,
+            started: match sort_existing {
+                true => None,
+                false => Some(SystemTime::now()),
+            },
+            watches,
+            events,
+            dirs: HashMap::new(),
+            pending: HashMap::new(),
+            moved_from: HashMap::new(),
+        };
+        watcher.rescan()?;
+        Ok(watcher)
+    }
+
+    /// Look over the whole source again, watching all its directories and
+    /// waiting for its files to settle, apart from the ones that were
+    /// already there when watching started, if they are left alone.
+    fn rescan(&mut self) -> Result<(), WatchError> {
+        self.dirs.clear();
+        self.moved_from.clear();
+        let source = self.sorter.source.to_path_buf();
+        self.add_tree(&source, None).map_err(|error| WatchError::Failed { path: self.sorter.source.copy(), error })
+    }
+
+    /// Watch the directory `root` and the directories in it, apart from the
+    /// target, and wait for the files in them to settle, as if they changed
+    /// at `changed`, or when they were last modified if it is [`None`], in
+    /// which case the files that were already there when watching started
+    /// are left out if they are left alone.
+    fn add_tree(&mut self, root: &Path, changed: Option<SystemTime>) -> io::Result<()> {
+        let now = SystemTime::now();
+        let canonical_target = &self.canonical_target;
+        let walk = WalkDir::new(root)
+            .follow_links(self.sorter.follow_symlinks)
+            .into_iter()
+            .filter_entry(|entry| {
+                !entry.file_type().is_dir() || entry.depth() == 0
+                    || canonical_target.as_ref().is_none_or(|target| fs::canonicalize(entry.path()).ok().as_ref() != Some(target))
+            });
+        for entry in walk {
+
+            // Paths that vanish while they are looked over are left out,
+            // apart from the root
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(error) if error.depth() > 0 => continue,
+                Err(error) => return Err(error.into()),
+            };
+            if entry.file_type().is_dir() {
+                match self.watches.add(entry.path(), WATCH_MASK) {
+                    Ok(wd) => { self.dirs.insert(wd, entry.path().to_path_buf()); },
+                    Err(error) if entry.depth() == 0 => return Err(error),
+                    Err(_) => {},
+                }
+            } else {
+                let metadata = entry.metadata().ok();
+                if let (None, Some(started), Some(metadata)) = (changed, self.started, &metadata) {
+                    let arrived = UNIX_EPOCH + Duration::new(metadata.ctime().max(0) as u64, metadata.ctime_nsec().max(0) as u32);
+                    if arrived < started {
+                        continue;
+                    }
+                }
+                let modified = metadata.and_then(|metadata| metadata.modified().ok()).unwrap_or(now);
+                self.pending.entry(entry.path().to_path_buf()).or_insert(changed.unwrap_or_else(|| modified.min(now)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop watching the directory `root` and the directories in it, and
+    /// forget about the files in them.
+    fn remove_tree(&mut self, root: &Path) {
+        let watches = &mut self.watches;
+        self.dirs.retain(|wd, dir| {
+            let inside = dir.starts_with(root);
+            if inside {
+                let _ = watches.remove(wd.clone());
+            }
+            !inside
+        });
+        self.pending.retain(|path, _| !path.starts_with(root));
+    }
+
+    /// Follow the directory `from` being renamed to `to`, along with the
+    /// directories and files in it.
+    fn rename_tree(&mut self, from: &Path, to: &Path) {
+        let rename = |path: &Path| match path.strip_prefix(from) {
+            Ok(relative) => to.join(relative),
+            Err(_) => path.to_path_buf(),
+        };
+        for dir in self.dirs.values_mut() {
+            *dir = rename(dir);
+        }
+        self.pending = self.pending.drain().map(|(path, changed)| (rename(&path), changed)).collect();
+    }
+
+    /// Keep track of what `event` tells happened at `now`.
+    fn handle(&mut self, event: EventOwned, now: SystemTime) -> Result<(), WatchError> {
+        if event.mask.contains(EventMask::Q_OVERFLOW) {
+            return self.rescan();
+        }
+        if event.mask.contains(EventMask::IGNORED) {
+            self.dirs.remove(&event.wd);
+            return Ok(());
+        }
+        let path = match (self.dirs.get(&event.wd), &event.name) {
+            (Some(dir), Some(name)) if !name.is_empty() => dir.join(name),
+            _ => return Ok(()),
+        };
+
+        if event.mask.contains(EventMask::ISDIR) {
+            if event.mask.contains(EventMask::MOVED_FROM) {
+                self.moved_from.insert(event.cookie, (path, now));
+            } else if event.mask.contains(EventMask::MOVED_TO) {
+                match self.moved_from.remove(&event.cookie) {
+                    Some((from, _)) => self.rename_tree(&from, &path),
+                    None => { let _ = self.add_tree(&path, Some(now)); },
+                }
+            } else if event.mask.contains(EventMask::CREATE) {
+                let _ = self.add_tree(&path, Some(now));
+            }
+        } else if event.mask.intersects(EventMask::MOVED_FROM | EventMask::DELETE) {
+            self.pending.remove(&path);
+        } else {
+            self.pending.insert(path, now);
+        }
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for changes, or until there are some if it is
+    /// [`None`], and return all the changes there are.
+    fn read_events(&self, timeout: Option<Duration>) -> io::Result<Vec<EventOwned>> {
+        let first = match timeout {
+            Some(timeout) => self.events.recv_timeout(timeout),
+            None => self.events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        let mut events = match first {
+            Ok(events) => events?,
+            Err(RecvTimeoutError::Timeout) => return Ok(Vec::new()),
+            Err(RecvTimeoutError::Disconnected) => return Err(io::Error::new(io::ErrorKind::BrokenPipe, "stopped reading changes")),
+        };
+        for more in self.events.try_iter() {
+            events.extend(more?);
+        }
+        Ok(events)
+    }
+
+    /// Wait for changes in the source, until the next file settles if some
+    /// are waiting to, or until there are changes otherwise, and return the
+    /// plan for sorting the files that have settled, or [`None`] if there
+    /// aren't any. The plan isn't executed, and the files in it aren't looked
+    /// at again unless they change. `observer` is told about the planning.
+    pub fn poll(&mut self, observer: &mut dyn SortObserver) -> Result<Option<SortPlan>, WatchError> {
+        let debounce = self.debounce;
+        let now = SystemTime::now();
+        let timeout = self.pending.values()
+            .chain(self.moved_from.values().map(|(_, moved)| moved))
+            .map(|changed| debounce.saturating_sub(now.duration_since(*changed).unwrap_or_default()))
+            .min();
+        let events = self.read_events(timeout)
+            .map_err(|error| WatchError::Failed { path: self.sorter.source.copy(), error })?;
+
+        let now = SystemTime::now();
+        for event in events {
+            self.handle(event, now)?;
+        }
+
+        // Directories moved away are gone if they haven't been moved back
+        // into the source by a later poll, and the debounce after, since the
+        // two halves of a rename can be read apart; and everything is gone
+        // if the source or the target is
+        let moved_away: Vec<u32> = self.moved_from.iter()
+            .filter(|(_, (_, moved))| *moved < now && now.duration_since(*moved).unwrap_or_default() >= debounce)
+            .map(|(cookie, _)| *cookie)
+            .collect();
+        for cookie in moved_away.iter() {
+            if let Some((dir, _)) = self.moved_from.remove(cookie) {
+                self.remove_tree(&dir);
+            }
+        }
+        let errors = self.sorter.validate();
+        if !errors.is_empty() {
+            return Err(WatchError::Invalid(errors));
+        }
+
+        let settled: Vec<PathBuf> = self.pending.iter()
+            .filter(|(_, changed)| now.duration_since(**changed).unwrap_or_default() >= debounce)
+            .map(|(path, _)| path.clone())
+            .collect();
+        if settled.is_empty() {
+            return Ok(None);
+        }
+        for path in settled.iter() {
+            self.pending.remove(path);
+        }
+
+        // Plan the sort of just the settled files
+        self.sorter.paths = Some(settled.into_iter().collect());
+        let plan = self.sorter.plan_with(observer);
+        self.sorter.paths = None;
+        match plan {
+            Ok(plan) if plan.moves.is_empty() => Ok(None),
+            Ok(plan) => Ok(Some(plan)),
+            Err(errors) => Err(WatchError::Invalid(errors)),
+        }
+    }
+}
+impl Drop for Watcher {
+
+    /// Stop reading changes: the channel is closed first, and then the
+    /// watches are removed, which wakes the thread reading the changes up to
+    /// find it closed.
+    fn drop(&mut self) {
+        drop(mem::replace(&mut self.events, mpsc::channel().1));
+        for wd in self.dirs.keys() {
+            let _ = self.watches.remove(wd.clone());
+        }
+    }
+}